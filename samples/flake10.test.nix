@@ -0,0 +1,15 @@
+{
+  description = "A flake whose input names are quoted";
+
+  inputs = {
+    "nixpkgs-23.05".url = "github:NixOS/nixpkgs/nixos-23.05";
+    "my-crane" = {
+      url = "github:ipetkov/crane";
+      inputs.nixpkgs.follows = "nixpkgs-23.05";
+    };
+  };
+
+  inputs."flake.utils".url = "github:numtide/flake-utils";
+
+  outputs = { self, ... } @ inputs: { };
+}
@@ -0,0 +1,15 @@
+let
+  system = "x86_64-linux";
+in
+{
+  description = "A flake wrapped in a let";
+
+  inputs = {
+    nixpkgs.url = "github:NixOS/nixpkgs/nixos-23.05";
+    flake-utils.url = "github:numtide/flake-utils";
+  };
+
+  outputs = { self, nixpkgs, ... }: {
+    packages.${system}.default = nixpkgs.legacyPackages.${system}.hello;
+  };
+}
@@ -1,5 +1,7 @@
 use std::collections::VecDeque;
 
+use color_eyre::Section;
+use owo_colors::OwoColorize;
 use tracing::{span, Level};
 
 const NEWLINE: &str = "\n";
@@ -12,6 +14,7 @@ pub(crate) fn upsert_flake_input(
     flake_contents: String,
     input_attr_path: VecDeque<String>,
     inputs_insertion_location: InputsInsertionLocation,
+    comment: Option<&str>,
 ) -> color_eyre::Result<String> {
     match find_first_attrset_by_path(expr, Some(input_attr_path))? {
         Some(attr) => update_flake_input(attr, flake_input_name, flake_input_value, flake_contents),
@@ -21,6 +24,7 @@ pub(crate) fn upsert_flake_input(
             flake_input_value,
             flake_contents,
             inputs_insertion_location,
+            comment,
         ),
     }
 }
@@ -31,28 +35,265 @@ pub(crate) fn update_flake_input(
     flake_input_value: url::Url,
     flake_contents: String,
 ) -> color_eyre::Result<String> {
-    match *attr.to {
-        nixel::Expression::String(existing_input_value) => replace_input_value_string(
-            &existing_input_value.parts,
-            &flake_input_value,
-            &flake_contents,
-        ),
-        nixel::Expression::IndentedString(existing_input_value) => replace_input_value_string(
-            &existing_input_value.parts,
-            &flake_input_value,
-            &flake_contents,
-        ),
-        nixel::Expression::Uri(existing_input_value) => {
-            replace_input_value_uri(&existing_input_value, &flake_input_value, &flake_contents)
+    match url_edit(&attr.to, &flake_input_name, &flake_input_value)? {
+        Some(edit) => apply_edits(&flake_contents, vec![edit]),
+        None => Ok(flake_contents),
+    }
+}
+
+/// A replacement of the text at `span` of a flake.nix, e.g. of an input's URL.
+#[derive(Debug, Clone)]
+pub(crate) struct Edit {
+    pub(crate) span: nixel::Span,
+    pub(crate) replacement: String,
+}
+
+/// The edit that points the `url` value `value` of the input `flake_input_name` at
+/// `flake_input_value`, or `None` if it already points there.
+pub(crate) fn url_edit(
+    value: &nixel::Expression,
+    flake_input_name: &str,
+    flake_input_value: &url::Url,
+) -> color_eyre::Result<Option<Edit>> {
+    match value {
+        nixel::Expression::String(string) => {
+            let Some(raw_parts) = raw_parts(&string.parts)? else {
+                return Ok(None);
+            };
+
+            let current_value: String = raw_parts.iter().map(|raw| &*raw.content).collect();
+            if current_value == flake_input_value.as_str() {
+                return Ok(None);
+            }
+
+            // The string may be split into several parts (e.g. around escape sequences), so
+            // replace all of them at once
+            let (first, last) = (raw_parts[0], raw_parts[raw_parts.len() - 1]);
+            Ok(Some(Edit {
+                span: nixel::Span {
+                    start: first.span.start.clone(),
+                    end: last.span.end.clone(),
+                },
+                replacement: flake_input_value.to_string(),
+            }))
+        }
+        // Replace the whole `''...''` string rather than just its contents, since those may span
+        // several lines (and be indented) in a way that doesn't fit a URL.
+        nixel::Expression::IndentedString(indented_string) => {
+            if let Some(raw_parts) = raw_parts(&indented_string.parts)? {
+                let current_value: String = raw_parts.iter().map(|raw| &*raw.content).collect();
+                if current_value.trim() == flake_input_value.as_str() {
+                    return Ok(None);
+                }
+            }
+
+            Ok(Some(Edit {
+                span: (*indented_string.span).clone(),
+                replacement: format!("''{flake_input_value}''"),
+            }))
+        }
+        nixel::Expression::Uri(uri) => {
+            if uri.uri.as_ref() == flake_input_value.as_str() {
+                return Ok(None);
+            }
+
+            Ok(Some(Edit {
+                span: (*uri.span).clone(),
+                replacement: format!(r#""{flake_input_value}""#),
+            }))
         }
         otherwise => {
             // a boolean, a number, or even another attrset, etc.
-            Err(color_eyre::eyre::eyre!(
-                "`inputs.{flake_input_name}.url` was not a String, Indented String, or URI. Instead: {:?}", // this is enforced by Nix itself
-                otherwise
-            ))
+            Err(UnsupportedExpression::new(
+                format!(
+                    "`inputs.{flake_input_name}.url` was not a String, Indented String, or URI, but {}", // this is enforced by Nix itself
+                    otherwise.variant_name()
+                ),
+                otherwise.start(),
+            )
+            .into())
+        }
+    }
+}
+
+/// Applies all of `edits` in a single pass from the end of the flake to its start, so that no edit
+/// shifts the text that the ones still to be applied refer to. The edits may come in any order,
+/// but mustn't overlap.
+#[tracing::instrument(skip_all)]
+pub(crate) fn apply_edits(flake_contents: &str, edits: Vec<Edit>) -> color_eyre::Result<String> {
+    let index = LineIndex::new(flake_contents);
+    let mut edits = edits
+        .into_iter()
+        .map(|edit| {
+            let start = index.offset(&edit.span.start)?;
+            let end = index.offset(&edit.span.end)?;
+            Ok((start..end, edit))
+        })
+        .collect::<color_eyre::Result<Vec<_>>>()?;
+    edits.sort_by_key(|(range, _)| (range.start, range.end));
+
+    for pair in edits.windows(2) {
+        let ((first, _), (second, edit)) = (&pair[0], &pair[1]);
+        if second.start < first.end {
+            return Err(color_eyre::eyre::eyre!(
+                "overlapping edits of the flake (at {}:{})",
+                edit.span.start.line,
+                edit.span.start.column
+            ));
         }
     }
+
+    let mut new_flake_contents = flake_contents.to_string();
+    for (range, edit) in edits.into_iter().rev() {
+        new_flake_contents.replace_range(range, &edit.replacement);
+    }
+
+    Ok(new_flake_contents)
+}
+
+/// Sets `inputs.<flake_input_name>.inputs.<child>.follows = "<parent>"`, either by updating an
+/// existing `follows` or by inserting one right after the input's `url`, formatted the same way.
+#[tracing::instrument(skip_all)]
+pub(crate) fn upsert_flake_input_follows(
+    expr: &nixel::Expression,
+    flake_input_name: &str,
+    child: &str,
+    parent: &str,
+    flake_contents: String,
+) -> color_eyre::Result<String> {
+    let follows_attr_path: VecDeque<String> = [
+        String::from("inputs"),
+        flake_input_name.to_string(),
+        String::from("inputs"),
+        child.to_string(),
+        String::from("follows"),
+    ]
+    .into();
+
+    if let Some(follows_attr) = find_first_attrset_by_path(expr, Some(follows_attr_path))? {
+        return match *follows_attr.to {
+            nixel::Expression::String(existing) => match &existing.parts[..] {
+                [nixel::Part::Raw(raw)] => {
+                    let mut new_flake_contents = flake_contents.clone();
+                    let index = LineIndex::new(&flake_contents);
+                    let start = index.offset(&raw.span.start)?;
+                    let end = index.offset(&raw.span.end)?;
+                    new_flake_contents.replace_range(start..end, parent);
+                    Ok(new_flake_contents)
+                }
+                _ => Err(color_eyre::eyre::eyre!(
+                    "`inputs.{flake_input_name}.inputs.{child}.follows` was not a plain string"
+                )),
+            },
+            otherwise => Err(color_eyre::eyre::eyre!(
+                "`inputs.{flake_input_name}.inputs.{child}.follows` was not a String. Instead: {:?}",
+                otherwise
+            )),
+        };
+    }
+
+    insert_next_to_input_url(
+        expr,
+        flake_input_name,
+        &["inputs", child, "follows"],
+        &format!(r#""{parent}""#),
+        flake_contents,
+    )
+}
+
+/// Sets `inputs.<flake_input_name>.<attr_path> = <value>`, where `value` is Nix code (e.g. `false`
+/// or `"tarball"`), either by replacing the value of an existing binding or by inserting one right
+/// after the input's `url`, formatted the same way.
+#[tracing::instrument(skip_all)]
+pub(crate) fn upsert_flake_input_attr(
+    expr: &nixel::Expression,
+    flake_input_name: &str,
+    attr_path: &[&str],
+    value: &str,
+    flake_contents: String,
+) -> color_eyre::Result<String> {
+    let full_attr_path: VecDeque<String> = ["inputs", flake_input_name]
+        .iter()
+        .chain(attr_path)
+        .map(ToString::to_string)
+        .collect();
+
+    if let Some(attr) = find_first_attrset_by_path(expr, Some(full_attr_path))? {
+        return apply_edits(
+            &flake_contents,
+            vec![Edit {
+                span: attr.to.span(),
+                replacement: value.to_string(),
+            }],
+        );
+    }
+
+    insert_next_to_input_url(expr, flake_input_name, attr_path, value, flake_contents)
+}
+
+/// Inserts `inputs.<flake_input_name>.<attr_path> = <value>;` right after the input's `url`: on a
+/// line of its own with the same indentation if the `url` has a line of its own, or else on the
+/// same line.
+fn insert_next_to_input_url(
+    expr: &nixel::Expression,
+    flake_input_name: &str,
+    attr_path: &[&str],
+    value: &str,
+    flake_contents: String,
+) -> color_eyre::Result<String> {
+    let url_attr_path: VecDeque<String> = [
+        String::from("inputs"),
+        flake_input_name.to_string(),
+        String::from("url"),
+    ]
+    .into();
+    let Some(url_attr) = find_first_attrset_by_path(expr, Some(url_attr_path))? else {
+        return Err(color_eyre::eyre::eyre!(
+            "there was no `inputs.{flake_input_name}.url` to add `{}` next to",
+            attr_path.join(".")
+        ));
+    };
+
+    // Mirror the way the `url` was written, e.g. `inputs.foo.url` -> `inputs.foo.inputs.bar.follows`
+    // or `url` -> `inputs.bar.follows`
+    let mut new_attr_path = url_attr
+        .from
+        .iter()
+        .map(|part| match (part, attr_name(part)) {
+            (nixel::Part::Raw(_), Some(name)) => Ok(name.to_string()),
+            // Keep quoting names that were quoted, e.g. `inputs."foo.bar".url`
+            (_, Some(name)) => Ok(format!("\"{name}\"")),
+            (part, None) => Err(UnsupportedExpression::new(
+                "unexpected expression or interpolation",
+                part.start(),
+            )),
+        })
+        .collect::<Result<Vec<String>, _>>()?;
+    new_attr_path.pop(); // `url`
+    new_attr_path.extend(attr_path.iter().map(ToString::to_string));
+    let binding = format!("{} = {value};", new_attr_path.join("."));
+
+    let (from_span, to_span) = kv_to_span(&url_attr);
+    let index = LineIndex::new(&flake_contents);
+    let indentation = indentation_from_from_span(&index, &from_span)?;
+    let end = index.offset(&to_span.end)?;
+    let Some(semicolon) = flake_contents[end..].find(';') else {
+        return Err(color_eyre::eyre::eyre!(
+            "could not find the end of `inputs.{flake_input_name}.url`"
+        ));
+    };
+    let offset = end + semicolon + 1;
+
+    let mut new_flake_contents = flake_contents.clone();
+    if indentation.trim().is_empty() {
+        // The `url` is on its own line, so put the new binding on its own line right below it.
+        new_flake_contents.insert_str(offset, &format!("{NEWLINE}{indentation}{binding}"));
+    } else {
+        // e.g. `inputs.foo = { url = "..."; };`
+        new_flake_contents.insert_str(offset, &format!(" {binding}"));
+    }
+
+    Ok(new_flake_contents)
 }
 
 pub(crate) fn insert_flake_input(
@@ -61,16 +302,43 @@ pub(crate) fn insert_flake_input(
     flake_input_value: url::Url,
     flake_contents: String,
     inputs_insertion_location: InputsInsertionLocation,
+    comment: Option<&str>,
 ) -> color_eyre::Result<String> {
     let inputs_attr_path: VecDeque<String> = [String::from("inputs")].into();
     let outputs_attr_path: VecDeque<String> = [String::from("outputs")].into();
 
-    let inputs_attr = match inputs_insertion_location {
-        InputsInsertionLocation::Top => find_first_attrset_by_path(expr, Some(inputs_attr_path))?,
+    let (inputs_attr, inputs_insertion_location) = match inputs_insertion_location {
+        InputsInsertionLocation::Top => (
+            find_first_attrset_by_path(expr, Some(inputs_attr_path))?,
+            InputsInsertionLocation::Top,
+        ),
         InputsInsertionLocation::Bottom => {
             let all_toplevel_inputs = find_all_attrsets_by_path(expr, Some(inputs_attr_path))?;
             let all_inputs = collect_all_inputs(all_toplevel_inputs)?;
-            all_inputs.into_iter().last()
+            (
+                all_inputs.into_iter().last().map(|input| input.binding),
+                InputsInsertionLocation::Bottom,
+            )
+        }
+        InputsInsertionLocation::Alphabetical => {
+            let all_toplevel_inputs = find_all_attrsets_by_path(expr, Some(inputs_attr_path))?;
+            let mut all_inputs: Vec<nixel::BindingKeyValue> =
+                collect_all_inputs(all_toplevel_inputs)?
+                    .into_iter()
+                    .map(|input| input.binding)
+                    .collect();
+
+            // Insert above the first input that sorts after the new one, or below all of them if
+            // there's none.
+            match all_inputs.iter().position(|input| {
+                input_name(input).is_some_and(|name| name > flake_input_name.as_str())
+            }) {
+                Some(idx) => (
+                    Some(all_inputs.swap_remove(idx)),
+                    InputsInsertionLocation::Top,
+                ),
+                None => (all_inputs.pop(), InputsInsertionLocation::Bottom),
+            }
         }
     };
 
@@ -80,17 +348,168 @@ pub(crate) fn insert_flake_input(
         flake_input_name,
         flake_input_value,
         flake_contents,
-        expr.span(),
+        unwrap_expression(expr).span(),
         inputs_attr,
         outputs_attr,
         inputs_insertion_location,
+        comment,
     )
 }
 
+/// Sorts the top-level `inputs.*` bindings, as well as the bindings inside of `inputs = { ... }`,
+/// alphabetically by input name. Each binding keeps its exact text, including the comments directly
+/// above it, and bindings of the same input keep their relative order.
+///
+/// Bindings that don't sit on lines of their own can't be moved without mangling the flake, so a
+/// group of inputs containing one is left unsorted.
+#[tracing::instrument(skip_all)]
+pub(crate) fn sort_inputs(
+    expr: &nixel::Expression,
+    flake_contents: &str,
+) -> color_eyre::Result<String> {
+    let nixel::Expression::Map(map) = unwrap_expression(expr) else {
+        return Ok(flake_contents.to_string());
+    };
+
+    let key_values = |bindings: &[nixel::Binding]| -> Vec<nixel::BindingKeyValue> {
+        bindings
+            .iter()
+            .filter_map(|binding| match binding {
+                nixel::Binding::KeyValue(kv) => Some(kv.clone()),
+                nixel::Binding::Inherit(_) => None,
+            })
+            .collect()
+    };
+
+    let mut groups: Vec<Vec<(String, nixel::BindingKeyValue)>> = Vec::new();
+    let mut toplevel_group = Vec::new();
+    for kv in key_values(&map.bindings) {
+        match attr_names(&kv)[..] {
+            // inputs = { ... };
+            [Some("inputs")] => {
+                if let nixel::Expression::Map(inputs) = &*kv.to {
+                    let group = key_values(&inputs.bindings)
+                        .into_iter()
+                        .map(|kv| (input_name(&kv).map(ToString::to_string), kv))
+                        .map(|(name, kv)| name.map(|name| (name, kv)))
+                        .collect::<Option<Vec<_>>>();
+                    groups.extend(group);
+                }
+            }
+            // inputs.nixpkgs.url = "";
+            [Some("inputs"), Some(name), ..] => toplevel_group.push((name.to_string(), kv)),
+            _ => {}
+        }
+    }
+    groups.push(toplevel_group);
+
+    let index = LineIndex::new(flake_contents);
+    let lines: Vec<&str> = flake_contents.split_inclusive('\n').collect();
+    // (first line, last line, replacement text); lines are 0-based and inclusive
+    let mut replacements: Vec<(usize, usize, String)> = Vec::new();
+
+    'groups: for group in groups {
+        let mut slots = Vec::new();
+        for (name, kv) in group {
+            let (from_span, to_span) = kv_to_span(&kv);
+
+            let indentation = indentation_from_from_span(&index, &from_span)?;
+            let end = index.offset(&to_span.end)?;
+            let rest_of_line = flake_contents[end..].lines().next().unwrap_or_default();
+            let owns_its_lines = indentation.trim().is_empty()
+                && rest_of_line
+                    .trim_start()
+                    .strip_prefix(';')
+                    .is_some_and(|rest| rest.trim().is_empty() || rest.trim().starts_with('#'));
+            if !owns_its_lines {
+                tracing::warn!(
+                    "not sorting inputs because `{name}` shares a line with another binding"
+                );
+                continue 'groups;
+            }
+
+            let first_line = first_line_of_leading_comments(&lines, from_span.start.line);
+            slots.push((name, first_line - 1, to_span.end.line - 1));
+        }
+
+        let mut sorted = slots.clone();
+        sorted.sort_by(|(a, _, _), (b, _, _)| a.cmp(b));
+
+        for ((_, first, last), (_, sorted_first, sorted_last)) in slots.iter().zip(sorted.iter()) {
+            replacements.push((*first, *last, lines[*sorted_first..=*sorted_last].concat()));
+        }
+    }
+
+    replacements.sort_by_key(|(first, _, _)| *first);
+
+    let mut new_flake_contents = String::with_capacity(flake_contents.len());
+    let mut line = 0;
+    for (first, last, text) in replacements {
+        new_flake_contents.push_str(&lines[line..first].concat());
+        new_flake_contents.push_str(&text);
+        line = last + 1;
+    }
+    new_flake_contents.push_str(&lines[line..].concat());
+
+    Ok(new_flake_contents)
+}
+
+/// An input found by [`collect_all_inputs`].
+#[derive(Debug, Clone)]
+pub(crate) struct CollectedInput {
+    /// The binding that sets the input, e.g. `inputs.nixpkgs.url = "...";` or (inside of
+    /// `inputs = { ... }`) `nixpkgs = { url = "..."; };`.
+    pub(crate) binding: nixel::BindingKeyValue,
+    /// The full attr path of the binding, e.g. `["inputs", "nixpkgs", "url"]`, even when it's
+    /// inside of `inputs = { ... }`.
+    pub(crate) attr_path: Vec<String>,
+}
+
+impl CollectedInput {
+    fn new(binding: nixel::BindingKeyValue, prefix: &[&str]) -> Self {
+        let attr_path: Vec<String> = prefix
+            .iter()
+            .copied()
+            .chain(binding.from.iter().filter_map(attr_name))
+            .map(ToString::to_string)
+            .collect();
+        Self { binding, attr_path }
+    }
+
+    /// The input's `url` value: either the value of the binding itself (`inputs.nixpkgs.url =
+    /// ...`), or of the `url` in the attrset it binds (`inputs.nixpkgs = { url = ...; }`).
+    pub(crate) fn url_value(&self) -> Option<&nixel::Expression> {
+        if self.attr_path.last().map(String::as_str) == Some("url") {
+            return Some(&self.binding.to);
+        }
+
+        let nixel::Expression::Map(map) = &*self.binding.to else {
+            return None;
+        };
+        map.bindings.iter().find_map(|binding| match binding {
+            nixel::Binding::KeyValue(kv) if attr_names(kv)[..] == [Some("url")] => Some(&*kv.to),
+            _ => None,
+        })
+    }
+
+    /// The input's name, e.g. `nixpkgs` for `inputs.nixpkgs.url = ...`.
+    pub(crate) fn name(&self) -> Option<String> {
+        self.binding.from.iter().find_map(|part| {
+            let content = attr_name(part)?.trim().to_string();
+
+            if ["inputs", "url"].contains(&content.as_ref()) {
+                None
+            } else {
+                Some(content)
+            }
+        })
+    }
+}
+
 #[tracing::instrument(skip_all)]
 pub(crate) fn collect_all_inputs(
     all_toplevel_inputs: Vec<nixel::BindingKeyValue>,
-) -> color_eyre::Result<Vec<nixel::BindingKeyValue>> {
+) -> color_eyre::Result<Vec<CollectedInput>> {
     let mut all_inputs = Vec::new();
 
     for v in all_toplevel_inputs {
@@ -101,10 +520,7 @@ pub(crate) fn collect_all_inputs(
             .from
             .iter()
             // Deliberately not filter_map, because if any of the values aren't Raw, we want to skip the whole "input"
-            .map(|from| match from {
-                nixel::Part::Raw(p) => Some(&*p.content),
-                _ => None,
-            })
+            .map(attr_name)
             .collect::<Option<Vec<&str>>>();
         let name_parts = match name_parts {
             Some(n) => n,
@@ -125,15 +541,19 @@ pub(crate) fn collect_all_inputs(
 
         match name_parts[..] {
             ["inputs"] => {
-                all_inputs.extend(find_all_attrsets_by_path(&v.to, None)?);
+                all_inputs.extend(
+                    find_all_attrsets_by_path(&v.to, None)?
+                        .into_iter()
+                        .map(|kv| CollectedInput::new(kv, &["inputs"])),
+                );
             }
             ["inputs", name] => {
                 tracing::trace!("Identified input.{name} = ...");
-                all_inputs.push(v);
+                all_inputs.push(CollectedInput::new(v, &[]));
             }
             ["inputs", name, "url"] => {
                 tracing::trace!("Identified input.{name}.url = ...");
-                all_inputs.push(v);
+                all_inputs.push(CollectedInput::new(v, &[]));
             }
             _ => {
                 tracing::debug!("Skipping processing: {:?}", name_parts);
@@ -144,6 +564,19 @@ pub(crate) fn collect_all_inputs(
     Ok(all_inputs)
 }
 
+/// The expression that `expr` evaluates to once the `let ... in`, `with ...;`, and `assert ...;`
+/// wrapped around it are stripped away, e.g. the attrset of `let system = "x86_64-linux"; in { }`.
+pub(crate) fn unwrap_expression(mut expr: &nixel::Expression) -> &nixel::Expression {
+    loop {
+        expr = match expr {
+            nixel::Expression::LetIn(let_in) => &let_in.target,
+            nixel::Expression::With(with) => &with.target,
+            nixel::Expression::Assert(assert) => &assert.target,
+            _ => return expr,
+        };
+    }
+}
+
 #[tracing::instrument(skip_all)]
 pub(crate) fn find_first_attrset_by_path(
     expr: &nixel::Expression,
@@ -164,19 +597,18 @@ pub(crate) fn find_all_attrsets_by_path(
 ) -> color_eyre::Result<Vec<nixel::BindingKeyValue>> {
     let mut found_kvs = Vec::new();
 
-    match expr {
+    match unwrap_expression(expr) {
         nixel::Expression::Map(map) => {
             for binding in map.bindings.iter() {
                 match binding {
                     nixel::Binding::KeyValue(kv) => {
                         if let Some(ref attr_path) = attr_path {
                             // Transform `inputs.nixpkgs.url` into `["inputs", "nixpkgs", "url"]`
-                            let mut this_attr_path: VecDeque<(String, &nixel::PartRaw)> = kv
+                            let mut this_attr_path: VecDeque<(String, &nixel::Part)> = kv
                                 .from
                                 .iter()
-                                .filter_map(|attr| match attr {
-                                    nixel::Part::Raw(raw) => Some((raw.content.to_string(), raw)),
-                                    _ => None,
+                                .filter_map(|attr| {
+                                    attr_name(attr).map(|name| (name.to_string(), attr))
                                 })
                                 .collect();
 
@@ -234,36 +666,54 @@ pub(crate) fn find_all_attrsets_by_path(
                         }
                     }
                     nixel::Binding::Inherit(inherit) => {
-                        let start = &inherit.span.start;
-                        return Err(color_eyre::eyre::eyre!(
-                            "`inherit` not supported (at {}:{})",
-                            start.line,
-                            start.column
-                        ));
+                        // We only care about an `inherit` if it binds the attr we're looking for;
+                        // otherwise, it's just some other unrelated binding.
+                        match attr_path.as_ref().and_then(|path| path.front()) {
+                            Some(attr) if inherit_binds_attr(inherit, attr) => {
+                                return Err(UnsupportedExpression::new(
+                                    format!(
+                                        "`{attr}` is bound with `inherit`, which is not supported"
+                                    ),
+                                    (*inherit.span.start).clone(),
+                                )
+                                .into());
+                            }
+                            _ => {
+                                tracing::debug!("Skipping unrelated `inherit` binding");
+                            }
+                        }
                     }
                 }
             }
         }
         t => {
-            let start = t.start();
-            return Err(color_eyre::eyre::eyre!(
-                "unsupported expression type {} (at {}:{})",
-                t.variant_name(),
-                start.line,
-                start.column
-            ));
+            return Err(UnsupportedExpression::new(
+                format!("unsupported expression type {}", t.variant_name()),
+                t.start(),
+            )
+            .into());
         }
     }
 
     Ok(found_kvs)
 }
 
+/// Whether the `inherit` binding binds `attr`, e.g. `inherit (sources) nixpkgs;` binds `nixpkgs`.
+pub(crate) fn inherit_binds_attr(inherit: &nixel::BindingInherit, attr: &str) -> bool {
+    inherit
+        .attributes
+        .iter()
+        .any(|part| attr_name(part) == Some(attr))
+}
+
 #[derive(Clone, Copy, Debug)]
 pub(crate) enum InputsInsertionLocation {
     /// The new input will be inserted at the top (either above all other `inputs`, or as the first input inside of `inputs = { ... }`)
     Top,
     /// The new input will be inserted at the bottom (either below all other `inputs`, or as the last input inside of `inputs = { ... }`)
     Bottom,
+    /// The new input will be inserted above the first input whose name sorts after it (or at the bottom, if there is none)
+    Alphabetical,
 }
 
 impl std::fmt::Display for InputsInsertionLocation {
@@ -271,6 +721,7 @@ impl std::fmt::Display for InputsInsertionLocation {
         match self {
             InputsInsertionLocation::Top => f.write_str("top"),
             InputsInsertionLocation::Bottom => f.write_str("bottom"),
+            InputsInsertionLocation::Alphabetical => f.write_str("alphabetical"),
         }
     }
 }
@@ -282,9 +733,10 @@ impl std::str::FromStr for InputsInsertionLocation {
         Ok(match s {
             "top" => InputsInsertionLocation::Top,
             "bottom" | "🥺" => InputsInsertionLocation::Bottom,
+            "alphabetical" => InputsInsertionLocation::Alphabetical,
             _ => {
                 return Err(color_eyre::eyre::eyre!(
-                    "only `top` and `bottom` are valid insertion locations"
+                    "only `top`, `bottom`, and `alphabetical` are valid insertion locations"
                 ))
             }
         })
@@ -307,35 +759,43 @@ impl AttrType {
         flake_input_name: &str,
         flake_input_value: &url::Url,
         insertion_location: InputsInsertionLocation,
+        comment: Option<&str>,
     ) -> color_eyre::Result<String> {
+        let inline_comment = comment;
+        let comment = comment.map(comment_lines).unwrap_or_default();
+
         match self {
             AttrType::Inputs(ref inputs_attr) => {
-                match inputs_attr.from.len() {
+                // `Alphabetical` was already resolved to an input to insert above or below.
+                let below = matches!(insertion_location, InputsInsertionLocation::Bottom);
+                let nested_input =
+                    format!(r#"{comment}{flake_input_name}.url = "{flake_input_value}";{NEWLINE}"#);
+
+                match attr_names(inputs_attr)[..] {
                     // inputs = { nixpkgs.url = ""; };
-                    1 => {
-                        let flake_input =
-                            format!(r#"{flake_input_name}.url = "{flake_input_value}";{NEWLINE}"#);
-
-                        match insertion_location {
-                            InputsInsertionLocation::Top => {
-                                let first_input =
-                                    find_first_attrset_by_path(&inputs_attr.to, None)?
-                                        .expect("there must be a first input");
-                                let (from_span, _to_span) = kv_to_span(&first_input);
-
-                                self.insert_input(from_span, None, flake_contents, &flake_input)
-                            }
-                            InputsInsertionLocation::Bottom => {
-                                let (from_span, to_span) = self.span();
-
-                                self.insert_input(
-                                    from_span,
-                                    Some(to_span),
-                                    flake_contents,
-                                    &flake_input,
-                                )
-                            }
-                        }
+                    [Some("inputs")] => {
+                        let all_inputs = find_all_attrsets_by_path(&inputs_attr.to, None)?;
+                        let input = if below {
+                            all_inputs.last()
+                        } else {
+                            all_inputs.first()
+                        };
+                        // inputs = { };
+                        let Some(input) = input else {
+                            return AttrType::insert_input_into_empty_inputs(
+                                inputs_attr,
+                                flake_contents,
+                                &nested_input,
+                            );
+                        };
+                        let (from_span, to_span) = kv_to_span(input);
+
+                        self.insert_input(
+                            from_span,
+                            below.then_some(to_span),
+                            flake_contents,
+                            &nested_input,
+                        )
                     }
 
                     // inputs.nixpkgs = { url = ""; inputs.something.follows = ""; };
@@ -344,23 +804,30 @@ impl AttrType {
                     // OR
                     // inputs.nixpkgs.inputs.something.follows = "";
                     // etc...
-                    _len => {
+                    [Some("inputs"), ..] => {
                         let (from_span, to_span) = self.span();
                         let flake_input = format!(
-                            r#"inputs.{flake_input_name}.url = "{flake_input_value}";{NEWLINE}"#
+                            r#"{comment}inputs.{flake_input_name}.url = "{flake_input_value}";{NEWLINE}"#
                         );
 
-                        match insertion_location {
-                            InputsInsertionLocation::Top => {
-                                self.insert_input(from_span, None, flake_contents, &flake_input)
-                            }
-                            InputsInsertionLocation::Bottom => self.insert_input(
-                                from_span,
-                                Some(to_span),
-                                flake_contents,
-                                &flake_input,
-                            ),
-                        }
+                        self.insert_input(
+                            from_span,
+                            below.then_some(to_span),
+                            flake_contents,
+                            &flake_input,
+                        )
+                    }
+
+                    // nixpkgs.url = ""; (inside of an `inputs = { ... };`)
+                    _ => {
+                        let (from_span, to_span) = self.span();
+
+                        self.insert_input(
+                            from_span,
+                            below.then_some(to_span),
+                            flake_contents,
+                            &nested_input,
+                        )
                     }
                 }
             }
@@ -384,20 +851,32 @@ impl AttrType {
                             Ok(flake_contents.to_string())
                         }
                     },
-                    t => {
-                        let start = t.start();
-                        Err(color_eyre::eyre::eyre!(
-                            "unsupported `outputs` expression type {} (at {}:{})",
-                            t.variant_name(),
-                            start.line,
-                            start.column
-                        ))
-                    }
+                    t => Err(UnsupportedExpression::new(
+                        format!("unsupported `outputs` expression type {}", t.variant_name()),
+                        t.start(),
+                    )
+                    .into()),
                 }
             }
             AttrType::MissingInputs((ref outputs_span_from, ref _outputs_span_to)) => {
-                let flake_input =
-                    format!(r#"inputs.{flake_input_name}.url = "{flake_input_value}";{NEWLINE}"#);
+                // `{ description = "..."; outputs = ...; }`: there's no line of its own to put the
+                // new input on, so it goes right in front of `outputs` instead
+                if !indentation_from_from_span(&LineIndex::new(flake_contents), outputs_span_from)?
+                    .trim()
+                    .is_empty()
+                {
+                    return AttrType::insert_input_before_outputs_inline(
+                        outputs_span_from,
+                        flake_contents,
+                        flake_input_name,
+                        flake_input_value,
+                        inline_comment,
+                    );
+                }
+
+                let flake_input = format!(
+                    r#"{comment}inputs.{flake_input_name}.url = "{flake_input_value}";{NEWLINE}"#
+                );
 
                 self.insert_input(
                     outputs_span_from.clone(),
@@ -435,17 +914,61 @@ impl AttrType {
     ) -> color_eyre::Result<String> {
         let mut new_flake_contents = flake_contents.to_string();
 
-        let indentation = indentation_from_from_span(flake_contents, &from_span)?;
+        let index = LineIndex::new(flake_contents);
+        let own_indentation = indentation_from_from_span(&index, &from_span)?;
+        let starts_its_line = own_indentation.trim().is_empty();
+        // e.g. `inputs = { nixpkgs.url = "..."; ...`, where the text in front of the input isn't
+        // indentation at all
+        let indentation = if starts_its_line {
+            own_indentation.to_string()
+        } else {
+            sibling_indentation(flake_contents, &from_span)?
+        };
+
+        let mut input = indent_lines(flake_input, &indentation);
+
+        if let Some(to_span) = &to_span {
+            // The new input goes on the line after the existing one's `;`, unless something else
+            // (like the closing brace of `inputs = { ... };`) follows it on that line
+            let end = index.offset(&to_span.end)?;
+            let after_semicolon = flake_contents[end..]
+                .find(';')
+                .map_or(end, |semicolon| end + semicolon + 1);
+            let rest_of_line = flake_contents[after_semicolon..]
+                .split(NEWLINE)
+                .next()
+                .unwrap_or_default()
+                .trim();
+            if !rest_of_line.is_empty() && !rest_of_line.starts_with('#') {
+                new_flake_contents.insert_str(
+                    after_semicolon,
+                    &format!("{NEWLINE}{}", input.trim_end_matches(NEWLINE)),
+                );
+
+                return Ok(new_flake_contents);
+            }
+        } else if !starts_its_line {
+            // Move the existing input to a line of its own, below the new one
+            let start = index.offset(&from_span.start)?;
+            let end_of_preceding_text = flake_contents[..start].trim_end_matches([' ', '\t']).len();
+            new_flake_contents.replace_range(
+                end_of_preceding_text..start,
+                &format!("{NEWLINE}{input}{indentation}"),
+            );
+
+            return Ok(new_flake_contents);
+        }
 
         let line = if let Some(to_span) = to_span {
             to_span.end.line + 1
         } else {
-            from_span.start.line
+            // Don't wedge the new input between an attr and the comment documenting it.
+            let lines: Vec<&str> = flake_contents.lines().collect();
+            first_line_of_leading_comments(&lines, from_span.start.line)
         };
         let old_content_pos = nixel::Position { line, column: 1 };
-        let offset = position_to_offset(&new_flake_contents, &old_content_pos)?;
-
-        let mut input = format!("{indentation}{flake_input}");
+        // Nothing was inserted yet, so `new_flake_contents` is still `flake_contents`
+        let offset = index.offset(&old_content_pos)?;
 
         // If we're not adding our new input above or below an existing `inputs` construct, let's
         // add another newline so that it looks nicer.
@@ -459,6 +982,60 @@ impl AttrType {
         Ok(new_flake_contents)
     }
 
+    /// Inserts `inputs.<name>.url = "...";` on the same line as, and right before, `outputs`. A
+    /// comment becomes a `/* ... */` one, since a `#` one would swallow the rest of the line.
+    #[tracing::instrument(skip_all)]
+    fn insert_input_before_outputs_inline(
+        outputs_span_from: &nixel::Span,
+        flake_contents: &str,
+        flake_input_name: &str,
+        flake_input_value: &url::Url,
+        comment: Option<&str>,
+    ) -> color_eyre::Result<String> {
+        let mut new_flake_contents = flake_contents.to_string();
+
+        let comment = comment
+            .map(|comment| {
+                let comment = comment.lines().map(str::trim).collect::<Vec<_>>().join(" ");
+                format!("/* {} */ ", comment.replace("*/", "* /"))
+            })
+            .unwrap_or_default();
+        let offset = LineIndex::new(flake_contents).offset(&outputs_span_from.start)?;
+        new_flake_contents.insert_str(
+            offset,
+            &format!(r#"{comment}inputs.{flake_input_name}.url = "{flake_input_value}"; "#),
+        );
+
+        Ok(new_flake_contents)
+    }
+
+    /// Replaces the braces of an empty `inputs = { };` with ones containing the new input, since
+    /// there's no existing input to insert it next to.
+    #[tracing::instrument(skip_all)]
+    fn insert_input_into_empty_inputs(
+        inputs_attr: &nixel::BindingKeyValue,
+        flake_contents: &str,
+        flake_input: &str,
+    ) -> color_eyre::Result<String> {
+        let mut new_flake_contents = flake_contents.to_string();
+
+        let (from_span, to_span) = kv_to_span(inputs_attr);
+        let index = LineIndex::new(flake_contents);
+        let indentation = indentation_from_from_span(&index, &from_span)?;
+        let start = index.offset(&to_span.start)?;
+        let end = index.offset(&to_span.end)?;
+
+        new_flake_contents.replace_range(
+            start..end,
+            &format!(
+                "{{{NEWLINE}{}{indentation}}}",
+                indent_lines(flake_input, &format!("{indentation}  "))
+            ),
+        );
+
+        Ok(new_flake_contents)
+    }
+
     #[tracing::instrument(skip_all)]
     pub(crate) fn insert_input_name_into_outputs_function(
         flake_input_name: &str,
@@ -484,8 +1061,9 @@ impl AttrType {
         // don't get span information for each input arg...)
         // let multiline_args = from_span.start.line != to_span.end.line;
 
-        let start = position_to_offset(flake_contents, &from_span.start)?;
-        let end = position_to_offset(flake_contents, &to_span.end)?;
+        let index = LineIndex::new(flake_contents);
+        let start = index.offset(&from_span.start)?;
+        let end = index.offset(&to_span.end)?;
         let mut span_text = String::from(&flake_contents[start..end]);
 
         new_flake_contents.replace_range(start..end, "");
@@ -541,27 +1119,207 @@ impl AttrType {
     }
 }
 
+/// Finds the first syntax error `nixel` reported while parsing, if any. `nixel` doesn't fail to parse
+/// broken Nix, but leaves `Error` expressions in the places it couldn't make sense of.
+pub(crate) fn find_parse_error(expr: &nixel::Expression) -> Option<&nixel::Error> {
+    match expr {
+        nixel::Expression::Error(error) => Some(error),
+        nixel::Expression::Assert(nixel::Assert {
+            expression, target, ..
+        })
+        | nixel::Expression::With(nixel::With {
+            expression, target, ..
+        }) => find_parse_error(expression).or_else(|| find_parse_error(target)),
+        nixel::Expression::BinaryOperation(op) => {
+            find_parse_error(&op.left).or_else(|| find_parse_error(&op.right))
+        }
+        nixel::Expression::UnaryOperation(op) => find_parse_error(&op.operand),
+        nixel::Expression::Function(function) => {
+            let in_head = match &function.head {
+                nixel::FunctionHead::Destructured(head) => head
+                    .arguments
+                    .iter()
+                    .find_map(|arg| arg.default.as_deref().and_then(find_parse_error)),
+                nixel::FunctionHead::Simple(_) => None,
+            };
+
+            in_head.or_else(|| find_parse_error(&function.body))
+        }
+        nixel::Expression::FunctionApplication(application) => {
+            find_parse_error(&application.function)
+                .or_else(|| application.arguments.iter().find_map(find_parse_error))
+        }
+        nixel::Expression::HasAttribute(has) => find_parse_error(&has.expression)
+            .or_else(|| find_parse_error_in_parts(&has.attribute_path)),
+        nixel::Expression::PropertyAccess(access) => find_parse_error(&access.expression)
+            .or_else(|| find_parse_error_in_parts(&access.attribute_path))
+            .or_else(|| access.default.as_deref().and_then(find_parse_error)),
+        nixel::Expression::IfThenElse(if_then_else) => find_parse_error(&if_then_else.predicate)
+            .or_else(|| find_parse_error(&if_then_else.then))
+            .or_else(|| find_parse_error(&if_then_else.else_)),
+        nixel::Expression::LetIn(let_in) => find_parse_error_in_bindings(&let_in.bindings)
+            .or_else(|| find_parse_error(&let_in.target)),
+        nixel::Expression::List(list) => list.elements.iter().find_map(find_parse_error),
+        nixel::Expression::Map(map) => find_parse_error_in_bindings(&map.bindings),
+        nixel::Expression::String(nixel::String_ { parts, .. })
+        | nixel::Expression::IndentedString(nixel::IndentedString { parts, .. })
+        | nixel::Expression::Path(nixel::Path { parts, .. }) => find_parse_error_in_parts(parts),
+        nixel::Expression::Float(_)
+        | nixel::Expression::Identifier(_)
+        | nixel::Expression::Integer(_)
+        | nixel::Expression::SearchNixPath(_)
+        | nixel::Expression::Uri(_) => None,
+    }
+}
+
+fn find_parse_error_in_bindings(bindings: &[nixel::Binding]) -> Option<&nixel::Error> {
+    bindings.iter().find_map(|binding| match binding {
+        nixel::Binding::KeyValue(kv) => {
+            find_parse_error_in_parts(&kv.from).or_else(|| find_parse_error(&kv.to))
+        }
+        nixel::Binding::Inherit(inherit) => inherit
+            .from
+            .as_deref()
+            .and_then(find_parse_error)
+            .or_else(|| find_parse_error_in_parts(&inherit.attributes)),
+    })
+}
+
+fn find_parse_error_in_parts(parts: &[nixel::Part]) -> Option<&nixel::Error> {
+    parts.iter().find_map(|part| match part {
+        nixel::Part::Expression(nixel::PartExpression { expression })
+        | nixel::Part::Interpolation(nixel::PartInterpolation { expression }) => {
+            find_parse_error(expression)
+        }
+        nixel::Part::Raw(_) => None,
+    })
+}
+
+/// The names in a binding's attr path, e.g. `[Some("inputs"), Some("nixpkgs"), Some("url")]` for
+/// `inputs.nixpkgs.url`.
+fn attr_names(kv: &nixel::BindingKeyValue) -> Vec<Option<&str>> {
+    kv.from.iter().map(attr_name).collect()
+}
+
+/// The name a part of an attr path refers to, e.g. `my-input` for both `my-input` and `"my-input"`
+/// in `inputs."my-input".url`. Returns `None` if the name is interpolated (e.g. `${name}`).
+pub(crate) fn attr_name(part: &nixel::Part) -> Option<&str> {
+    match part {
+        nixel::Part::Raw(raw) => Some(&raw.content),
+        nixel::Part::Expression(nixel::PartExpression { expression }) => match &**expression {
+            nixel::Expression::String(nixel::String_ { parts, .. }) => match &parts[..] {
+                [nixel::Part::Raw(raw)] => Some(&raw.content),
+                _ => None,
+            },
+            _ => None,
+        },
+        nixel::Part::Interpolation(_) => None,
+    }
+}
+
+/// The name of an input returned by [`collect_all_inputs`], whether it was bound at the top level
+/// (`inputs.nixpkgs.url = ...`) or inside of `inputs = { ... }` (`nixpkgs.url = ...`).
+fn input_name(kv: &nixel::BindingKeyValue) -> Option<&str> {
+    match attr_names(kv)[..] {
+        [Some("inputs"), name, ..] => name,
+        [name, ..] => name,
+        [] => None,
+    }
+}
+
+/// Returns the (1-based) line at which the run of comment lines directly above `line` starts, or
+/// `line` itself if it isn't preceded by a comment.
+fn first_line_of_leading_comments(lines: &[&str], line: usize) -> usize {
+    let mut first_line = line;
+
+    while first_line > 1 {
+        let previous = lines
+            .get(first_line - 2)
+            .map(|l| l.trim())
+            .unwrap_or_default();
+        if previous.starts_with('#') {
+            first_line -= 1;
+        } else {
+            break;
+        }
+    }
+
+    first_line
+}
+
 pub(crate) fn indentation_from_from_span<'a>(
-    flake_contents: &'a str,
+    index: &LineIndex<'a>,
     from_span: &nixel::Span,
 ) -> color_eyre::Result<&'a str> {
-    let old_content_start_of_indentation_pos = nixel::Position {
+    let start_of_indentation_pos = nixel::Position {
         line: from_span.start.line,
         column: 1,
     };
-    let old_content_end_of_indentation_pos = from_span.start.clone();
-
-    let indentation_span = nixel::Span {
-        start: Box::new(old_content_start_of_indentation_pos),
-        end: old_content_end_of_indentation_pos,
-    };
-    let (indentation_start, indentation_end) =
-        span_to_start_end_offsets(flake_contents, &indentation_span)?;
-    let indentation = &flake_contents[indentation_start..indentation_end];
+    let indentation_start = index.offset(&start_of_indentation_pos)?;
+    let indentation_end = index.offset(&from_span.start)?;
+    let indentation = &index.contents[indentation_start..indentation_end];
 
     Ok(indentation)
 }
 
+/// The indentation to give a new input next to the one starting at `from_span`, which doesn't start
+/// its line: that of the closest input in the same group (the top-level `inputs.*` bindings, or the
+/// ones inside of an `inputs = { ... }`) that does, or else one level deeper than the line it's on.
+fn sibling_indentation(
+    flake_contents: &str,
+    from_span: &nixel::Span,
+) -> color_eyre::Result<String> {
+    let parsed = nixel::parse(flake_contents.to_string());
+    let inputs_attr_path: VecDeque<String> = [String::from("inputs")].into();
+    let all_inputs = find_all_attrsets_by_path(&parsed.expression, Some(inputs_attr_path))?;
+
+    let (nested, toplevel): (Vec<_>, Vec<_>) = all_inputs
+        .into_iter()
+        .partition(|kv| attr_names(kv)[..] == [Some("inputs")]);
+    let mut groups = vec![toplevel];
+    for inputs_attr in nested {
+        groups.push(find_all_attrsets_by_path(&inputs_attr.to, None).unwrap_or_default());
+    }
+
+    let siblings = groups
+        .into_iter()
+        .find(|group| {
+            group
+                .iter()
+                .any(|kv| *kv_to_span(kv).0.start == *from_span.start)
+        })
+        .unwrap_or_default();
+
+    let index = LineIndex::new(flake_contents);
+    let closest_indentation = siblings
+        .iter()
+        .map(|kv| kv_to_span(kv).0)
+        .filter_map(|span| {
+            let indentation = indentation_from_from_span(&index, &span).ok()?;
+            indentation
+                .trim()
+                .is_empty()
+                .then(|| (span.start.line.abs_diff(from_span.start.line), indentation))
+        })
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, indentation)| indentation.to_string());
+
+    Ok(closest_indentation.unwrap_or_else(|| {
+        let line = flake_contents
+            .split(NEWLINE)
+            .nth(from_span.start.line - 1)
+            .unwrap_or_default();
+        let line_indentation = &line[..line.len() - line.trim_start().len()];
+        let level = if line_indentation.contains('\t') {
+            "\t"
+        } else {
+            "  "
+        };
+
+        format!("{line_indentation}{level}")
+    }))
+}
+
 #[tracing::instrument(skip_all)]
 pub(crate) fn kv_to_span(kv: &nixel::BindingKeyValue) -> (nixel::Span, nixel::Span) {
     (
@@ -574,7 +1332,26 @@ pub(crate) fn kv_to_span(kv: &nixel::BindingKeyValue) -> (nixel::Span, nixel::Sp
     )
 }
 
+/// Turns the text of `--comment` into Nix comment lines, one per line of text.
+fn comment_lines(comment: &str) -> String {
+    comment
+        .lines()
+        .map(|line| match line.trim_end() {
+            "" => format!("#{NEWLINE}"),
+            line => format!("# {line}{NEWLINE}"),
+        })
+        .collect()
+}
+
+/// Prefixes every line of `text` with `indentation`.
+fn indent_lines(text: &str, indentation: &str) -> String {
+    text.split_inclusive(NEWLINE)
+        .map(|line| format!("{indentation}{line}"))
+        .collect()
+}
+
 #[tracing::instrument(skip_all)]
+#[allow(clippy::too_many_arguments)]
 pub(crate) fn upsert_into_inputs_and_outputs(
     flake_input_name: String,
     flake_input_value: url::Url,
@@ -583,6 +1360,7 @@ pub(crate) fn upsert_into_inputs_and_outputs(
     inputs_attr: Option<nixel::BindingKeyValue>,
     outputs_attr: Option<nixel::BindingKeyValue>,
     insertion_location: InputsInsertionLocation,
+    comment: Option<&str>,
 ) -> color_eyre::Result<String> {
     let inputs_attr = inputs_attr.map(AttrType::Inputs);
     let outputs_attr = outputs_attr.map(AttrType::Outputs);
@@ -618,6 +1396,7 @@ pub(crate) fn upsert_into_inputs_and_outputs(
             &flake_input_name,
             &flake_input_value,
             insertion_location,
+            comment,
         )?;
     }
     if let Some(second_attr_to_process) = second_attr_to_process {
@@ -626,116 +1405,450 @@ pub(crate) fn upsert_into_inputs_and_outputs(
             &flake_input_name,
             &flake_input_value,
             insertion_location,
+            comment,
         )?;
     }
 
     Ok(flake_contents)
 }
 
-#[tracing::instrument(skip_all)]
-pub(crate) fn replace_input_value_string(
-    parts: &[nixel::Part],
-    flake_input_value: &url::Url,
-    flake_contents: &str,
-) -> color_eyre::Result<String> {
-    let mut parts_iter = parts.iter();
-    let mut new_flake_contents = flake_contents.to_string();
+/// The parts of a string, if it has any, or an [`InterpolatedUrl`] error if any of them is an
+/// interpolation rather than text.
+fn raw_parts(parts: &[nixel::Part]) -> Result<Option<Vec<&nixel::PartRaw>>, InterpolatedUrl> {
+    let raw_parts = parts
+        .iter()
+        .map(|part| match part {
+            nixel::Part::Raw(raw) => Ok(raw),
+            part => Err(InterpolatedUrl::new(part.start())),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
 
-    if let Some(part) = parts_iter.next() {
-        match part {
-            nixel::Part::Raw(raw) => {
-                let (start, end) = span_to_start_end_offsets(flake_contents, &raw.span)?;
+    Ok((!raw_parts.is_empty()).then_some(raw_parts))
+}
 
-                // Replace the current contents with nothingness
-                new_flake_contents.replace_range(start..end, "");
-                // Insert the new contents
-                new_flake_contents.insert_str(start, flake_input_value.as_ref());
-            }
-            part => {
-                let start = part.start();
-                return Err(color_eyre::eyre::eyre!(
-                    "unexpected expression or interpolation (at {}:{})",
-                    start.line,
-                    start.column
-                ));
-            }
-        }
-    }
+/// A part of the flake that `fh` doesn't know how to handle. Commands attach the offending line of
+/// the flake to it with [`with_source_context`].
+#[derive(Debug, thiserror::Error)]
+#[error("{message} (at {}:{})", position.line, position.column)]
+pub(crate) struct UnsupportedExpression {
+    pub(crate) message: String,
+    pub(crate) position: nixel::Position,
+}
 
-    // idk when this list of parts could have more than 1.... (maybe just a side-effect of the
-    // bindgen code generation?)
-    if parts_iter.next().is_some() {
-        return Err(color_eyre::eyre::eyre!(
-            "Nix string had multiple parts -- please report this and include the flake.nix that triggered this!"
-        ));
+impl UnsupportedExpression {
+    pub(crate) fn new(message: impl Into<String>, position: nixel::Position) -> Self {
+        Self {
+            message: message.into(),
+            position,
+        }
     }
+}
 
-    Ok(new_flake_contents)
+/// A URL built with string interpolation, e.g. `"github:${owner}/repo"`, which can't be replaced
+/// without losing whatever it interpolates.
+#[derive(Debug, thiserror::Error)]
+#[error("the URL uses string interpolation, which is not supported (at {}:{})", position.line, position.column)]
+pub(crate) struct InterpolatedUrl {
+    pub(crate) position: nixel::Position,
 }
 
-#[tracing::instrument(skip_all)]
-pub(crate) fn replace_input_value_uri(
-    uri: &nixel::Uri,
-    flake_input_value: &url::Url,
-    flake_contents: &str,
-) -> color_eyre::Result<String> {
-    let mut new_flake_contents = flake_contents.to_string();
+impl InterpolatedUrl {
+    pub(crate) fn new(position: nixel::Position) -> Self {
+        Self { position }
+    }
+}
 
-    let (start, end) = span_to_start_end_offsets(flake_contents, &uri.span)?;
-    // Replace the current contents with nothingness
-    new_flake_contents.replace_range(start..end, "");
-    // Insert the new contents
-    new_flake_contents.insert_str(start, &format!(r#""{}""#, flake_input_value.as_ref()));
+/// If `err` was caused by an [`UnsupportedExpression`] or an [`InterpolatedUrl`], adds the line of
+/// `input` it points at to the report.
+pub(crate) fn with_source_context(err: color_eyre::Report, input: &str) -> color_eyre::Report {
+    let context = err
+        .chain()
+        .find_map(|cause| {
+            cause
+                .downcast_ref::<UnsupportedExpression>()
+                .map(|cause| &cause.position)
+                .or_else(|| {
+                    cause
+                        .downcast_ref::<InterpolatedUrl>()
+                        .map(|cause| &cause.position)
+                })
+        })
+        .and_then(|position| format_source_context(input, position));
 
-    Ok(new_flake_contents)
+    match context {
+        Some(context) => err.section(context),
+        None => err,
+    }
 }
 
-#[tracing::instrument(skip_all)]
-pub(crate) fn span_to_start_end_offsets(
-    flake_contents: &str,
-    span: &nixel::Span,
-) -> color_eyre::Result<(usize, usize)> {
-    let start = &*span.start;
-    let end = &*span.end;
-
-    Ok((
-        position_to_offset(flake_contents, start)?,
-        position_to_offset(flake_contents, end)?,
+/// Renders the line `position` is on with a caret under its column, e.g.:
+///
+/// ```text
+///    |
+/// 12 |   inputs.nixpkgs.url = 1;
+///    |                        ^
+/// ```
+///
+/// Returns `None` if `position` isn't inside of `input`.
+pub(crate) fn format_source_context(input: &str, position: &nixel::Position) -> Option<String> {
+    let line = input.lines().nth(position.line.checked_sub(1)?)?;
+    // Keep tabs so that the caret lines up with the line above it
+    let padding: String = line
+        .chars()
+        .take(position.column.saturating_sub(1))
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect();
+
+    let line_number = position.line.to_string();
+    let gutter = " ".repeat(line_number.len());
+
+    Some(format!(
+        "{gutter} {bar}\n{line_number} {bar} {line}\n{gutter} {bar} {padding}{caret}",
+        bar = "|".blue(),
+        line_number = line_number.blue(),
+        caret = "^".red().bold(),
     ))
 }
 
-#[tracing::instrument(skip_all)]
-pub(crate) fn position_to_offset(
-    flake_contents: &str,
-    position: &nixel::Position,
-) -> color_eyre::Result<usize> {
-    let mut column = 1;
-    let mut line = 1;
-
-    for (idx, ch) in flake_contents.char_indices() {
-        if column == position.column && line == position.line {
-            return Ok(idx);
-        }
+/// The byte offset of the start of each line of a document, so that several (1-based, in
+/// characters) positions can be turned into byte offsets without rescanning the document from the
+/// start for each of them.
+pub(crate) struct LineIndex<'a> {
+    contents: &'a str,
+    line_starts: Vec<usize>,
+}
 
-        if ch == '\n' {
-            line += 1;
-            column = 1;
-        } else {
-            column += 1;
+impl<'a> LineIndex<'a> {
+    pub(crate) fn new(contents: &'a str) -> Self {
+        let line_starts = std::iter::once(0)
+            .chain(contents.match_indices('\n').map(|(idx, _)| idx + 1))
+            .collect();
+
+        Self {
+            contents,
+            line_starts,
         }
     }
 
-    Err(color_eyre::eyre::eyre!(
-        "could not find {}:{} in input",
-        position.line,
-        position.column
-    ))
+    pub(crate) fn offset(&self, position: &nixel::Position) -> color_eyre::Result<usize> {
+        let not_found = || {
+            color_eyre::eyre::eyre!(
+                "could not find {}:{} in input",
+                position.line,
+                position.column
+            )
+        };
+
+        let line = position.line.checked_sub(1).ok_or_else(not_found)?;
+        let line_start = *self.line_starts.get(line).ok_or_else(not_found)?;
+        // Include the newline itself, since a span may end on it
+        let line_end = self
+            .line_starts
+            .get(line + 1)
+            .copied()
+            .unwrap_or(self.contents.len());
+        let line_contents = &self.contents[line_start..line_end];
+
+        // The end of the document is a valid position too, if it doesn't end with a newline
+        let end_of_document = (!line_contents.ends_with('\n')).then_some(line_contents.len());
+
+        let column = position.column.checked_sub(1).ok_or_else(not_found)?;
+        line_contents
+            .char_indices()
+            .map(|(idx, _)| idx)
+            .chain(end_of_document)
+            .nth(column)
+            .map(|idx| line_start + idx)
+            .ok_or_else(not_found)
+    }
 }
 
 #[cfg(test)]
 mod test {
     use super::InputsInsertionLocation;
 
+    #[test]
+    fn test_update_indented_string_input() {
+        for (flake_contents, expected) in [
+            (
+                "{\n  inputs.foo.url = ''github:a/b'';\n  outputs = _: { };\n}",
+                "{\n  inputs.foo.url = ''github:c/d'';\n  outputs = _: { };\n}",
+            ),
+            (
+                "{\n  inputs.foo.url = ''\n    github:a/b\n  '';\n  outputs = _: { };\n}",
+                "{\n  inputs.foo.url = ''github:c/d'';\n  outputs = _: { };\n}",
+            ),
+            (
+                "{\n  inputs.foo.url = ''\n    github:c/d\n  '';\n  outputs = _: { };\n}",
+                "{\n  inputs.foo.url = ''\n    github:c/d\n  '';\n  outputs = _: { };\n}",
+            ),
+        ] {
+            let parsed = nixel::parse(flake_contents.to_string());
+            let attr = super::find_first_attrset_by_path(
+                &parsed.expression,
+                Some(["inputs".into(), "foo".into(), "url".into()].into()),
+            )
+            .unwrap()
+            .unwrap();
+
+            let new_flake_contents = super::update_flake_input(
+                attr,
+                "foo".into(),
+                "github:c/d".parse().unwrap(),
+                flake_contents.to_string(),
+            )
+            .unwrap();
+            assert_eq!(new_flake_contents, expected);
+
+            let reparsed = nixel::parse(new_flake_contents.clone());
+            assert!(super::find_parse_error(&reparsed.expression).is_none());
+        }
+    }
+
+    #[test]
+    fn test_update_interpolated_input() {
+        let update = |flake_contents: &str| {
+            let parsed = nixel::parse(flake_contents.to_string());
+            let attr = super::find_first_attrset_by_path(
+                &parsed.expression,
+                Some(["inputs".into(), "foo".into(), "url".into()].into()),
+            )
+            .unwrap()
+            .unwrap();
+
+            super::update_flake_input(
+                attr,
+                "foo".into(),
+                "github:c/d".parse().unwrap(),
+                flake_contents.to_string(),
+            )
+        };
+
+        for flake_contents in [
+            "{\n  inputs.foo.url = \"github:${owner}/b\";\n  outputs = _: { };\n}",
+            "{\n  inputs.foo.url = \"${base}/b\";\n  outputs = _: { };\n}",
+            "{\n  inputs.foo.url = ''github:${owner}/b'';\n  outputs = _: { };\n}",
+        ] {
+            let err = update(flake_contents).unwrap_err();
+            assert!(
+                err.downcast_ref::<super::InterpolatedUrl>().is_some(),
+                "{flake_contents}: {err}"
+            );
+        }
+
+        // Escape sequences aren't interpolation
+        assert_eq!(
+            update("{\n  inputs.foo.url = \"github:a/b?dir=\\$x\";\n  outputs = _: { };\n}")
+                .unwrap(),
+            "{\n  inputs.foo.url = \"github:c/d\";\n  outputs = _: { };\n}"
+        );
+    }
+
+    #[test]
+    fn test_collect_all_inputs() {
+        let flake_contents = r#"{
+  inputs.nixpkgs.url = "github:NixOS/nixpkgs";
+  inputs.crane = { url = github:ipetkov/crane; flake = true; };
+  inputs = {
+    fenix.url = ''github:nix-community/fenix'';
+    naersk = { inputs.nixpkgs.follows = "nixpkgs"; };
+  };
+
+  outputs = { self, ... } @ inputs: { };
+}
+"#;
+        let parsed = nixel::parse(flake_contents.to_string());
+        let all_toplevel_inputs =
+            super::find_all_attrsets_by_path(&parsed.expression, Some(["inputs".into()].into()))
+                .unwrap();
+        let all_inputs = super::collect_all_inputs(all_toplevel_inputs).unwrap();
+
+        let found: Vec<(Vec<&str>, Option<&str>)> = all_inputs
+            .iter()
+            .map(|input| {
+                let url = input.url_value().map(|url_value| {
+                    let span = url_value.span();
+                    let index = super::LineIndex::new(flake_contents);
+                    &flake_contents
+                        [index.offset(&span.start).unwrap()..index.offset(&span.end).unwrap()]
+                });
+                (input.attr_path.iter().map(String::as_str).collect(), url)
+            })
+            .collect();
+        assert_eq!(
+            found,
+            [
+                (
+                    vec!["inputs", "nixpkgs", "url"],
+                    Some(r#""github:NixOS/nixpkgs""#)
+                ),
+                (vec!["inputs", "crane"], Some("github:ipetkov/crane")),
+                (
+                    vec!["inputs", "fenix", "url"],
+                    Some("''github:nix-community/fenix''")
+                ),
+                (vec!["inputs", "naersk"], None),
+            ]
+        );
+
+        let names: Vec<Option<String>> = all_inputs.iter().map(|input| input.name()).collect();
+        assert_eq!(
+            names,
+            ["nixpkgs", "crane", "fenix", "naersk"].map(|name| Some(name.to_string()))
+        );
+    }
+
+    #[test]
+    fn test_apply_edits() {
+        let flake_contents = r#"{
+  inputs = { a.url = "github:a/a"; b.url = "github:b/b"; };
+  inputs.c.url = github:c/c;
+
+  outputs = { self, ... } @ inputs: { };
+}
+"#;
+        let parsed = nixel::parse(flake_contents.to_string());
+        let all_toplevel_inputs =
+            super::find_all_attrsets_by_path(&parsed.expression, Some(["inputs".into()].into()))
+                .unwrap();
+        let all_inputs = super::collect_all_inputs(all_toplevel_inputs).unwrap();
+
+        // URLs of different lengths, on the same line and on different lines, in no particular
+        // order
+        let mut edits: Vec<super::Edit> = all_inputs
+            .iter()
+            .zip([
+                "https://flakehub.com/f/a/a/*.tar.gz",
+                "github:b",
+                "https://flakehub.com/f/c/c/0.1.0.tar.gz",
+            ])
+            .map(|(input, new_url)| {
+                super::url_edit(
+                    input.url_value().unwrap(),
+                    &input.attr_path[1],
+                    &new_url.parse().unwrap(),
+                )
+                .unwrap()
+                .unwrap()
+            })
+            .collect();
+        edits.swap(0, 2);
+
+        assert_eq!(
+            super::apply_edits(flake_contents, edits.clone()).unwrap(),
+            r#"{
+  inputs = { a.url = "https://flakehub.com/f/a/a/*.tar.gz"; b.url = "github:b"; };
+  inputs.c.url = "https://flakehub.com/f/c/c/0.1.0.tar.gz";
+
+  outputs = { self, ... } @ inputs: { };
+}
+"#
+        );
+
+        edits.push(edits[0].clone());
+        assert!(super::apply_edits(flake_contents, edits).is_err());
+    }
+
+    #[test]
+    fn test_format_source_context() {
+        let flake_contents = "{\n  inputs.nixpkgs.url = 1;\n\toutputs = _: { };\n}";
+        let strip_ansi = |s: String| {
+            regex::Regex::new("\x1b\\[[0-9;]*m")
+                .unwrap()
+                .replace_all(&s, "")
+                .to_string()
+        };
+
+        let context = super::format_source_context(
+            flake_contents,
+            &nixel::Position {
+                line: 2,
+                column: 24,
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            strip_ansi(context),
+            "  |\n2 |   inputs.nixpkgs.url = 1;\n  |                        ^"
+        );
+
+        let context =
+            super::format_source_context(flake_contents, &nixel::Position { line: 3, column: 2 })
+                .unwrap();
+        assert_eq!(strip_ansi(context), "  |\n3 | \toutputs = _: { };\n  | \t^");
+
+        assert!(super::format_source_context(
+            flake_contents,
+            &nixel::Position { line: 5, column: 1 }
+        )
+        .is_none());
+    }
+
+    #[test]
+    fn test_unsupported_expression_is_found_in_chain() {
+        use color_eyre::eyre::WrapErr;
+
+        let flake_contents = "{\n  inputs.nixpkgs.url = 1;\n  outputs = _: { };\n}";
+        let parsed = nixel::parse(flake_contents.to_string());
+        let attr = super::find_first_attrset_by_path(
+            &parsed.expression,
+            Some(["inputs".into(), "nixpkgs".into(), "url".into()].into()),
+        )
+        .unwrap()
+        .unwrap();
+
+        let err = super::update_flake_input(
+            attr,
+            "nixpkgs".into(),
+            "github:NixOS/nixpkgs".parse().unwrap(),
+            flake_contents.to_string(),
+        )
+        .wrap_err("Failed to add `nixpkgs`")
+        .unwrap_err();
+        let cause = err
+            .chain()
+            .find_map(|cause| cause.downcast_ref::<super::UnsupportedExpression>())
+            .unwrap();
+        assert_eq!(
+            cause.position,
+            nixel::Position {
+                line: 2,
+                column: 24
+            }
+        );
+        assert!(cause.to_string().ends_with("but Integer (at 2:24)"));
+    }
+
+    #[test]
+    fn test_line_index_matches_char_positions() {
+        let flake_contents = "{\n  description = \"Ünïcödé flake 🦀\";\n\n  outputs = _: { };\n}";
+        let index = super::LineIndex::new(flake_contents);
+
+        let (mut line, mut column) = (1, 1);
+        for (idx, ch) in flake_contents.char_indices() {
+            assert_eq!(
+                index.offset(&nixel::Position { line, column }).unwrap(),
+                idx,
+                "{line}:{column}"
+            );
+
+            if ch == '\n' {
+                line += 1;
+                column = 1;
+            } else {
+                column += 1;
+            }
+        }
+
+        assert_eq!(
+            index.offset(&nixel::Position { line, column }).unwrap(),
+            flake_contents.len()
+        );
+        for (line, column) in [(0, 1), (1, 0), (1, 3), (6, 1), (line, column + 1)] {
+            assert!(index.offset(&nixel::Position { line, column }).is_err());
+        }
+    }
+
     #[test]
     fn test_flake_1_rewrite_less_simple_flake_input() {
         let flake_contents = include_str!(concat!(
@@ -757,6 +1870,7 @@ mod test {
                 .map(ToString::to_string)
                 .into(),
             InputsInsertionLocation::Top,
+            None,
         );
         assert!(res.is_ok());
 
@@ -792,6 +1906,7 @@ mod test {
                 .map(ToString::to_string)
                 .into(),
             InputsInsertionLocation::Top,
+            None,
         );
         assert!(res.is_ok());
 
@@ -829,6 +1944,7 @@ mod test {
                     .map(ToString::to_string)
                     .into(),
                 InputsInsertionLocation::Top,
+                None,
             );
             assert!(res.is_ok());
 
@@ -860,6 +1976,7 @@ mod test {
                 .map(ToString::to_string)
                 .into(),
             InputsInsertionLocation::Top,
+            None,
         );
         assert!(res.is_ok());
 
@@ -931,6 +2048,7 @@ mod test {
                 .map(ToString::to_string)
                 .into(),
             InputsInsertionLocation::Top,
+            None,
         );
         assert!(res.is_ok());
 
@@ -975,6 +2093,7 @@ mod test {
                 .map(ToString::to_string)
                 .into(),
             InputsInsertionLocation::Top,
+            None,
         );
         assert!(res.is_ok());
 
@@ -1019,6 +2138,7 @@ mod test {
                 .map(ToString::to_string)
                 .into(),
             InputsInsertionLocation::Bottom,
+            None,
         );
         assert!(res.is_ok());
 
@@ -1056,4 +2176,615 @@ mod test {
 
         assert!(wezterm_line_idx < nixpkgs_input_idx, "when inserting at the bottom, the new nixpkgs input should have come after the wezterm input");
     }
+
+    #[test]
+    fn test_flake_11_walks_through_let_in() {
+        let flake_contents = include_str!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/samples/flake11.test.nix"
+        ));
+        let parsed = nixel::parse(flake_contents.to_string());
+
+        let outputs =
+            super::find_first_attrset_by_path(&parsed.expression, Some(["outputs".into()].into()))
+                .unwrap();
+        assert!(outputs.is_some());
+
+        let input_value =
+            url::Url::parse("https://flakehub.com/f/NixOS/nixpkgs/0.2305.*.tar.gz").unwrap();
+        let res = super::upsert_flake_input(
+            &parsed.expression,
+            String::from("nixpkgs"),
+            input_value.clone(),
+            flake_contents.to_string(),
+            ["inputs", "nixpkgs", "url"].map(ToString::to_string).into(),
+            InputsInsertionLocation::Top,
+            None,
+        )
+        .unwrap();
+        assert!(res
+            .contains(r#"nixpkgs.url = "https://flakehub.com/f/NixOS/nixpkgs/0.2305.*.tar.gz";"#));
+
+        let parsed = nixel::parse(res.clone());
+        let res = super::upsert_flake_input(
+            &parsed.expression,
+            String::from("crane"),
+            url::Url::parse("https://flakehub.com/f/ipetkov/crane/*.tar.gz").unwrap(),
+            res,
+            ["inputs", "crane", "url"].map(ToString::to_string).into(),
+            InputsInsertionLocation::Alphabetical,
+            None,
+        )
+        .unwrap();
+        assert!(res.starts_with("let\n  system = \"x86_64-linux\";\nin\n{\n"));
+        assert!(res.contains(
+            "    crane.url = \"https://flakehub.com/f/ipetkov/crane/*.tar.gz\";\n    nixpkgs.url"
+        ));
+        assert!(res.contains("outputs = { self, nixpkgs, crane, ... }: {"));
+    }
+
+    #[test]
+    fn test_insert_above_comment() {
+        let flake_contents = r#"{
+  description = "My flake.";
+
+  # The one and only
+  # nixpkgs
+  inputs.nixpkgs.url = "github:NixOS/nixpkgs";
+
+  outputs = { self, ... } @ inputs: { };
+}
+"#;
+        let parsed = nixel::parse(flake_contents.to_string());
+
+        let res = super::insert_flake_input(
+            &parsed.expression,
+            "crane".into(),
+            "github:ipetkov/crane".parse().unwrap(),
+            flake_contents.to_string(),
+            InputsInsertionLocation::Top,
+            None,
+        )
+        .unwrap();
+
+        assert!(res.contains(
+            r#"  inputs.crane.url = "github:ipetkov/crane";
+  # The one and only
+  # nixpkgs
+  inputs.nixpkgs.url = "github:NixOS/nixpkgs";"#
+        ));
+    }
+
+    #[test]
+    fn test_insert_alphabetical_and_bottom_in_inputs_attrset() {
+        let flake_contents = r#"{
+  inputs = {
+    crane.url = "github:ipetkov/crane";
+    # The package set
+    nixpkgs = {
+      url = "github:NixOS/nixpkgs";
+    };
+  };
+
+  outputs = { self, ... } @ inputs: { };
+}
+"#;
+        let parsed = nixel::parse(flake_contents.to_string());
+        let insert = |location| {
+            super::insert_flake_input(
+                &parsed.expression,
+                "fenix".into(),
+                "github:nix-community/fenix".parse().unwrap(),
+                flake_contents.to_string(),
+                location,
+                None,
+            )
+            .unwrap()
+        };
+
+        let res = insert(InputsInsertionLocation::Alphabetical);
+        assert!(res.contains(
+            r#"    crane.url = "github:ipetkov/crane";
+    fenix.url = "github:nix-community/fenix";
+    # The package set
+"#
+        ));
+
+        let res = insert(InputsInsertionLocation::Bottom);
+        assert!(res.contains(
+            r#"      url = "github:NixOS/nixpkgs";
+    };
+    fenix.url = "github:nix-community/fenix";
+  };
+"#
+        ));
+    }
+
+    #[test]
+    fn test_insert_matches_indentation() {
+        let insert = |flake_contents: &str, location| {
+            let parsed = nixel::parse(flake_contents.to_string());
+            super::insert_flake_input(
+                &parsed.expression,
+                "fenix".into(),
+                "github:nix-community/fenix".parse().unwrap(),
+                flake_contents.to_string(),
+                location,
+                None,
+            )
+            .unwrap()
+        };
+
+        for indentation in ["\t", "  ", "    "] {
+            let flake_contents = format!(
+                "{{\n{i}inputs = {{\n{i}{i}nixpkgs = {{\n{i}{i}{i}url = \"github:NixOS/nixpkgs\";\n{i}{i}}};\n{i}}};\n\n{i}outputs = {{ self, ... }} @ inputs: {{ }};\n}}\n",
+                i = indentation
+            );
+            let new_input = format!("\n{indentation}{indentation}fenix.url = ");
+
+            let res = insert(&flake_contents, InputsInsertionLocation::Top);
+            assert!(
+                res.contains(&format!("{indentation}inputs = {{{new_input}")),
+                "{res}"
+            );
+
+            let res = insert(&flake_contents, InputsInsertionLocation::Bottom);
+            assert!(
+                res.contains(&format!("{indentation}{indentation}}};{new_input}")),
+                "{res}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_insert_next_to_inputs_sharing_lines() {
+        let flake_contents = "{\n\tinputs = { nixpkgs.url = \"github:NixOS/nixpkgs\";\n\t\tcrane.url = \"github:ipetkov/crane\"; };\n\n\toutputs = { self, ... } @ inputs: { };\n}\n";
+        let insert = |location| {
+            let parsed = nixel::parse(flake_contents.to_string());
+            super::insert_flake_input(
+                &parsed.expression,
+                "fenix".into(),
+                "github:nix-community/fenix".parse().unwrap(),
+                flake_contents.to_string(),
+                location,
+                None,
+            )
+            .unwrap()
+        };
+
+        // The input the new one goes above gets a line of its own, indented like the other inputs
+        assert_eq!(
+            insert(InputsInsertionLocation::Top),
+            "{\n\tinputs = {\n\t\tfenix.url = \"github:nix-community/fenix\";\n\t\tnixpkgs.url = \"github:NixOS/nixpkgs\";\n\t\tcrane.url = \"github:ipetkov/crane\"; };\n\n\toutputs = { self, fenix, ... } @ inputs: { };\n}\n"
+        );
+
+        // ...and the one below stays inside of the braces
+        assert_eq!(
+            insert(InputsInsertionLocation::Bottom),
+            "{\n\tinputs = { nixpkgs.url = \"github:NixOS/nixpkgs\";\n\t\tcrane.url = \"github:ipetkov/crane\";\n\t\tfenix.url = \"github:nix-community/fenix\"; };\n\n\toutputs = { self, fenix, ... } @ inputs: { };\n}\n"
+        );
+
+        // Without another input to take the indentation from, it's one level deeper than `inputs`
+        let flake_contents = "{\n  inputs = { nixpkgs.url = \"github:NixOS/nixpkgs\"; };\n\n  outputs = { self, ... } @ inputs: { };\n}\n";
+        let parsed = nixel::parse(flake_contents.to_string());
+        let res = super::insert_flake_input(
+            &parsed.expression,
+            "fenix".into(),
+            "github:nix-community/fenix".parse().unwrap(),
+            flake_contents.to_string(),
+            InputsInsertionLocation::Top,
+            None,
+        )
+        .unwrap();
+        assert!(res.starts_with(
+            "{\n  inputs = {\n    fenix.url = \"github:nix-community/fenix\";\n    nixpkgs.url"
+        ));
+    }
+
+    #[test]
+    fn test_insert_with_comment() {
+        let insert = |flake_contents: &str| {
+            let parsed = nixel::parse(flake_contents.to_string());
+            super::insert_flake_input(
+                &parsed.expression,
+                "crane".into(),
+                "github:ipetkov/crane".parse().unwrap(),
+                flake_contents.to_string(),
+                InputsInsertionLocation::Top,
+                Some("Builds our Rust packages\n\nPinned for reproducibility"),
+            )
+            .unwrap()
+        };
+
+        let res = insert(
+            r#"{
+  inputs.nixpkgs.url = "github:NixOS/nixpkgs";
+
+  outputs = { self, ... } @ inputs: { };
+}
+"#,
+        );
+        assert!(res.contains(
+            r#"  # Builds our Rust packages
+  #
+  # Pinned for reproducibility
+  inputs.crane.url = "github:ipetkov/crane";
+  inputs.nixpkgs.url = "github:NixOS/nixpkgs";"#
+        ));
+
+        let res = insert(
+            r#"{
+  inputs = {
+    nixpkgs.url = "github:NixOS/nixpkgs";
+  };
+
+  outputs = { self, ... } @ inputs: { };
+}
+"#,
+        );
+        assert!(res.contains(
+            r#"  inputs = {
+    # Builds our Rust packages
+    #
+    # Pinned for reproducibility
+    crane.url = "github:ipetkov/crane";
+    nixpkgs.url = "github:NixOS/nixpkgs";"#
+        ));
+
+        let res = insert(
+            r#"{
+  inputs = { };
+
+  outputs = { self, ... } @ inputs: { };
+}
+"#,
+        );
+        assert!(res.contains(
+            r#"  inputs = {
+    # Builds our Rust packages
+    #
+    # Pinned for reproducibility
+    crane.url = "github:ipetkov/crane";
+  };"#
+        ));
+    }
+
+    #[test]
+    fn test_insert_without_any_inputs() {
+        let insert = |flake_contents: &str, comment: Option<&str>| {
+            let parsed = nixel::parse(flake_contents.to_string());
+            super::insert_flake_input(
+                &parsed.expression,
+                "crane".into(),
+                "github:ipetkov/crane".parse().unwrap(),
+                flake_contents.to_string(),
+                InputsInsertionLocation::Top,
+                comment,
+            )
+            .unwrap()
+        };
+
+        // Below the description, above the outputs
+        assert_eq!(
+            insert(super::super::FALLBACK_FLAKE_CONTENTS, None),
+            r#"{
+  description = "My new flake.";
+
+  inputs.crane.url = "github:ipetkov/crane";
+
+  outputs = { crane, ... } @ inputs: { };
+}
+"#
+        );
+
+        // With nowhere to put a line of its own, the input goes in front of the outputs
+        assert_eq!(
+            insert(
+                "{ description = \"My new flake.\"; outputs = { self }: { }; }\n",
+                Some("Builds our Rust packages"),
+            ),
+            "{ description = \"My new flake.\"; /* Builds our Rust packages */ inputs.crane.url = \"github:ipetkov/crane\"; outputs = { self, crane }: { }; }\n"
+        );
+    }
+
+    #[test]
+    fn test_insert_alphabetical_at_toplevel() {
+        let flake_contents = r#"{
+  inputs.crane.url = "github:ipetkov/crane";
+  inputs.nixpkgs.url = "github:NixOS/nixpkgs";
+
+  outputs = { self, ... } @ inputs: { };
+}
+"#;
+        let parsed = nixel::parse(flake_contents.to_string());
+
+        let res = super::insert_flake_input(
+            &parsed.expression,
+            "zig".into(),
+            "github:mitchellh/zig-overlay".parse().unwrap(),
+            flake_contents.to_string(),
+            InputsInsertionLocation::Alphabetical,
+            None,
+        )
+        .unwrap();
+        assert!(res.contains(
+            r#"  inputs.nixpkgs.url = "github:NixOS/nixpkgs";
+  inputs.zig.url = "github:mitchellh/zig-overlay";
+"#
+        ));
+    }
+
+    #[test]
+    fn test_insert_into_empty_inputs() {
+        let flake_contents = r#"{
+  inputs = { };
+
+  outputs = { self, ... } @ inputs: { };
+}
+"#;
+        let parsed = nixel::parse(flake_contents.to_string());
+
+        let res = super::insert_flake_input(
+            &parsed.expression,
+            "nixpkgs".into(),
+            "https://flakehub.com/f/NixOS/nixpkgs/*.tar.gz"
+                .parse()
+                .unwrap(),
+            flake_contents.to_string(),
+            InputsInsertionLocation::Top,
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            res,
+            r#"{
+  inputs = {
+    nixpkgs.url = "https://flakehub.com/f/NixOS/nixpkgs/*.tar.gz";
+  };
+
+  outputs = { self, nixpkgs, ... } @ inputs: { };
+}
+"#
+        );
+    }
+
+    #[test]
+    fn test_sort_inputs() {
+        let flake_contents = r#"{
+  description = "My flake.";
+
+  inputs.nixpkgs.url = "github:NixOS/nixpkgs";
+  # Rust toolchains
+  inputs.fenix = {
+    url = "github:nix-community/fenix";
+    inputs.nixpkgs.follows = "nixpkgs";
+  };
+  inputs.crane.url = "github:ipetkov/crane"; # builds Rust
+  inputs = {
+    zig.url = "github:mitchellh/zig-overlay";
+    flake-utils.url = "github:numtide/flake-utils";
+  };
+
+  outputs = { self, ... } @ inputs: { };
+}
+"#;
+        let parsed = nixel::parse(flake_contents.to_string());
+
+        let res = super::sort_inputs(&parsed.expression, flake_contents).unwrap();
+        assert_eq!(
+            res,
+            r#"{
+  description = "My flake.";
+
+  inputs.crane.url = "github:ipetkov/crane"; # builds Rust
+  # Rust toolchains
+  inputs.fenix = {
+    url = "github:nix-community/fenix";
+    inputs.nixpkgs.follows = "nixpkgs";
+  };
+  inputs.nixpkgs.url = "github:NixOS/nixpkgs";
+  inputs = {
+    flake-utils.url = "github:numtide/flake-utils";
+    zig.url = "github:mitchellh/zig-overlay";
+  };
+
+  outputs = { self, ... } @ inputs: { };
+}
+"#
+        );
+    }
+
+    #[test]
+    fn test_sort_inputs_skips_shared_lines() {
+        let flake_contents = r#"{
+  inputs.nixpkgs.url = "github:NixOS/nixpkgs"; inputs.crane.url = "github:ipetkov/crane";
+
+  outputs = { self, ... } @ inputs: { };
+}
+"#;
+        let parsed = nixel::parse(flake_contents.to_string());
+
+        let res = super::sort_inputs(&parsed.expression, flake_contents).unwrap();
+        assert_eq!(res, flake_contents);
+    }
+
+    /// Stands in for a benchmark: every input used to rescan the whole flake to find its offsets,
+    /// which made sorting the inputs quadratic in the size of the flake. Run with
+    /// `cargo test -- --ignored`.
+    #[test]
+    #[ignore]
+    fn test_sort_inputs_of_large_flake() {
+        let inputs = (0..5000)
+            .rev()
+            .map(|i| format!("  inputs.input{i:04}.url = \"github:owner/repo{i}\";\n"))
+            .collect::<String>();
+        let flake_contents =
+            format!("{{\n{inputs}\n  outputs = {{ self, ... }} @ inputs: {{ }};\n}}\n");
+        let parsed = nixel::parse(flake_contents.clone());
+
+        let started = std::time::Instant::now();
+        let res = super::sort_inputs(&parsed.expression, &flake_contents).unwrap();
+        let elapsed = started.elapsed();
+
+        assert!(res.starts_with("{\n  inputs.input0000.url"), "{res}");
+        assert!(
+            elapsed < std::time::Duration::from_secs(1),
+            "sorting the inputs took {elapsed:?}"
+        );
+    }
+
+    #[test]
+    fn test_upsert_follows() {
+        let flake_contents = r#"{
+  inputs.nixpkgs.url = "https://flakehub.com/f/NixOS/nixpkgs/0.2305.*.tar.gz";
+  inputs.fh.url = "https://flakehub.com/f/DeterminateSystems/fh/*.tar.gz";
+  inputs = {
+    naersk = {
+      url = "github:nix-community/naersk";
+      inputs.nixpkgs.follows = "nixpkgs-old";
+    };
+    crane.url = "github:ipetkov/crane";
+  };
+  inputs.agenix = { url = "github:ryantm/agenix"; };
+
+  outputs = { self, ... } @ inputs: { };
+}
+"#;
+        let mut flake_contents = flake_contents.to_string();
+
+        for input in ["fh", "naersk", "crane", "agenix"] {
+            let parsed = nixel::parse(flake_contents.clone());
+            flake_contents = super::upsert_flake_input_follows(
+                &parsed.expression,
+                input,
+                "nixpkgs",
+                "nixpkgs",
+                flake_contents,
+            )
+            .unwrap();
+        }
+
+        assert_eq!(
+            flake_contents,
+            r#"{
+  inputs.nixpkgs.url = "https://flakehub.com/f/NixOS/nixpkgs/0.2305.*.tar.gz";
+  inputs.fh.url = "https://flakehub.com/f/DeterminateSystems/fh/*.tar.gz";
+  inputs.fh.inputs.nixpkgs.follows = "nixpkgs";
+  inputs = {
+    naersk = {
+      url = "github:nix-community/naersk";
+      inputs.nixpkgs.follows = "nixpkgs";
+    };
+    crane.url = "github:ipetkov/crane";
+    crane.inputs.nixpkgs.follows = "nixpkgs";
+  };
+  inputs.agenix = { url = "github:ryantm/agenix"; inputs.nixpkgs.follows = "nixpkgs"; };
+
+  outputs = { self, ... } @ inputs: { };
+}
+"#
+        );
+    }
+
+    #[test]
+    fn test_upsert_attr() {
+        let flake_contents = r#"{
+  inputs.nixpkgs.url = "https://flakehub.com/f/NixOS/nixpkgs/0.2305.*.tar.gz";
+  inputs = {
+    naersk = {
+      url = "github:nix-community/naersk";
+      flake = true;
+    };
+    crane.url = "github:ipetkov/crane";
+  };
+  inputs.agenix = { url = "github:ryantm/agenix"; };
+
+  outputs = { self, ... } @ inputs: { };
+}
+"#;
+        let mut flake_contents = flake_contents.to_string();
+
+        for input in ["nixpkgs", "naersk", "crane", "agenix"] {
+            let parsed = nixel::parse(flake_contents.clone());
+            flake_contents = super::upsert_flake_input_attr(
+                &parsed.expression,
+                input,
+                &["flake"],
+                "false",
+                flake_contents,
+            )
+            .unwrap();
+        }
+
+        assert_eq!(
+            flake_contents,
+            r#"{
+  inputs.nixpkgs.url = "https://flakehub.com/f/NixOS/nixpkgs/0.2305.*.tar.gz";
+  inputs.nixpkgs.flake = false;
+  inputs = {
+    naersk = {
+      url = "github:nix-community/naersk";
+      flake = false;
+    };
+    crane.url = "github:ipetkov/crane";
+    crane.flake = false;
+  };
+  inputs.agenix = { url = "github:ryantm/agenix"; flake = false; };
+
+  outputs = { self, ... } @ inputs: { };
+}
+"#
+        );
+
+        let parsed = nixel::parse(flake_contents.clone());
+        let err = super::upsert_flake_input_attr(
+            &parsed.expression,
+            "missing",
+            &["flake"],
+            "false",
+            flake_contents,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("no `inputs.missing.url`"), "{err}");
+    }
+
+    #[test]
+    fn test_skips_unrelated_inherit() {
+        let flake_contents = r#"{
+  inputs = {
+    inherit (sources) nixpkgs;
+    fh.url = "github:DeterminateSystems/fh";
+  };
+
+  outputs = { self, ... } @ inputs: { };
+}
+"#;
+        let flake_contents = flake_contents.to_string();
+        let input_value =
+            url::Url::parse("https://flakehub.com/f/DeterminateSystems/fh/*.tar.gz").unwrap();
+        let parsed = nixel::parse(flake_contents.clone());
+
+        let res = super::upsert_flake_input(
+            &parsed.expression,
+            String::from("fh"),
+            input_value.clone(),
+            flake_contents.clone(),
+            ["inputs", "fh", "url"].map(ToString::to_string).into(),
+            InputsInsertionLocation::Top,
+            None,
+        );
+        assert!(res.is_ok());
+        assert!(res.unwrap().contains(input_value.as_str()));
+
+        let res = super::upsert_flake_input(
+            &parsed.expression,
+            String::from("nixpkgs"),
+            input_value,
+            flake_contents,
+            ["inputs", "nixpkgs", "url"].map(ToString::to_string).into(),
+            InputsInsertionLocation::Top,
+            None,
+        );
+        assert!(res.is_err(), "modifying an inherited input should fail");
+    }
 }
@@ -1,14 +1,17 @@
 // FIXME: extract to somewhere else so it's more convenient
 pub(crate) mod flake;
 
-use std::collections::VecDeque;
-use std::path::PathBuf;
-use std::process::ExitCode;
+use std::collections::{HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::process::{ExitCode, Stdio};
+use std::sync::Mutex;
 
 use clap::Parser;
 use color_eyre::eyre::WrapErr;
+use once_cell::sync::Lazy;
 use reqwest::header::{HeaderValue, ACCEPT, AUTHORIZATION};
 use serde::Deserialize;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
 
 use self::flake::InputsInsertionLocation;
 
@@ -23,232 +26,2667 @@ const FALLBACK_FLAKE_CONTENTS: &str = r#"{
 
 /// Adds a flake input to your flake.nix.
 #[derive(Parser, Debug)]
+#[command(mut_arg("flake_path", |arg| arg.help("The flake.nix to modify, or `-` to read it from stdin and write the result to stdout")))]
 pub(crate) struct AddSubcommand {
-    /// The flake.nix to modify.
-    #[clap(long, default_value = "./flake.nix")]
-    pub(crate) flake_path: PathBuf,
+    #[clap(flatten)]
+    pub(crate) flake: crate::cli::cmd::FlakePathArgs,
     /// The name of the flake input.
     ///
-    /// If not provided, it will be inferred from the provided input URL (if possible).
+    /// If not provided, it will be inferred from the provided input URL (if possible). May only be
+    /// used when adding a single input.
     #[clap(long)]
     pub(crate) input_name: Option<String>,
-    /// The flake reference to add as an input.
+    /// The dotted attribute path of an existing input's URL to update, e.g. `inputs.foo.url`,
+    /// instead of `inputs.<input name>.url`. May only be used when adding a single input.
+    #[clap(long, value_parser = parse_attr_path)]
+    pub(crate) attr_path: Option<VecDeque<String>>,
+    /// Pin the new `github:`, `gitlab:`, or `sourcehut:` input(s) to this commit (a full 40
+    /// character hash, ideally).
+    #[clap(long, value_parser = parse_rev)]
+    pub(crate) rev: Option<String>,
+    /// The flake reference(s) to add as inputs.
     ///
     /// A reference in the form of `NixOS/nixpkgs` or `NixOS/nixpkgs/0.2305.*` (without a URL
-    /// scheme) will be inferred as a FlakeHub input.
-    pub(crate) input_ref: String,
-    /// Whether to insert a new input at the top of or the bottom of an existing `inputs` attrset.
-    #[clap(long, default_value_t = InputsInsertionLocation::Top)]
+    /// scheme) will be inferred as a FlakeHub input, and a local directory like `./libs/mylib` as a
+    /// `path:` input. A reference like `nixpkgs` is only accepted with `--default-org`.
+    #[clap(required = true)]
+    pub(crate) input_ref: Vec<String>,
+    /// How to interpret the flake reference(s): `flakehub` infers FlakeHub inputs from references
+    /// like `NixOS/nixpkgs`, `github` turns them into `github:NixOS/nixpkgs`, and `raw` uses them
+    /// verbatim as the input URL.
+    #[clap(long, default_value_t = RefType::FlakeHub)]
+    pub(crate) ref_type: RefType,
+    /// Whether to insert a new input at the top of or the bottom of an existing `inputs` attrset, or
+    /// in alphabetical order among the existing inputs.
+    #[clap(long, visible_alias = "position", default_value_t = InputsInsertionLocation::Top)]
     pub(crate) insertion_location: InputsInsertionLocation,
+    /// A comment to write above the new input, e.g. why it was added. Each line of the text becomes
+    /// a `#` comment line. Not written when updating an existing input.
+    #[clap(long)]
+    pub(crate) comment: Option<String>,
+    /// Make one of the new input's own inputs follow one of your inputs, in the form of
+    /// `<child>=<parent>` (e.g. `nixpkgs=nixpkgs`). May be specified multiple times.
+    #[clap(long = "follows", visible_alias = "input-follows", value_parser = parse_follows)]
+    pub(crate) follows: Vec<(String, String)>,
+    /// Set another attribute of the new input, in the form of `<attr>=<value>` (e.g. `flake=false`
+    /// or `type=tarball`). `true`, `false`, and numbers are written as they are, anything else as a
+    /// string. May be specified multiple times.
+    #[clap(long = "set", value_parser = parse_set)]
+    pub(crate) set_attrs: Vec<(String, String)>,
+    /// Sort all of the flake's inputs alphabetically after adding the new one(s).
+    #[clap(long)]
+    pub(crate) sort: bool,
     /// Print to stdout the new flake.nix contents instead of writing it to disk.
     #[clap(long)]
     pub(crate) dry_run: bool,
+    /// Don't run `nix flake lock` after writing the new flake.nix contents.
+    #[clap(long)]
+    pub(crate) no_lock: bool,
+    /// Only print the name and URL each reference resolves to (as JSON), without reading or
+    /// modifying any flake.nix.
+    #[clap(long)]
+    pub(crate) print_name: bool,
+    /// Print a JSON description of what happened to each input instead: its name, its URL, whether
+    /// it was inserted or updated, and the byte range of the flake.nix that changed.
+    #[clap(long, conflicts_with_all = ["print_name", "print_changed", "sort"])]
+    pub(crate) json: bool,
+    /// Print the absolute path of each file that was modified (one per line), e.g. for passing to
+    /// `git add`. With `--dry-run`, print the files that would be modified instead of the new
+    /// flake.nix contents.
+    #[clap(long)]
+    pub(crate) print_changed: bool,
+    /// Save the original flake.nix to `flake.nix.bak` before overwriting it.
+    #[clap(long)]
+    pub(crate) backup: bool,
+    /// What to do when the backup file already exists: `overwrite` it, or pick the next free
+    /// `numbered` name (`flake.nix.bak.1`, `flake.nix.bak.2`, ...).
+    #[clap(long, default_value_t = BackupMode::Overwrite)]
+    pub(crate) backup_mode: BackupMode,
+    /// What to do when an input with the same name already points somewhere else: `update` its
+    /// URL, fail with an `error`, or `skip` it and leave it as it is.
+    #[clap(long, default_value_t = OnExists::Update)]
+    pub(crate) on_exists: OnExists,
+    /// What to do when the inferred input name is already taken by an input pointing somewhere
+    /// else: `overwrite` it (subject to `--on-exists`), fail with an `error`, or add the new input
+    /// under a `suffix`ed name like `foo-2`. Doesn't apply with `--input-name` or `--attr-path`.
+    #[clap(long, default_value_t = OnNameCollision::Overwrite)]
+    pub(crate) on_name_collision: OnNameCollision,
+    /// Leave the `.tar.gz` suffix off of FlakeHub URLs, e.g.
+    /// `https://flakehub.com/f/NixOS/nixpkgs/0.2305.*`. Otherwise, it's only left off if the
+    /// installed Nix supports such URLs.
+    #[clap(long)]
+    pub(crate) bare_urls: bool,
+    /// The archive format of the FlakeHub URLs: `tar.gz` or `zip`. Only tarball URLs have their
+    /// suffix left off automatically.
+    #[clap(long, default_value_t = ArchiveFormat::TarGz, conflicts_with = "bare_urls")]
+    pub(crate) archive_format: ArchiveFormat,
+    /// The FlakeHub organization of references that only name a project, e.g. with
+    /// `--default-org myorg`, `myflake` is added as `myorg/myflake`.
+    #[clap(long)]
+    pub(crate) default_org: Option<String>,
 
     #[clap(from_global)]
     api_addr: url::Url,
+
+    #[clap(from_global)]
+    token: Option<String>,
 }
 
 #[async_trait::async_trait]
 impl CommandExecute for AddSubcommand {
     async fn execute(self) -> color_eyre::Result<ExitCode> {
-        let (flake_contents, parsed) = load_flake(&self.flake_path).await?;
+        if self.input_name.is_some() && self.input_ref.len() > 1 {
+            return Err(color_eyre::eyre::eyre!(
+                "`--input-name` can only be used when adding a single input"
+            ));
+        }
+        if self.attr_path.is_some() && self.input_ref.len() > 1 {
+            return Err(color_eyre::eyre::eyre!(
+                "`--attr-path` can only be used when adding a single input"
+            ));
+        }
 
-        let (flake_input_name, flake_input_url) =
-            infer_flake_input_name_url(self.api_addr, self.input_ref, self.input_name).await?;
-        let input_url_attr_path: VecDeque<String> = [
-            String::from("inputs"),
-            flake_input_name.clone(),
-            String::from("url"),
-        ]
-        .into();
+        let strip_tar_gz = self.bare_urls
+            || (self.archive_format == ArchiveFormat::TarGz
+                && nix_supports_bare_flakehub_urls().await);
 
-        let new_flake_contents = flake::upsert_flake_input(
-            &parsed.expression,
-            flake_input_name,
-            flake_input_url,
-            flake_contents,
-            input_url_attr_path,
-            self.insertion_location,
-        )?;
+        if self.print_name {
+            super::print_json(self.resolve_inputs(strip_tar_gz).await?)?;
+            return Ok(ExitCode::SUCCESS);
+        }
 
-        if self.dry_run {
-            println!("{new_flake_contents}");
-        } else {
-            tokio::fs::write(self.flake_path, new_flake_contents).await?;
+        let (flake_contents, _) = load_flake(&self.flake.path).await?;
+        let (new_flake_contents, added) = self.add_inputs(&flake_contents, strip_tar_gz).await?;
+
+        if self.json {
+            super::print_json(&added)?;
+        }
+
+        if flake_path_is_stdio(&self.flake.path) {
+            if !self.json {
+                println!("{new_flake_contents}");
+            }
+            return Ok(ExitCode::SUCCESS);
+        }
+
+        let changed = self
+            .write_flake(&flake_contents, new_flake_contents)
+            .await?;
+        if self.print_changed {
+            print_changed_paths(&changed)?;
         }
 
         Ok(ExitCode::SUCCESS)
     }
 }
 
-#[tracing::instrument(skip_all)]
-// FIXME: make a nix or nix_util module or something
-pub(crate) async fn load_flake(
-    flake_path: &PathBuf,
-) -> color_eyre::Result<(String, nixel::Parsed)> {
-    let mut contents = tokio::fs::read_to_string(&flake_path)
-        .await
-        .or_else(|e| {
-            if e.kind() == std::io::ErrorKind::NotFound {
-                Ok(FALLBACK_FLAKE_CONTENTS.to_string())
-            } else {
-                Err(e)
+/// What a flake reference passed to `fh add` resolves to, for `--print-name`.
+#[derive(Debug, serde::Serialize)]
+struct ResolvedInput {
+    input_ref: String,
+    name: String,
+    url: url::Url,
+}
+
+impl AddSubcommand {
+    /// Writes the new flake.nix and locks it (or, with `--dry-run`, prints it instead). Returns the
+    /// files that changed, or would have.
+    async fn write_flake(
+        &self,
+        flake_contents: &str,
+        new_flake_contents: String,
+    ) -> color_eyre::Result<Vec<PathBuf>> {
+        let mut changed = Vec::new();
+        if new_flake_contents != flake_contents {
+            changed.push(self.flake.path.clone());
+        }
+
+        if self.dry_run {
+            if !self.print_changed && !self.json {
+                println!("{new_flake_contents}");
             }
-        })
-        .wrap_err_with(|| format!("Failed to open {}", flake_path.display()))?;
+            return Ok(changed);
+        }
 
-    if contents.trim().is_empty() {
-        contents = FALLBACK_FLAKE_CONTENTS.to_string();
-    };
+        if self.backup {
+            backup_flake(&self.flake.path, flake_contents, self.backup_mode).await?;
+        }
+        tokio::fs::write(&self.flake.path, new_flake_contents).await?;
 
-    let mut parsed = nixel::parse(contents.clone());
+        if !self.no_lock {
+            changed
+                .extend(changed_flake_lock(&self.flake.path, lock_flake(&self.flake.path)).await?);
+        }
 
-    if let nixel::Expression::Map(map) = *parsed.expression.clone() {
-        if map.bindings.is_empty() {
-            contents = FALLBACK_FLAKE_CONTENTS.to_string();
-            parsed = nixel::parse(FALLBACK_FLAKE_CONTENTS.to_string());
+        Ok(changed)
+    }
+
+    async fn resolve_inputs(&self, strip_tar_gz: bool) -> color_eyre::Result<Vec<ResolvedInput>> {
+        let mut resolved = Vec::new();
+        for input_ref in self.input_ref.iter() {
+            let (name, url) = self
+                .resolve_input(input_ref, strip_tar_gz)
+                .await
+                .wrap_err_with(|| format!("Failed to resolve `{input_ref}`"))?;
+
+            resolved.push(ResolvedInput {
+                input_ref: input_ref.clone(),
+                name,
+                url,
+            });
         }
+
+        Ok(resolved)
     }
 
-    Ok((contents, parsed))
-}
+    /// Returns the new flake contents, and what happened to each input (in order).
+    async fn add_inputs(
+        &self,
+        flake_contents: &str,
+        strip_tar_gz: bool,
+    ) -> color_eyre::Result<(String, Vec<AddedInput>)> {
+        let mut new_flake_contents = flake_contents.to_string();
+        let mut added = Vec::new();
 
-#[tracing::instrument(skip_all)]
-async fn infer_flake_input_name_url(
-    api_addr: url::Url,
-    flake_ref: String,
-    input_name: Option<String>,
-) -> color_eyre::Result<(String, url::Url)> {
-    let flake_ref = flake_ref.trim_end_matches('/');
-    let url_result = flake_ref.parse::<url::Url>();
+        // Thread the contents through every input, and only write them out once all of them
+        // succeeded, so that a bad ref doesn't leave a half-modified flake.nix behind.
+        for input_ref in self.input_ref.iter() {
+            let (contents, mut added_input) = self
+                .add_input(input_ref, new_flake_contents.clone(), strip_tar_gz)
+                .await
+                .map_err(|err| flake::with_source_context(err, &new_flake_contents))
+                .wrap_err_with(|| format!("Failed to add `{input_ref}`"))?;
+            let contents = match_trailing_newline(flake_contents, contents);
 
-    match url_result {
-        // A URL like `github:nixos/nixpkgs`
-        Ok(parsed_url) if parsed_url.host().is_none() => {
-            // TODO: validate that the format of all Nix-supported schemes allows us to do this;
-            // else, have an allowlist of schemes
-            let mut path_parts = parsed_url.path().split('/');
-            path_parts.next(); // e.g. in `fh:` or `github:`, the org name
+            if let Some((replaced_range, range)) = changed_ranges(&new_flake_contents, &contents) {
+                added_input.text = Some(contents[range.clone()].to_string());
+                added_input.replaced_range = Some(replaced_range);
+                added_input.range = Some(range);
+            }
+            added.push(added_input);
+            new_flake_contents = contents;
+        }
 
-            match (input_name, path_parts.next()) {
-                (Some(input_name), _) => Ok((input_name, parsed_url)),
-                (None, Some(input_name)) => Ok((input_name.to_string(), parsed_url)),
-                (None, _) =>  Err(color_eyre::eyre::eyre!(
-                    "cannot infer an input name for {parsed_url}; please specify one with the `--input-name` flag"
-                ))
+        if self.sort {
+            let parsed = nixel::parse(new_flake_contents.clone());
+            new_flake_contents = flake::sort_inputs(&parsed.expression, &new_flake_contents)?;
+        }
+
+        Ok((
+            match_trailing_newline(flake_contents, new_flake_contents),
+            added,
+        ))
+    }
+
+    /// The name and URL of the input a flake reference resolves to, pinned to `--rev` (if given).
+    async fn resolve_input(
+        &self,
+        input_ref: &str,
+        strip_tar_gz: bool,
+    ) -> color_eyre::Result<(String, url::Url)> {
+        let (name, url) = infer_flake_input_name_url(
+            self.api_addr.clone(),
+            input_ref.to_string(),
+            self.input_name.clone(),
+            self.ref_type,
+            &LookupOptions {
+                strip_tar_gz,
+                archive_format: self.archive_format,
+                token: self.token.as_deref(),
+                default_org: self.default_org.as_deref(),
+            },
+        )
+        .await?;
+
+        let url = match &self.rev {
+            Some(rev) => pin_rev(url, rev)?,
+            None => url,
+        };
+
+        Ok((name, url))
+    }
+
+    #[tracing::instrument(skip_all, fields(input_ref))]
+    async fn add_input(
+        &self,
+        input_ref: &str,
+        flake_contents: String,
+        strip_tar_gz: bool,
+    ) -> color_eyre::Result<(String, AddedInput)> {
+        let (flake_input_name, flake_input_url) =
+            self.resolve_input(input_ref, strip_tar_gz).await?;
+
+        // Re-parse the contents since a previous input may have modified them, and that would
+        // screw up offset calculations.
+        let parsed = nixel::parse(flake_contents.clone());
+
+        let flake_input_name = if self.input_name.is_none()
+            && self.attr_path.is_none()
+            && self.on_name_collision != OnNameCollision::Overwrite
+        {
+            self.avoid_name_collision(
+                &parsed.expression,
+                flake_input_name,
+                &flake_input_url,
+                &flake_contents,
+            )?
+        } else {
+            flake_input_name
+        };
+
+        let input_url_attr_path: VecDeque<String> = match &self.attr_path {
+            Some(attr_path) => attr_path.clone(),
+            None => [
+                String::from("inputs"),
+                flake_input_name.clone(),
+                String::from("url"),
+            ]
+            .into(),
+        };
+
+        // An explicit attr path has to point at an existing input, since a new input is always
+        // inserted as `inputs.<input name>.url`
+        if let Some(attr_path) = &self.attr_path {
+            if flake::find_first_attrset_by_path(&parsed.expression, Some(attr_path.clone()))?
+                .is_none()
+            {
+                return Err(color_eyre::eyre::eyre!(
+                    "there was no `{}` attribute to update",
+                    Vec::from(attr_path.clone()).join(".")
+                ));
             }
         }
-        // A URL like `nixos/nixpkgs` or `nixos/nixpkgs/0.2305`
-        Err(url::ParseError::RelativeUrlWithoutBase) => {
-            let (org, project, version) = match flake_ref.split('/').collect::<Vec<_>>()[..] {
-                // `nixos/nixpkgs/0.2305`
-                [org, project, version] => {
-                    let version = version.strip_suffix(".tar.gz").unwrap_or(version);
-                    let version = version.strip_prefix('v').unwrap_or(version);
-                    semver::VersionReq::parse(version).map_err(|_| {
-                        color_eyre::eyre::eyre!(
-                            "version '{version}' was not a valid SemVer version requirement"
-                        )
-                    })?;
 
-                    (org, project, Some(version))
+        if self.on_exists != OnExists::Update
+            && points_elsewhere(
+                &parsed.expression,
+                input_url_attr_path.clone(),
+                &flake_input_name,
+                &flake_input_url,
+                &flake_contents,
+            )?
+        {
+            match self.on_exists {
+                OnExists::Error => {
+                    return Err(color_eyre::eyre::eyre!(
+                        "the input `{flake_input_name}` already exists with a different URL; pass `--on-exists update` to overwrite it"
+                    ));
                 }
-                // `nixos/nixpkgs`
-                [org, project] => (org, project, None),
-                _ => Err(color_eyre::eyre::eyre!(
-                    "flakehub input did not match the expected format of \
-                    `org/project` or `org/project/version`"
-                ))?,
-            };
+                OnExists::Skip => {
+                    tracing::warn!(
+                        "the input `{flake_input_name}` already exists with a different URL; skipping it"
+                    );
+                    return Ok((
+                        flake_contents,
+                        AddedInput::new(
+                            input_ref,
+                            flake_input_name,
+                            flake_input_url,
+                            AddAction::Skipped,
+                        ),
+                    ));
+                }
+                OnExists::Update => unreachable!(),
+            }
+        }
+
+        let action = if flake::find_first_attrset_by_path(
+            &parsed.expression,
+            Some(input_url_attr_path.clone()),
+        )?
+        .is_some()
+        {
+            AddAction::Update
+        } else {
+            AddAction::Insert
+        };
 
-            let (flakehub_input, url) =
-                get_flakehub_project_and_url(&api_addr, org, project, version).await?;
+        let mut new_flake_contents = flake::upsert_flake_input(
+            &parsed.expression,
+            flake_input_name.clone(),
+            flake_input_url.clone(),
+            flake_contents.clone(),
+            input_url_attr_path,
+            self.insertion_location,
+            self.comment.as_deref(),
+        )?;
 
-            if let Some(input_name) = input_name {
-                Ok((input_name, url))
-            } else {
-                Ok((flakehub_input, url))
-            }
+        for (child, parent) in self.follows.iter() {
+            let parsed = nixel::parse(new_flake_contents.clone());
+            new_flake_contents = flake::upsert_flake_input_follows(
+                &parsed.expression,
+                &flake_input_name,
+                child,
+                parent,
+                new_flake_contents,
+            )?;
         }
-        // A URL like `https://flakehub.com/f/NixOS/nixpkgs/*.tar.gz`
-        Ok(parsed_url) => {
-            if let Some(input_name) = input_name {
-                Ok((input_name, parsed_url))
-            } else {
-                Err(color_eyre::eyre::eyre!(
-                    "cannot infer an input name for `{flake_ref}`; please specify one with the `--input-name` flag"
-                ))?
+
+        // Each attribute goes right below the `url`, so insert them back to front to keep them in
+        // the order they were given in
+        for (attr, value) in self.set_attrs.iter().rev() {
+            let parsed = nixel::parse(new_flake_contents.clone());
+            let attr_path: Vec<&str> = attr.split('.').collect();
+            new_flake_contents = flake::upsert_flake_input_attr(
+                &parsed.expression,
+                &flake_input_name,
+                &attr_path,
+                value,
+                new_flake_contents,
+            )?;
+        }
+
+        let action = if new_flake_contents == flake_contents {
+            AddAction::Unchanged
+        } else {
+            action
+        };
+
+        Ok((
+            new_flake_contents,
+            AddedInput::new(input_ref, flake_input_name, flake_input_url, action),
+        ))
+    }
+
+    /// The name to add the input under, according to `--on-name-collision`, if an input with the
+    /// inferred name already points somewhere else.
+    fn avoid_name_collision(
+        &self,
+        expr: &nixel::Expression,
+        flake_input_name: String,
+        flake_input_url: &url::Url,
+        flake_contents: &str,
+    ) -> color_eyre::Result<String> {
+        let collides = |name: &str| {
+            points_elsewhere(
+                expr,
+                ["inputs".into(), name.to_string(), "url".into()].into(),
+                name,
+                flake_input_url,
+                flake_contents,
+            )
+        };
+
+        if !collides(&flake_input_name)? {
+            return Ok(flake_input_name);
+        }
+
+        match self.on_name_collision {
+            OnNameCollision::Error => Err(color_eyre::eyre::eyre!(
+                "the input `{flake_input_name}` already exists with a different URL; pass `--input-name` to pick another name, or `--on-name-collision suffix` to pick one automatically"
+            )),
+            OnNameCollision::Suffix => {
+                for n in 2.. {
+                    let suffixed = format!("{flake_input_name}-{n}");
+                    if !collides(&suffixed)? {
+                        tracing::warn!(
+                            "the input `{flake_input_name}` already exists with a different URL; adding this one as `{suffixed}`"
+                        );
+                        return Ok(suffixed);
+                    }
+                }
+                unreachable!()
             }
+            OnNameCollision::Overwrite => Ok(flake_input_name),
         }
-        Err(e) => Err(e)?,
     }
 }
 
-#[tracing::instrument(skip_all)]
-pub(crate) async fn get_flakehub_project_and_url(
-    api_addr: &url::Url,
-    org: &str,
-    project: &str,
-    version: Option<&str>,
-) -> color_eyre::Result<(String, url::Url)> {
-    let mut headers = reqwest::header::HeaderMap::new();
-    headers.insert(ACCEPT, HeaderValue::from_static("application/json"));
+/// What `fh add --json` reports about an input. The ranges are byte offsets into the flake.nix as it
+/// was before and after this input was added (after the previous input, if several were added).
+#[derive(Debug, serde::Serialize)]
+struct AddedInput {
+    input_ref: String,
+    name: String,
+    url: url::Url,
+    action: AddAction,
+    /// The part of the flake.nix that was replaced...
+    replaced_range: Option<std::ops::Range<usize>>,
+    /// ...and where its replacement ended up.
+    range: Option<std::ops::Range<usize>>,
+    text: Option<String>,
+}
 
-    let xdg = xdg::BaseDirectories::new()?;
-    // $XDG_CONFIG_HOME/fh/auth; basically ~/.config/fh/auth
-    let token_path = xdg.get_config_file("flakehub/auth");
+impl AddedInput {
+    fn new(input_ref: &str, name: String, url: url::Url, action: AddAction) -> Self {
+        Self {
+            input_ref: input_ref.to_string(),
+            name,
+            url,
+            action,
+            replaced_range: None,
+            range: None,
+            text: None,
+        }
+    }
+}
 
-    if token_path.exists() {
-        let token = tokio::fs::read_to_string(&token_path)
-            .await
-            .wrap_err_with(|| format!("Could not open {}", token_path.display()))?;
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+enum AddAction {
+    /// A new input was inserted.
+    Insert,
+    /// An existing input was pointed at the new URL.
+    Update,
+    /// The input already pointed at the URL.
+    Unchanged,
+    /// The input pointed somewhere else and was left alone (`--on-exists skip`).
+    Skipped,
+}
 
-        headers.insert(
-            AUTHORIZATION,
-            HeaderValue::from_str(&format!("Bearer {token}"))?,
-        );
+/// The smallest range of `old` that has to be replaced to get `new`, along with the range of its
+/// replacement in `new` (if they differ at all).
+fn changed_ranges(
+    old: &str,
+    new: &str,
+) -> Option<(std::ops::Range<usize>, std::ops::Range<usize>)> {
+    if old == new {
+        return None;
     }
 
-    let client = reqwest::Client::builder()
-        .user_agent(crate::APP_USER_AGENT)
-        .default_headers(headers)
-        .build()?;
+    let mut prefix = old
+        .bytes()
+        .zip(new.bytes())
+        .take_while(|(a, b)| a == b)
+        .count();
+    while !old.is_char_boundary(prefix) || !new.is_char_boundary(prefix) {
+        prefix -= 1;
+    }
 
-    let mut flakehub_json_url = api_addr.clone();
-    {
-        let mut path_segments_mut = flakehub_json_url
-            .path_segments_mut()
-            .expect("flakehub url cannot be base (this should never happen)");
+    let mut suffix = old[prefix..]
+        .bytes()
+        .rev()
+        .zip(new[prefix..].bytes().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    while !old.is_char_boundary(old.len() - suffix) || !new.is_char_boundary(new.len() - suffix) {
+        suffix -= 1;
+    }
 
-        match version {
-            Some(version) => {
-                path_segments_mut
-                    .push("version")
-                    .push(org)
-                    .push(project)
-                    .push(version);
+    Some((prefix..old.len() - suffix, prefix..new.len() - suffix))
+}
+
+/// Whether the input at `input_url_attr_path` exists and has a URL other than `flake_input_url`.
+fn points_elsewhere(
+    expr: &nixel::Expression,
+    input_url_attr_path: VecDeque<String>,
+    flake_input_name: &str,
+    flake_input_url: &url::Url,
+    flake_contents: &str,
+) -> color_eyre::Result<bool> {
+    let Some(attr) = flake::find_first_attrset_by_path(expr, Some(input_url_attr_path))? else {
+        return Ok(false);
+    };
+
+    // Updating an input to the URL it already has leaves the contents untouched
+    let updated_flake_contents = flake::update_flake_input(
+        attr,
+        flake_input_name.to_string(),
+        flake_input_url.clone(),
+        flake_contents.to_string(),
+    )?;
+
+    Ok(updated_flake_contents != flake_contents)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum RefType {
+    /// References like `NixOS/nixpkgs` are looked up on FlakeHub; anything else is used as a URL.
+    FlakeHub,
+    /// References like `NixOS/nixpkgs` are turned into `github:NixOS/nixpkgs`.
+    GitHub,
+    /// References are used verbatim as the input URL.
+    Raw,
+}
+
+impl std::fmt::Display for RefType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RefType::FlakeHub => f.write_str("flakehub"),
+            RefType::GitHub => f.write_str("github"),
+            RefType::Raw => f.write_str("raw"),
+        }
+    }
+}
+
+impl std::str::FromStr for RefType {
+    type Err = color_eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "flakehub" => RefType::FlakeHub,
+            "github" => RefType::GitHub,
+            "raw" => RefType::Raw,
+            _ => {
+                return Err(color_eyre::eyre::eyre!(
+                    "only `flakehub`, `github`, and `raw` are valid ref types"
+                ))
             }
-            None => {
-                path_segments_mut.push("f").push(org).push(project);
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub(crate) enum ArchiveFormat {
+    #[default]
+    TarGz,
+    Zip,
+}
+
+impl ArchiveFormat {
+    const ALL: [ArchiveFormat; 2] = [ArchiveFormat::TarGz, ArchiveFormat::Zip];
+
+    /// The suffix of a FlakeHub URL of an archive in this format, e.g. `.tar.gz`.
+    pub(crate) fn suffix(self) -> &'static str {
+        match self {
+            ArchiveFormat::TarGz => ".tar.gz",
+            ArchiveFormat::Zip => ".zip",
+        }
+    }
+
+    /// The format of the archive that `version` (the last segment of a FlakeHub URL) ends in, e.g.
+    /// `Zip` for `0.1.0.zip`. Versions without a suffix are tarballs.
+    pub(crate) fn of(version: &str) -> Self {
+        Self::ALL
+            .into_iter()
+            .find(|format| version.ends_with(format.suffix()))
+            .unwrap_or_default()
+    }
+
+    /// Removes the suffix of any format from `version`, e.g. `0.1.0.zip` becomes `0.1.0`.
+    pub(crate) fn strip_suffix(version: &str) -> &str {
+        Self::ALL
+            .iter()
+            .find_map(|format| version.strip_suffix(format.suffix()))
+            .unwrap_or(version)
+    }
+}
+
+impl std::fmt::Display for ArchiveFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.suffix()[1..])
+    }
+}
+
+impl std::str::FromStr for ArchiveFormat {
+    type Err = color_eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|format| format.to_string() == s)
+            .ok_or_else(|| {
+                color_eyre::eyre::eyre!("only `tar.gz` and `zip` are valid archive formats")
+            })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum BackupMode {
+    /// Replace an existing backup.
+    Overwrite,
+    /// Keep existing backups and pick the next free numbered name.
+    Numbered,
+}
+
+impl std::fmt::Display for BackupMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BackupMode::Overwrite => f.write_str("overwrite"),
+            BackupMode::Numbered => f.write_str("numbered"),
+        }
+    }
+}
+
+impl std::str::FromStr for BackupMode {
+    type Err = color_eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "overwrite" => BackupMode::Overwrite,
+            "numbered" => BackupMode::Numbered,
+            _ => {
+                return Err(color_eyre::eyre::eyre!(
+                    "only `overwrite` and `numbered` are valid backup modes"
+                ))
             }
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum OnExists {
+    /// Point the existing input at the new URL.
+    Update,
+    /// Fail without modifying the flake.
+    Error,
+    /// Leave the existing input as it is.
+    Skip,
+}
+
+impl std::fmt::Display for OnExists {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OnExists::Update => f.write_str("update"),
+            OnExists::Error => f.write_str("error"),
+            OnExists::Skip => f.write_str("skip"),
         }
     }
+}
 
-    #[derive(Debug, Deserialize)]
-    struct ProjectCanonicalNames {
-        project: String,
-        // FIXME: detect Nix version and strip .tar.gz if it supports it
-        pretty_download_url: url::Url,
+impl std::str::FromStr for OnExists {
+    type Err = color_eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "update" => OnExists::Update,
+            "error" => OnExists::Error,
+            "skip" => OnExists::Skip,
+            _ => {
+                return Err(color_eyre::eyre::eyre!(
+                    "only `update`, `error`, and `skip` are valid policies"
+                ))
+            }
+        })
     }
+}
 
-    let res = client.get(&flakehub_json_url.to_string()).send().await?;
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum OnNameCollision {
+    /// Use the inferred name anyway, leaving it to `--on-exists`.
+    Overwrite,
+    /// Fail without modifying the flake.
+    Error,
+    /// Add the input as `<name>-2` (or `-3`, ...) instead.
+    Suffix,
+}
 
-    if let Err(e) = res.error_for_status_ref() {
-        let err_text = res.text().await?;
-        return Err(e).wrap_err(err_text)?;
-    };
+impl std::fmt::Display for OnNameCollision {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OnNameCollision::Overwrite => f.write_str("overwrite"),
+            OnNameCollision::Error => f.write_str("error"),
+            OnNameCollision::Suffix => f.write_str("suffix"),
+        }
+    }
+}
 
-    let res = res.json::<ProjectCanonicalNames>().await?;
+impl std::str::FromStr for OnNameCollision {
+    type Err = color_eyre::Report;
 
-    Ok((res.project, res.pretty_download_url))
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "overwrite" => OnNameCollision::Overwrite,
+            "error" => OnNameCollision::Error,
+            "suffix" => OnNameCollision::Suffix,
+            _ => {
+                return Err(color_eyre::eyre::eyre!(
+                    "only `overwrite`, `error`, and `suffix` are valid policies"
+                ))
+            }
+        })
+    }
+}
+
+/// Writes the original contents of the flake next to it (e.g. `flake.nix.bak`) so that they
+/// survive the flake being overwritten. Returns the path of the backup.
+pub(crate) async fn backup_flake(
+    flake_path: &Path,
+    contents: &str,
+    mode: BackupMode,
+) -> color_eyre::Result<PathBuf> {
+    let mut backup_path = flake_path.as_os_str().to_owned();
+    backup_path.push(".bak");
+    let backup_path = PathBuf::from(backup_path);
+
+    let mut candidate = backup_path.clone();
+    let mut n = 0;
+    loop {
+        let mut options = tokio::fs::OpenOptions::new();
+        options.write(true);
+        match mode {
+            BackupMode::Overwrite => options.create(true).truncate(true),
+            BackupMode::Numbered => options.create_new(true),
+        };
+
+        match options.open(&candidate).await {
+            Ok(mut file) => {
+                file.write_all(contents.as_bytes()).await?;
+                tracing::debug!(
+                    "backed up {} to {}",
+                    flake_path.display(),
+                    candidate.display()
+                );
+                return Ok(candidate);
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                n += 1;
+                let mut next = backup_path.clone().into_os_string();
+                next.push(format!(".{n}"));
+                candidate = PathBuf::from(next);
+            }
+            Err(e) => {
+                return Err(e)
+                    .wrap_err_with(|| format!("Failed to back up {}", flake_path.display()))
+            }
+        }
+    }
+}
+
+fn parse_follows(follows: &str) -> Result<(String, String), String> {
+    match follows.split_once('=') {
+        Some((child, parent)) if !child.is_empty() && !parent.is_empty() => {
+            Ok((child.to_string(), parent.to_string()))
+        }
+        _ => Err(format!(
+            "`{follows}` was not of the form `<child>=<parent>` (e.g. `nixpkgs=nixpkgs`)"
+        )),
+    }
+}
+
+/// Parses `--set <attr>=<value>` into the attribute and its value as Nix code.
+fn parse_set(set: &str) -> Result<(String, String), String> {
+    let Some((attr, value)) = set.split_once('=') else {
+        return Err(format!(
+            "`{set}` was not of the form `<attr>=<value>` (e.g. `flake=false`)"
+        ));
+    };
+    // The attribute is written into the flake as it is, so anything but plain identifiers would make
+    // for invalid Nix
+    if !attr.split('.').all(is_valid_nix_identifier) {
+        return Err(format!("`{attr}` is not a valid attribute name"));
+    }
+    if attr == "url" {
+        return Err(String::from(
+            "the `url` is set from the flake reference, not with `--set`",
+        ));
+    }
+
+    let is_number = !value.is_empty()
+        && value
+            .chars()
+            .all(|c| c.is_ascii_digit() || c == '.' || c == '-')
+        && value.parse::<f64>().is_ok();
+    let value = if value == "true" || value == "false" || is_number {
+        value.to_string()
+    } else {
+        format!(
+            "\"{}\"",
+            value
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace("${", "\\${")
+        )
+    };
+
+    Ok((attr.to_string(), value))
+}
+
+/// The length of a full (SHA-1) commit hash.
+const FULL_REV_LENGTH: usize = 40;
+
+fn parse_rev(rev: &str) -> Result<String, String> {
+    if (4..=FULL_REV_LENGTH).contains(&rev.len()) && rev.chars().all(|c| c.is_ascii_hexdigit()) {
+        Ok(rev.to_lowercase())
+    } else {
+        Err(format!("`{rev}` is not a commit hash"))
+    }
+}
+
+/// Pins a `github:`-style URL to `rev`, e.g. `github:NixOS/nixpkgs?rev=<rev>`.
+fn pin_rev(mut url: url::Url, rev: &str) -> color_eyre::Result<url::Url> {
+    if !matches!(url.scheme(), "github" | "gitlab" | "sourcehut") {
+        return Err(color_eyre::eyre::eyre!(
+            "`--rev` can only be used with `github:`, `gitlab:`, and `sourcehut:` inputs, but the input's URL is `{url}` (try `--ref-type github`)"
+        ));
+    }
+    // Nix refuses URLs that specify both a branch or tag and a rev
+    if url.path().split('/').count() > 2 || url.query_pairs().any(|(key, _)| key == "ref") {
+        return Err(color_eyre::eyre::eyre!(
+            "`--rev` can't be combined with the branch or tag in `{url}`"
+        ));
+    }
+    if rev.len() < FULL_REV_LENGTH {
+        tracing::warn!(
+            "`{rev}` is a short commit hash, which Nix may not accept; consider using the full {FULL_REV_LENGTH} character hash"
+        );
+    }
+
+    let query = url
+        .query_pairs()
+        .filter(|(key, _)| key != "rev")
+        .map(|(key, value)| (key.into_owned(), value.into_owned()))
+        .collect::<Vec<_>>();
+    url.query_pairs_mut()
+        .clear()
+        .extend_pairs(query)
+        .append_pair("rev", rev);
+
+    Ok(url)
+}
+
+fn parse_attr_path(attr_path: &str) -> Result<VecDeque<String>, String> {
+    let attrs: VecDeque<String> = attr_path.split('.').map(ToString::to_string).collect();
+
+    if attrs.len() < 2 || attrs.back().map(String::as_str) != Some("url") {
+        return Err(format!(
+            "`{attr_path}` was not a dotted attribute path ending in `url` (e.g. `inputs.foo.url`)"
+        ));
+    }
+    if let Some(attr) = attrs.iter().find(|attr| !is_valid_nix_identifier(attr)) {
+        return Err(format!(
+            "`{attr}` in `{attr_path}` is not a valid Nix identifier"
+        ));
+    }
+
+    Ok(attrs)
+}
+
+/// Nix keywords, which can't be used as bare attribute names.
+const NIX_KEYWORDS: &[&str] = &[
+    "assert", "else", "if", "in", "inherit", "let", "or", "rec", "then", "with",
+];
+
+/// Whether `name` can be used as a bare Nix identifier (e.g. in `inputs.<name>.url` and in the
+/// `outputs` function's arguments), i.e. it matches `[a-zA-Z_][a-zA-Z0-9_'-]*` and isn't a keyword.
+fn is_valid_nix_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    let Some(first) = chars.next() else {
+        return false;
+    };
+
+    (first.is_ascii_alphabetic() || first == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '\'' | '-'))
+        && !NIX_KEYWORDS.contains(&name)
+}
+
+/// Input names inferred from a flake reference may not be valid Nix identifiers (e.g.
+/// `github:someorg/foo.nix`), so ask the user to pick one themselves instead of writing out an
+/// invalid flake.nix.
+fn validate_inferred_input_name(input_name: &str) -> color_eyre::Result<String> {
+    if is_valid_nix_identifier(input_name) {
+        Ok(input_name.to_string())
+    } else {
+        Err(color_eyre::eyre::eyre!(
+            "the inferred input name `{input_name}` is not a valid Nix identifier; please specify one with the `--input-name` flag"
+        ))
+    }
+}
+
+/// The flake.lock that goes with `flake_path`.
+pub(crate) fn flake_lock_path(flake_path: &Path) -> PathBuf {
+    flake_path.with_file_name("flake.lock")
+}
+
+/// The contents of the flake.lock that goes with `flake_path`, if there is one, to tell whether
+/// `nix flake lock` changed it.
+async fn read_flake_lock(flake_path: &Path) -> Option<Vec<u8>> {
+    tokio::fs::read(flake_lock_path(flake_path)).await.ok()
+}
+
+/// Runs `lock` (e.g. [`lock_flake`]) on the flake at `flake_path`, and returns its flake.lock if
+/// that changed.
+pub(crate) async fn changed_flake_lock(
+    flake_path: &Path,
+    lock: impl std::future::Future<Output = color_eyre::Result<()>>,
+) -> color_eyre::Result<Option<PathBuf>> {
+    let flake_lock = read_flake_lock(flake_path).await;
+    lock.await?;
+
+    Ok((read_flake_lock(flake_path).await != flake_lock).then(|| flake_lock_path(flake_path)))
+}
+
+/// Prints the absolute paths of the files modified by `--print-changed`, one per line.
+pub(crate) fn print_changed_paths(paths: &[PathBuf]) -> color_eyre::Result<()> {
+    for path in paths {
+        println!("{}", std::path::absolute(path)?.display());
+    }
+
+    Ok(())
+}
+
+/// Whether `--flake-path -` was passed, meaning the flake should be read from stdin and the result
+/// written to stdout.
+pub(crate) fn flake_path_is_stdio(flake_path: &Path) -> bool {
+    flake_path == Path::new("-")
+}
+
+#[tracing::instrument(skip_all)]
+// FIXME: make a nix or nix_util module or something
+pub(crate) async fn load_flake(
+    flake_path: &PathBuf,
+) -> color_eyre::Result<(String, nixel::Parsed)> {
+    load_flake_from(flake_path, tokio::io::stdin()).await
+}
+
+/// Like [`load_flake`], but reads the flake from `stdin` for `--flake-path -`.
+async fn load_flake_from(
+    flake_path: &PathBuf,
+    mut stdin: impl tokio::io::AsyncRead + Unpin,
+) -> color_eyre::Result<(String, nixel::Parsed)> {
+    let mut contents = if flake_path_is_stdio(flake_path) {
+        let mut contents = String::new();
+        stdin
+            .read_to_string(&mut contents)
+            .await
+            .wrap_err("Failed to read the flake from stdin")?;
+        contents
+    } else {
+        tokio::fs::read_to_string(&flake_path)
+            .await
+            .or_else(|e| {
+                if e.kind() == std::io::ErrorKind::NotFound {
+                    Ok(FALLBACK_FLAKE_CONTENTS.to_string())
+                } else {
+                    Err(e)
+                }
+            })
+            .wrap_err_with(|| format!("Failed to open {}", flake_path.display()))?
+    };
+
+    if contents.trim().is_empty() {
+        contents = FALLBACK_FLAKE_CONTENTS.to_string();
+    };
+
+    let mut parsed = parse_flake(&contents, flake_path)?;
+
+    if let nixel::Expression::Map(map) = *parsed.expression.clone() {
+        if map.bindings.is_empty() {
+            contents = FALLBACK_FLAKE_CONTENTS.to_string();
+            parsed = nixel::parse(FALLBACK_FLAKE_CONTENTS.to_string());
+        }
+    }
+
+    Ok((contents, parsed))
+}
+
+/// Makes the new flake.nix contents end with a newline exactly when the original contents did, so
+/// that editing a flake doesn't leave a noisy change at the end of the file.
+pub(crate) fn match_trailing_newline(original: &str, mut new_contents: String) -> String {
+    if original.ends_with('\n') {
+        if !new_contents.ends_with('\n') {
+            new_contents.push('\n');
+        }
+    } else {
+        let len = new_contents.trim_end_matches(['\r', '\n']).len();
+        new_contents.truncate(len);
+    }
+
+    new_contents
+}
+
+/// Parses the flake, reporting the first syntax error (if any) or a top-level expression that isn't
+/// an attribute set (even once any `let ... in` around it is stripped away) instead of handing it to
+/// code that would choke on it in more confusing ways.
+fn parse_flake(contents: &str, flake_path: &Path) -> color_eyre::Result<nixel::Parsed> {
+    let parsed = nixel::parse(contents.to_string());
+
+    if let Some(error) = flake::find_parse_error(&parsed.expression) {
+        let start = &error.span.start;
+        return Err(color_eyre::eyre::eyre!(
+            "failed to parse {} at line {}, column {}: {}",
+            flake_path.display(),
+            start.line,
+            start.column,
+            error.message
+        ));
+    }
+
+    match flake::unwrap_expression(&parsed.expression) {
+        nixel::Expression::Map(_) => {}
+        otherwise => {
+            return Err(color_eyre::eyre::eyre!(
+                "{} top-level must be an attribute set, but it was a {}",
+                flake_path.display(),
+                otherwise.variant_name()
+            ));
+        }
+    }
+
+    Ok(parsed)
+}
+
+/// Runs `nix flake lock` in the directory containing `flake_path`, forwarding its output to our
+/// logs.
+pub(crate) async fn lock_flake(flake_path: &Path) -> color_eyre::Result<()> {
+    lock_flake_inputs(flake_path, &[]).await
+}
+
+/// The arguments to `nix` that lock a flake, updating only `update_inputs` (if any) and leaving the
+/// rest of the flake.lock as it is.
+fn nix_flake_lock_args(update_inputs: &[&str]) -> Vec<String> {
+    let mut args: Vec<String> = ["--extra-experimental-features", "nix-command flakes"]
+        .into_iter()
+        .chain(["flake", "lock"])
+        .map(ToString::to_string)
+        .collect();
+    for input in update_inputs {
+        args.push(String::from("--update-input"));
+        args.push(input.to_string());
+    }
+
+    args
+}
+
+/// Like [`lock_flake`], but also updates `update_inputs` in the flake.lock.
+#[tracing::instrument(skip_all, fields(?update_inputs))]
+pub(crate) async fn lock_flake_inputs(
+    flake_path: &Path,
+    update_inputs: &[&str],
+) -> color_eyre::Result<()> {
+    let flake_dir = flake_path
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or_else(|| Path::new("."));
+
+    let mut child = tokio::process::Command::new("nix")
+        .args(nix_flake_lock_args(update_inputs))
+        .current_dir(flake_dir)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| {
+            if e.kind() == std::io::ErrorKind::NotFound {
+                color_eyre::eyre::eyre!(
+                    "could not find `nix` on your PATH; install Nix, or pass `--no-lock` to skip locking"
+                )
+            } else {
+                color_eyre::eyre::eyre!(e).wrap_err("failed to run `nix flake lock`")
+            }
+        })?;
+
+    let stdout = child.stdout.take().expect("stdout was piped");
+    let stderr = child.stderr.take().expect("stderr was piped");
+
+    let forward_stdout = async {
+        let mut lines = BufReader::new(stdout).lines();
+        while let Some(line) = lines.next_line().await? {
+            tracing::info!("{line}");
+        }
+        Ok::<_, std::io::Error>(())
+    };
+    let forward_stderr = async {
+        let mut lines = BufReader::new(stderr).lines();
+        while let Some(line) = lines.next_line().await? {
+            tracing::info!("{line}");
+        }
+        Ok::<_, std::io::Error>(())
+    };
+
+    let (status, stdout_res, stderr_res) =
+        tokio::join!(child.wait(), forward_stdout, forward_stderr);
+    stdout_res?;
+    stderr_res?;
+
+    let status = status?;
+    if !status.success() {
+        return Err(color_eyre::eyre::eyre!(
+            "`nix flake lock` failed in {} ({status})",
+            flake_dir.display()
+        ));
+    }
+
+    Ok(())
+}
+
+/// How [`infer_flake_input_name_url`] looks FlakeHub references up and builds their URLs.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct LookupOptions<'a> {
+    pub(crate) strip_tar_gz: bool,
+    pub(crate) archive_format: ArchiveFormat,
+    pub(crate) token: Option<&'a str>,
+    /// The org of references that only name a project, from `--default-org`.
+    pub(crate) default_org: Option<&'a str>,
+}
+
+#[tracing::instrument(skip_all)]
+async fn infer_flake_input_name_url(
+    api_addr: url::Url,
+    flake_ref: String,
+    input_name: Option<String>,
+    ref_type: RefType,
+    options: &LookupOptions<'_>,
+) -> color_eyre::Result<(String, url::Url)> {
+    let flake_ref = flake_ref.trim_end_matches('/');
+    // A local directory like `./libs/mylib`, which Nix wants written as `path:./libs/mylib`
+    let local_flake_ref;
+    let flake_ref = if ["./", "../", "/"]
+        .iter()
+        .any(|prefix| flake_ref.starts_with(prefix))
+    {
+        local_flake_ref = format!("path:{flake_ref}");
+        &local_flake_ref
+    } else {
+        flake_ref
+    };
+    let url_result = match ref_type {
+        RefType::FlakeHub => flake_ref.parse::<url::Url>(),
+        RefType::GitHub => match flake_ref.parse::<url::Url>() {
+            Err(url::ParseError::RelativeUrlWithoutBase) => {
+                format!("github:{flake_ref}").parse::<url::Url>()
+            }
+            _ => Err(color_eyre::eyre::eyre!(
+                "`--ref-type github` expects a reference like `org/repo` or `org/repo/ref`, but got `{flake_ref}`"
+            ))?,
+        },
+        RefType::Raw => Ok(flake_ref
+            .parse::<url::Url>()
+            .wrap_err_with(|| format!("`{flake_ref}` is not a valid URL"))?),
+    };
+
+    match url_result {
+        // A URL like `path:./libs/mylib`
+        Ok(parsed_url) if parsed_url.scheme() == "path" => match input_name {
+            Some(input_name) => Ok((input_name, parsed_url)),
+            None => {
+                let last_component = parsed_url.path().rsplit('/').next().unwrap_or_default();
+                Ok((validate_inferred_input_name(last_component)?, parsed_url))
+            }
+        },
+        // A URL like `github:nixos/nixpkgs`
+        Ok(parsed_url) if parsed_url.host().is_none() => {
+            // TODO: validate that the format of all Nix-supported schemes allows us to do this;
+            // else, have an allowlist of schemes
+            let mut path_parts = parsed_url.path().split('/');
+            path_parts.next(); // e.g. in `fh:` or `github:`, the org name
+
+            match (input_name, path_parts.next()) {
+                (Some(input_name), _) => Ok((input_name, parsed_url)),
+                (None, Some(input_name)) => Ok((validate_inferred_input_name(input_name)?, parsed_url)),
+                (None, _) =>  Err(color_eyre::eyre::eyre!(
+                    "cannot infer an input name for {parsed_url}; please specify one with the `--input-name` flag"
+                ))
+            }
+        }
+        // A URL like `nixos/nixpkgs` or `nixos/nixpkgs/0.2305`, optionally with a `?dir=subdir`
+        Err(url::ParseError::RelativeUrlWithoutBase) => {
+            let (flake_ref, query) = flake_ref.split_once('?').unwrap_or((flake_ref, ""));
+            let mut dir = None;
+            for (key, value) in url::form_urlencoded::parse(query.as_bytes()) {
+                match key.as_ref() {
+                    "dir" => dir = Some(value.into_owned()),
+                    other => Err(color_eyre::eyre::eyre!(
+                        "unsupported query parameter `{other}` in `{flake_ref}`; only `dir` is supported for FlakeHub inputs"
+                    ))?,
+                }
+            }
+
+            let unexpected_format = || {
+                color_eyre::eyre::eyre!(
+                    "flakehub input did not match the expected format of \
+                    `org/project` or `org/project/version`"
+                )
+            };
+            let (org, project, version) = match flake_ref.split('/').collect::<Vec<_>>()[..] {
+                // `nixos/nixpkgs/0.2305`
+                [org, project, version] => {
+                    let version = ArchiveFormat::strip_suffix(version);
+                    let version = version.strip_prefix('v').unwrap_or(version);
+                    semver::VersionReq::parse(version).map_err(|_| {
+                        color_eyre::eyre::eyre!(
+                            "version '{version}' was not a valid SemVer version requirement"
+                        )
+                    })?;
+
+                    (org, project, Some(version))
+                }
+                // `nixos/nixpkgs`
+                [org, project] => (org, project, None),
+                // `nixpkgs`, with `--default-org nixos`
+                [project] => match options.default_org {
+                    Some(org) => (org, project, None),
+                    None => Err(unexpected_format())?,
+                },
+                _ => Err(unexpected_format())?,
+            };
+
+            let (flakehub_input, mut url) = get_flakehub_project_and_url(
+                &api_addr,
+                org,
+                project,
+                version,
+                options.strip_tar_gz,
+                options.archive_format,
+                options.token,
+            )
+            .await?;
+
+            keep_version_requirement(
+                &mut url,
+                version,
+                options.strip_tar_gz,
+                options.archive_format,
+            );
+
+            // Point at the subflake, if any
+            if let Some(dir) = dir {
+                url.query_pairs_mut().append_pair("dir", &dir);
+            }
+
+            if let Some(input_name) = input_name {
+                Ok((input_name, url))
+            } else {
+                Ok((validate_inferred_input_name(&flakehub_input)?, url))
+            }
+        }
+        // A URL like `https://flakehub.com/f/NixOS/nixpkgs/*.tar.gz`
+        Ok(parsed_url) => {
+            if let Some(input_name) = input_name {
+                Ok((input_name, parsed_url))
+            } else if let Some((_, project, _, _)) =
+                crate::cli::cmd::update::parse_flakehub_url(&parsed_url)
+            {
+                Ok((validate_inferred_input_name(&project)?, parsed_url))
+            } else {
+                Err(color_eyre::eyre::eyre!(
+                    "cannot infer an input name for `{flake_ref}`; please specify one with the `--input-name` flag"
+                ))?
+            }
+        }
+        Err(e) => Err(e)?,
+    }
+}
+
+/// Everything that goes into looking up a project on FlakeHub: the API address, org, project,
+/// version requirement, whether to strip `.tar.gz`, the archive format, and the token.
+type ProjectLookupKey = (
+    url::Url,
+    String,
+    String,
+    Option<String>,
+    bool,
+    ArchiveFormat,
+    Option<String>,
+);
+
+/// The projects looked up on FlakeHub so far, so that each one is only requested once per run.
+/// Failed lookups aren't remembered.
+static FLAKEHUB_PROJECTS: Lazy<Mutex<HashMap<ProjectLookupKey, (String, url::Url)>>> =
+    Lazy::new(Default::default);
+
+#[tracing::instrument(skip_all)]
+pub(crate) async fn get_flakehub_project_and_url(
+    api_addr: &url::Url,
+    org: &str,
+    project: &str,
+    version: Option<&str>,
+    strip_tar_gz: bool,
+    archive_format: ArchiveFormat,
+    token: Option<&str>,
+) -> color_eyre::Result<(String, url::Url)> {
+    let key = (
+        api_addr.clone(),
+        org.to_string(),
+        project.to_string(),
+        version.map(ToString::to_string),
+        strip_tar_gz,
+        archive_format,
+        token.map(ToString::to_string),
+    );
+
+    if let Some(cached) = FLAKEHUB_PROJECTS
+        .lock()
+        .expect("cache lock was poisoned")
+        .get(&key)
+    {
+        tracing::debug!("Using cached FlakeHub response");
+        return Ok(cached.clone());
+    }
+
+    let client = flakehub_http_client(token).await?;
+
+    let mut flakehub_json_url = api_addr.clone();
+    {
+        let mut path_segments_mut = flakehub_json_url
+            .path_segments_mut()
+            .expect("flakehub url cannot be base (this should never happen)");
+
+        match version {
+            Some(version) => {
+                path_segments_mut
+                    .push("version")
+                    .push(org)
+                    .push(project)
+                    .push(version);
+            }
+            None => {
+                path_segments_mut.push("f").push(org).push(project);
+            }
+        }
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct ProjectCanonicalNames {
+        project: String,
+        pretty_download_url: url::Url,
+    }
+
+    let res = crate::cli::cmd::send_with_retries(client.get(flakehub_json_url)).await?;
+    if res.status() == reqwest::StatusCode::NOT_FOUND {
+        return Err(match version {
+            Some(version) => color_eyre::eyre::eyre!(
+                "no version of {org}/{project} matching `{version}` was found on FlakeHub (is it published?)"
+            ),
+            None => color_eyre::eyre::eyre!(
+                "no FlakeHub project found for {org}/{project} (is it published?)"
+            ),
+        });
+    }
+    let res = error_for_flakehub_status(res).await?;
+
+    let res = res.json::<ProjectCanonicalNames>().await?;
+
+    let mut url = validate_download_url(api_addr, res.pretty_download_url)?;
+    // FlakeHub answers with tarball URLs, which can be turned into ones of any other format
+    if let Some(path) = url.path().strip_suffix(".tar.gz").map(ToString::to_string) {
+        if strip_tar_gz {
+            url.set_path(&path);
+        } else {
+            url.set_path(&format!("{path}{}", archive_format.suffix()));
+        }
+    }
+
+    FLAKEHUB_PROJECTS
+        .lock()
+        .expect("cache lock was poisoned")
+        .insert(key, (res.project.clone(), url.clone()));
+
+    Ok((res.project, url))
+}
+
+/// Points a URL from [`get_flakehub_project_and_url`] back at `version` if it's a requirement like
+/// `0.1.*`. FlakeHub resolves those to the release that currently matches them, but an input
+/// should keep following the requirement the user asked for.
+pub(crate) fn keep_version_requirement(
+    url: &mut url::Url,
+    version: Option<&str>,
+    strip_tar_gz: bool,
+    archive_format: ArchiveFormat,
+) {
+    let Some(version) = version.filter(|version| version.contains('*')) else {
+        return;
+    };
+
+    let suffix = if strip_tar_gz {
+        ""
+    } else {
+        archive_format.suffix()
+    };
+    if let Ok(mut path_segments_mut) = url.path_segments_mut() {
+        path_segments_mut.pop().push(&format!("{version}{suffix}"));
+    }
+}
+
+/// The host FlakeHub serves flakes from.
+const FLAKEHUB_HOST: &str = "flakehub.com";
+
+/// Makes sure that a download URL returned by FlakeHub is fit to be written into a flake.nix: an
+/// `https` URL on FlakeHub itself (or, for a FlakeHub at another `--api-addr` like
+/// `api.example.com`, on `example.com`).
+fn validate_download_url(api_addr: &url::Url, url: url::Url) -> color_eyre::Result<url::Url> {
+    let host = url.host_str().unwrap_or_default();
+    let api_host = api_addr.host_str().unwrap_or_default();
+    let on_flakehub = host == FLAKEHUB_HOST
+        || host.ends_with(&format!(".{FLAKEHUB_HOST}"))
+        || api_host.strip_prefix("api.") == Some(host);
+
+    if url.scheme() != "https" || !on_flakehub {
+        return Err(color_eyre::eyre::eyre!(
+            "FlakeHub returned the unexpected download URL `{url}` (expected an https URL on {FLAKEHUB_HOST}); please report this"
+        ));
+    }
+
+    Ok(url)
+}
+
+/// Builds the HTTP client used to query FlakeHub. It authenticates with `token` if one was provided
+/// (via `--token` or `FH_TOKEN`), and otherwise with the token saved by `fh login`, if any.
+#[tracing::instrument(skip_all)]
+pub(crate) async fn flakehub_http_client(
+    token: Option<&str>,
+) -> color_eyre::Result<reqwest::Client> {
+    let mut headers = reqwest::header::HeaderMap::new();
+    headers.insert(ACCEPT, HeaderValue::from_static("application/json"));
+
+    let token = match token {
+        Some(token) => Some(token.to_string()),
+        None => crate::cli::cmd::login::saved_token().await?,
+    };
+
+    if let Some(token) = token {
+        headers.insert(
+            AUTHORIZATION,
+            HeaderValue::from_str(&format!("Bearer {}", token.trim()))?,
+        );
+    }
+
+    let client = reqwest::Client::builder()
+        .user_agent(crate::APP_USER_AGENT)
+        .default_headers(headers)
+        .build()?;
+
+    Ok(client)
+}
+
+/// Turns an unsuccessful FlakeHub response into an error, with [`FhError::AuthenticationRequired`]
+/// when the flake requires authentication.
+///
+/// [`FhError::AuthenticationRequired`]: super::FhError::AuthenticationRequired
+pub(crate) async fn error_for_flakehub_status(
+    res: reqwest::Response,
+) -> color_eyre::Result<reqwest::Response> {
+    if matches!(
+        res.status(),
+        reqwest::StatusCode::UNAUTHORIZED | reqwest::StatusCode::FORBIDDEN
+    ) {
+        return Err(super::FhError::AuthenticationRequired)
+            .wrap_err_with(|| format!("Failed to fetch {}", res.url()));
+    }
+
+    if let Err(e) = res.error_for_status_ref() {
+        let err_text = res.text().await?;
+        return Err(e).wrap_err(err_text)?;
+    };
+
+    Ok(res)
+}
+
+/// The first Nix version that accepts FlakeHub URLs without a `.tar.gz` suffix.
+const MIN_NIX_VERSION_FOR_BARE_FLAKEHUB_URLS: semver::Version = semver::Version::new(2, 20, 0);
+
+/// Whether the installed Nix is new enough to use FlakeHub URLs without the `.tar.gz` suffix. If
+/// the version can't be determined, we err on the side of keeping the suffix.
+#[tracing::instrument(skip_all)]
+pub(crate) async fn nix_supports_bare_flakehub_urls() -> bool {
+    let output = match tokio::process::Command::new("nix")
+        .arg("--version")
+        .stdin(Stdio::null())
+        .output()
+        .await
+    {
+        Ok(output) if output.status.success() => output,
+        Ok(output) => {
+            tracing::debug!(
+                "`nix --version` failed ({}), keeping `.tar.gz`",
+                output.status
+            );
+            return false;
+        }
+        Err(e) => {
+            tracing::debug!("couldn't run `nix --version` ({e}), keeping `.tar.gz`");
+            return false;
+        }
+    };
+
+    match parse_nix_version(&String::from_utf8_lossy(&output.stdout)) {
+        Some(version) => version >= MIN_NIX_VERSION_FOR_BARE_FLAKEHUB_URLS,
+        None => {
+            tracing::debug!("couldn't parse the output of `nix --version`, keeping `.tar.gz`");
+            false
+        }
+    }
+}
+
+/// Parses the output of `nix --version`, e.g. `nix (Nix) 2.18.1` or `nix (Nix) 2.19.0pre20231030_dirty`.
+fn parse_nix_version(output: &str) -> Option<semver::Version> {
+    let version = output.split_whitespace().last()?;
+    let numeric_len = version
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(version.len());
+    let mut parts = version[..numeric_len]
+        .split('.')
+        .map(|part| part.parse::<u64>().ok());
+
+    let major = parts.next()??;
+    let minor = parts.next().flatten().unwrap_or(0);
+    let patch = parts.next().flatten().unwrap_or(0);
+
+    Some(semver::Version::new(major, minor, patch))
+}
+
+#[cfg(test)]
+mod test {
+    #[test]
+    fn test_parse_nix_version() {
+        assert_eq!(
+            super::parse_nix_version("nix (Nix) 2.18.1\n"),
+            Some(semver::Version::new(2, 18, 1))
+        );
+        assert_eq!(
+            super::parse_nix_version("nix (Nix) 2.19.0pre20231030_dirty"),
+            Some(semver::Version::new(2, 19, 0))
+        );
+        assert_eq!(
+            super::parse_nix_version("nix (Nix) 2.20"),
+            Some(semver::Version::new(2, 20, 0))
+        );
+        assert_eq!(super::parse_nix_version(""), None);
+        assert_eq!(super::parse_nix_version("command not found"), None);
+    }
+
+    #[test]
+    fn test_parse_flake_reports_syntax_errors() {
+        let flake_contents = r#"{
+  inputs.nixpkgs.url = "github:NixOS/nixpkgs"
+  outputs = { self, ... }: { };
+}
+"#;
+        let err = super::parse_flake(flake_contents, std::path::Path::new("flake.nix"))
+            .unwrap_err()
+            .to_string();
+        assert!(
+            err.starts_with("failed to parse flake.nix at line 3, column "),
+            "{err}"
+        );
+
+        let flake_contents = r#"{ system }:
+{
+  inputs.nixpkgs.url = "github:NixOS/nixpkgs";
+  outputs = { self, ... }: { };
+}
+"#;
+        let err = super::parse_flake(flake_contents, std::path::Path::new("flake.nix"))
+            .unwrap_err()
+            .to_string();
+        assert_eq!(
+            err,
+            "flake.nix top-level must be an attribute set, but it was a Function"
+        );
+
+        let err = super::parse_flake(
+            "let system = \"x86_64-linux\"; in [ ]",
+            std::path::Path::new("flake.nix"),
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(err.ends_with("but it was a List"), "{err}");
+
+        assert!(super::parse_flake(
+            "let system = \"x86_64-linux\"; in { }",
+            std::path::Path::new("flake.nix")
+        )
+        .is_ok());
+
+        assert!(super::parse_flake(
+            super::FALLBACK_FLAKE_CONTENTS,
+            std::path::Path::new("flake.nix")
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn test_is_valid_nix_identifier() {
+        assert!(super::is_valid_nix_identifier("nixpkgs"));
+        assert!(super::is_valid_nix_identifier("flake-utils"));
+        assert!(super::is_valid_nix_identifier("_private"));
+        assert!(super::is_valid_nix_identifier("nixpkgs'"));
+        assert!(!super::is_valid_nix_identifier(""));
+        assert!(!super::is_valid_nix_identifier("foo.nix"));
+        assert!(!super::is_valid_nix_identifier("1password"));
+        assert!(!super::is_valid_nix_identifier("-foo"));
+        assert!(!super::is_valid_nix_identifier("with"));
+    }
+
+    #[test]
+    fn test_parse_set() {
+        for (set, attr, value) in [
+            ("flake=false", "flake", "false"),
+            ("submodules=true", "submodules", "true"),
+            ("depth=1", "depth", "1"),
+            ("type=tarball", "type", r#""tarball""#),
+            ("dir=a=b", "dir", r#""a=b""#),
+            (r#"ref=say "${hi}""#, "ref", r#""say \"\${hi}\"""#),
+            ("narHash=", "narHash", r#""""#),
+            ("inputs.nixpkgs.follows=", "inputs.nixpkgs.follows", r#""""#),
+        ] {
+            assert_eq!(
+                super::parse_set(set),
+                Ok((attr.to_string(), value.to_string())),
+                "{set}"
+            );
+        }
+
+        for set in [
+            "flake",
+            "=false",
+            "a..b=1",
+            "a b=1",
+            "x;y=1",
+            "inputs.\"foo\".follows=bar",
+            "url=github:NixOS/nixpkgs",
+        ] {
+            assert!(super::parse_set(set).is_err(), "{set}");
+        }
+    }
+
+    /// `fh add --ref-type github --dry-run --no-lock <input_ref>...`. There's no FlakeHub to talk to,
+    /// but GitHub references don't need one.
+    fn add_subcommand(input_ref: &[&str]) -> super::AddSubcommand {
+        let cli = crate::cli::config::Config::default()
+            .parse_cli(
+                [
+                    "fh",
+                    "--api-addr",
+                    "http://localhost:1",
+                    "add",
+                    "--flake-path",
+                    "flake.nix",
+                    "--ref-type",
+                    "github",
+                    "--dry-run",
+                    "--no-lock",
+                ]
+                .iter()
+                .chain(input_ref),
+            )
+            .unwrap();
+        let crate::cli::cmd::FhSubcommands::Add(add) = cli.subcommand else {
+            panic!("expected `fh add`");
+        };
+
+        add
+    }
+
+    #[tokio::test]
+    async fn test_add_preserves_trailing_newline() {
+        let add = super::AddSubcommand {
+            insertion_location: super::InputsInsertionLocation::Bottom,
+            ..add_subcommand(&["ipetkov/crane"])
+        };
+        let flake_contents =
+            "{\n  inputs.nixpkgs.url = \"github:NixOS/nixpkgs\";\n\n  outputs = { ... }: { };\n}";
+        let expected = "{\n  inputs.nixpkgs.url = \"github:NixOS/nixpkgs\";\n  inputs.crane.url = \"github:ipetkov/crane\";\n\n  outputs = { crane, ... }: { };\n}";
+
+        let (new_flake_contents, _) = add.add_inputs(flake_contents, false).await.unwrap();
+        assert_eq!(new_flake_contents, expected);
+
+        let (new_flake_contents, _) = add
+            .add_inputs(&format!("{flake_contents}\n"), false)
+            .await
+            .unwrap();
+        assert_eq!(new_flake_contents, format!("{expected}\n"));
+
+        let resolved = add.resolve_inputs(false).await.unwrap();
+        assert_eq!(
+            serde_json::to_value(resolved).unwrap(),
+            serde_json::json!([{
+                "input_ref": "ipetkov/crane",
+                "name": "crane",
+                "url": "github:ipetkov/crane",
+            }])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_add_twice_is_idempotent() {
+        let add = add_subcommand(&["ipetkov/crane"]);
+
+        let flake_contents =
+            "{\n  inputs.nixpkgs.url = \"github:NixOS/nixpkgs\";\n\n  outputs = { nixpkgs, ... }: { };\n}\n";
+        let (once, _) = add.add_inputs(flake_contents, false).await.unwrap();
+        let (twice, _) = add.add_inputs(&once, false).await.unwrap();
+        assert_eq!(once, twice);
+
+        // An unquoted URI that already matches isn't rewritten into a string
+        let flake_contents = "{\n  inputs = {\n    crane.url = github:ipetkov/crane;\n  };\n\n  outputs = { crane, ... }: { };\n}\n";
+        let (once, _) = add.add_inputs(flake_contents, false).await.unwrap();
+        assert_eq!(once, flake_contents);
+    }
+
+    #[tokio::test]
+    async fn test_add_on_exists() {
+        let mut add = add_subcommand(&["ipetkov/crane"]);
+        let flake_contents =
+            "{\n  inputs.crane.url = \"github:someone/crane\";\n\n  outputs = { crane, ... }: { };\n}\n";
+
+        let (new_flake_contents, _) = add.add_inputs(flake_contents, false).await.unwrap();
+        assert!(new_flake_contents.contains(r#"inputs.crane.url = "github:ipetkov/crane";"#));
+
+        add.on_exists = super::OnExists::Skip;
+        let (new_flake_contents, _) = add.add_inputs(flake_contents, false).await.unwrap();
+        assert_eq!(new_flake_contents, flake_contents);
+
+        add.on_exists = super::OnExists::Error;
+        assert!(add.add_inputs(flake_contents, false).await.is_err());
+
+        // Re-adding an input that already has the same URL isn't a conflict
+        let flake_contents =
+            "{\n  inputs.crane.url = \"github:ipetkov/crane\";\n\n  outputs = { crane, ... }: { };\n}\n";
+        let (new_flake_contents, _) = add.add_inputs(flake_contents, false).await.unwrap();
+        assert_eq!(new_flake_contents, flake_contents);
+    }
+
+    #[test]
+    fn test_changed_ranges() {
+        assert_eq!(super::changed_ranges("abc", "abc"), None);
+        assert_eq!(super::changed_ranges("abc", "abxc"), Some((2..2, 2..3)));
+        assert_eq!(super::changed_ranges("abxc", "abc"), Some((2..3, 2..2)));
+        assert_eq!(super::changed_ranges("aXa", "aYYa"), Some((1..2, 1..3)));
+        // Ranges never split a character
+        assert_eq!(super::changed_ranges("é", "è"), Some((0..2, 0..2)));
+    }
+
+    #[tokio::test]
+    async fn test_add_reports_what_happened() {
+        let add = super::AddSubcommand {
+            json: true,
+            ..add_subcommand(&["ipetkov/crane", "NixOS/nixpkgs"])
+        };
+        let flake_contents = "{\n  inputs.nixpkgs.url = \"github:NixOS/nixpkgs\";\n\n  outputs = { nixpkgs, ... }: { };\n}\n";
+
+        let (new_flake_contents, added) = add.add_inputs(flake_contents, false).await.unwrap();
+
+        assert_eq!(added[0].name, "crane");
+        assert_eq!(added[0].action, super::AddAction::Insert);
+        assert_eq!(added[1].name, "nixpkgs");
+        assert_eq!(added[1].action, super::AddAction::Unchanged);
+        assert_eq!(added[1].range, None);
+
+        // Applying the edit to the original contents gives the new ones
+        let mut edited = flake_contents.to_string();
+        edited.replace_range(
+            added[0].replaced_range.clone().unwrap(),
+            added[0].text.as_deref().unwrap(),
+        );
+        assert_eq!(edited, new_flake_contents);
+        assert_eq!(
+            &new_flake_contents[added[0].range.clone().unwrap()],
+            added[0].text.as_deref().unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_add_on_name_collision() {
+        let mut add = super::AddSubcommand {
+            on_name_collision: super::OnNameCollision::Error,
+            ..add_subcommand(&["ipetkov/crane"])
+        };
+        let flake_contents = "{\n  inputs.crane.url = \"github:someone/crane\";\n  inputs.crane-2.url = \"github:someone-else/crane\";\n\n  outputs = { crane, crane-2, ... }: { };\n}\n";
+
+        assert!(add.add_inputs(flake_contents, false).await.is_err());
+
+        add.on_name_collision = super::OnNameCollision::Suffix;
+        let (new_flake_contents, _) = add.add_inputs(flake_contents, false).await.unwrap();
+        assert!(new_flake_contents.contains(r#"inputs.crane.url = "github:someone/crane";"#));
+        assert!(new_flake_contents.contains(r#"inputs.crane-2.url = "github:someone-else/crane";"#));
+        assert!(new_flake_contents.contains(r#"inputs.crane-3.url = "github:ipetkov/crane";"#));
+
+        // An explicit name is taken as is
+        add.input_name = Some("crane".into());
+        let (new_flake_contents, _) = add.add_inputs(flake_contents, false).await.unwrap();
+        assert!(new_flake_contents.contains(r#"inputs.crane.url = "github:ipetkov/crane";"#));
+
+        // An input with the same name and URL isn't a collision
+        add.input_name = None;
+        let flake_contents =
+            "{\n  inputs.crane.url = \"github:ipetkov/crane\";\n\n  outputs = { crane, ... }: { };\n}\n";
+        let (new_flake_contents, _) = add.add_inputs(flake_contents, false).await.unwrap();
+        assert_eq!(new_flake_contents, flake_contents);
+    }
+
+    #[test]
+    fn test_pin_rev() {
+        let rev = "9a333eaa80901efe01df07eade2c16d183761fa3";
+        assert_eq!(super::parse_rev(&rev.to_uppercase()).unwrap(), rev);
+        assert!(super::parse_rev("9a333ea").is_ok());
+        assert!(super::parse_rev("9a3").is_err());
+        assert!(super::parse_rev("nixos-23.05").is_err());
+        assert!(super::parse_rev(&format!("{rev}0")).is_err());
+
+        for (url, expected) in [
+            (
+                "github:NixOS/nixpkgs",
+                Some(format!("github:NixOS/nixpkgs?rev={rev}")),
+            ),
+            (
+                "github:NixOS/nixpkgs?dir=lib",
+                Some(format!("github:NixOS/nixpkgs?dir=lib&rev={rev}")),
+            ),
+            (
+                "github:NixOS/nixpkgs?rev=abcd",
+                Some(format!("github:NixOS/nixpkgs?rev={rev}")),
+            ),
+            (
+                "gitlab:someorg/somerepo",
+                Some(format!("gitlab:someorg/somerepo?rev={rev}")),
+            ),
+            ("github:NixOS/nixpkgs/nixos-23.05", None),
+            ("github:NixOS/nixpkgs?ref=nixos-23.05", None),
+            ("https://flakehub.com/f/NixOS/nixpkgs/0.2305.*.tar.gz", None),
+        ] {
+            let pinned = super::pin_rev(url.parse().unwrap(), rev).ok();
+            assert_eq!(
+                pinned.as_ref().map(url::Url::as_str),
+                expected.as_deref(),
+                "{url}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_attr_path() {
+        assert_eq!(
+            super::parse_attr_path("inputs.foo.url").unwrap(),
+            ["inputs", "foo", "url"]
+        );
+        assert!(super::parse_attr_path("url").is_err());
+        assert!(super::parse_attr_path("inputs.foo").is_err());
+        assert!(super::parse_attr_path("inputs..url").is_err());
+        assert!(super::parse_attr_path("inputs.foo.nix.url").is_ok());
+        assert!(super::parse_attr_path("inputs.with.url").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_add_with_attr_path() {
+        let mut add = super::AddSubcommand {
+            attr_path: Some(["inputs".into(), "pkgs".into(), "url".into()].into()),
+            ..add_subcommand(&["NixOS/nixpkgs"])
+        };
+        let flake_contents = "{\n  inputs = {\n    pkgs.url = \"github:someone/nixpkgs\";\n  };\n\n  outputs = { pkgs, ... }: { };\n}\n";
+
+        let (new_flake_contents, _) = add.add_inputs(flake_contents, false).await.unwrap();
+        assert_eq!(
+            new_flake_contents,
+            flake_contents.replace("github:someone/nixpkgs", "github:NixOS/nixpkgs")
+        );
+
+        add.attr_path = Some(["inputs".into(), "nope".into(), "url".into()].into());
+        assert!(add.add_inputs(flake_contents, false).await.is_err());
+    }
+
+    #[test]
+    fn test_nix_flake_lock_args() {
+        let prefix = [
+            "--extra-experimental-features",
+            "nix-command flakes",
+            "flake",
+            "lock",
+        ];
+
+        assert_eq!(super::nix_flake_lock_args(&[]), prefix);
+        assert_eq!(
+            super::nix_flake_lock_args(&["nixpkgs", "crane"]),
+            prefix
+                .into_iter()
+                .chain(["--update-input", "nixpkgs", "--update-input", "crane"])
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_add_updates_quoted_input() {
+        let add = super::AddSubcommand {
+            input_name: Some("flake.utils".into()),
+            follows: vec![("nixpkgs".into(), "nixpkgs-23.05".into())],
+            ..add_subcommand(&["someorg/flake-utils"])
+        };
+        let flake_contents = include_str!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/samples/flake10.test.nix"
+        ));
+
+        let (new_flake_contents, _) = add.add_inputs(flake_contents, false).await.unwrap();
+        assert_eq!(
+            new_flake_contents,
+            flake_contents.replace(
+                "  inputs.\"flake.utils\".url = \"github:numtide/flake-utils\";\n",
+                "  inputs.\"flake.utils\".url = \"github:someorg/flake-utils\";\n  inputs.\"flake.utils\".inputs.nixpkgs.follows = \"nixpkgs-23.05\";\n"
+            )
+        );
+    }
+
+    #[test]
+    fn test_match_trailing_newline() {
+        assert_eq!(super::match_trailing_newline("a\n", "b".into()), "b\n");
+        assert_eq!(super::match_trailing_newline("a\n", "b\n".into()), "b\n");
+        assert_eq!(super::match_trailing_newline("a", "b\n\n".into()), "b");
+        assert_eq!(super::match_trailing_newline("a", "b".into()), "b");
+    }
+
+    #[tokio::test]
+    async fn test_backup_flake() {
+        let dir = std::env::temp_dir().join(format!("fh-backup-test-{}", std::process::id()));
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let flake_path = dir.join("flake.nix");
+
+        let backup_path = super::backup_flake(&flake_path, "one", super::BackupMode::Overwrite)
+            .await
+            .unwrap();
+        assert_eq!(backup_path, dir.join("flake.nix.bak"));
+        let backup_path = super::backup_flake(&flake_path, "two", super::BackupMode::Overwrite)
+            .await
+            .unwrap();
+        assert_eq!(backup_path, dir.join("flake.nix.bak"));
+        assert_eq!(
+            tokio::fs::read_to_string(&backup_path).await.unwrap(),
+            "two"
+        );
+
+        for (n, contents) in [(1, "three"), (2, "four")] {
+            let backup_path =
+                super::backup_flake(&flake_path, contents, super::BackupMode::Numbered)
+                    .await
+                    .unwrap();
+            assert_eq!(backup_path, dir.join(format!("flake.nix.bak.{n}")));
+            assert_eq!(
+                tokio::fs::read_to_string(&backup_path).await.unwrap(),
+                contents
+            );
+        }
+        assert_eq!(
+            tokio::fs::read_to_string(dir.join("flake.nix.bak"))
+                .await
+                .unwrap(),
+            "two"
+        );
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_infer_invalid_input_name() {
+        let api_addr: url::Url = "http://localhost".parse().unwrap();
+
+        for flake_ref in ["github:someorg/foo.nix", "github:someorg/1password"] {
+            let res = super::infer_flake_input_name_url(
+                api_addr.clone(),
+                flake_ref.to_string(),
+                None,
+                super::RefType::FlakeHub,
+                &super::LookupOptions::default(),
+            )
+            .await;
+            assert!(res.is_err(), "{flake_ref} should be rejected");
+        }
+
+        let (input_name, _) = super::infer_flake_input_name_url(
+            api_addr,
+            "github:someorg/foo.nix".to_string(),
+            Some("foo".to_string()),
+            super::RefType::FlakeHub,
+            &super::LookupOptions::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(input_name, "foo");
+    }
+
+    async fn project(
+        axum::extract::Path((org, project)): axum::extract::Path<(String, String)>,
+    ) -> axum::Json<serde_json::Value> {
+        axum::Json(serde_json::json!({
+            "project": project,
+            "pretty_download_url": format!("https://flakehub.com/f/{org}/{project}/0.1.0.tar.gz"),
+        }))
+    }
+
+    async fn project_version(
+        axum::extract::Path((org, project, version)): axum::extract::Path<(String, String, String)>,
+    ) -> axum::Json<serde_json::Value> {
+        axum::Json(serde_json::json!({
+            "project": project,
+            "pretty_download_url": format!("https://flakehub.com/f/{org}/{project}/{version}.tar.gz"),
+        }))
+    }
+
+    fn test_router() -> axum::Router {
+        axum::Router::new()
+            .route("/f/:org/:project", axum::routing::get(project))
+            .route(
+                "/version/:org/:project/:version",
+                axum::routing::get(project_version),
+            )
+    }
+
+    #[tokio::test]
+    async fn test_infer_forwards_version_requirement() {
+        // Pretend every requirement resolves to 0.1.7, and report back which one was asked for.
+        async fn resolve_version(
+            axum::extract::Path((org, project, version)): axum::extract::Path<(
+                String,
+                String,
+                String,
+            )>,
+        ) -> axum::Json<serde_json::Value> {
+            axum::Json(serde_json::json!({
+                "project": format!("{project}@{version}"),
+                "pretty_download_url": format!("https://flakehub.com/f/{org}/{project}/0.1.7.tar.gz"),
+            }))
+        }
+
+        let router = axum::Router::new().route(
+            "/version/:org/:project/:version",
+            axum::routing::get(resolve_version),
+        );
+        let test_server = axum_test::TestServer::new(router.into_make_service()).unwrap();
+        let api_addr: url::Url = test_server.server_address().parse().unwrap();
+
+        for (version, expected) in [("0.1.*", "0.1.*"), ("*", "*"), ("0.1.0", "0.1.7")] {
+            let (project, url) = super::get_flakehub_project_and_url(
+                &api_addr,
+                "someorg",
+                "repo",
+                Some(version),
+                false,
+                super::ArchiveFormat::TarGz,
+                None,
+            )
+            .await
+            .unwrap();
+            assert_eq!(project, format!("repo@{version}"));
+            // Other callers (like `fh convert`) get the release FlakeHub resolved
+            assert_eq!(
+                url.as_str(),
+                "https://flakehub.com/f/someorg/repo/0.1.7.tar.gz"
+            );
+
+            let (_, url) = super::infer_flake_input_name_url(
+                api_addr.clone(),
+                format!("someorg/repo/{version}"),
+                Some("repo".to_string()),
+                super::RefType::FlakeHub,
+                &super::LookupOptions {
+                    strip_tar_gz: true,
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+            assert_eq!(
+                url.as_str(),
+                format!("https://flakehub.com/f/someorg/repo/{expected}")
+            );
+
+            let (_, url) = super::infer_flake_input_name_url(
+                api_addr.clone(),
+                format!("someorg/repo/{version}"),
+                Some("repo".to_string()),
+                super::RefType::FlakeHub,
+                &super::LookupOptions {
+                    archive_format: super::ArchiveFormat::Zip,
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+            assert_eq!(
+                url.as_str(),
+                format!("https://flakehub.com/f/someorg/repo/{expected}.zip")
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_infer_keeps_dir() {
+        let test_server = axum_test::TestServer::new(test_router().into_make_service()).unwrap();
+        let api_addr: url::Url = test_server.server_address().parse().unwrap();
+
+        let (input_name, url) = super::infer_flake_input_name_url(
+            api_addr.clone(),
+            "someorg/repo?dir=nix".to_string(),
+            None,
+            super::RefType::FlakeHub,
+            &super::LookupOptions::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(input_name, "repo");
+        assert_eq!(
+            url.as_str(),
+            "https://flakehub.com/f/someorg/repo/0.1.0.tar.gz?dir=nix"
+        );
+
+        let (_, url) = super::infer_flake_input_name_url(
+            api_addr.clone(),
+            "someorg/repo/0.2.*?dir=nix".to_string(),
+            None,
+            super::RefType::FlakeHub,
+            &super::LookupOptions::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            url.as_str(),
+            "https://flakehub.com/f/someorg/repo/0.2.*.tar.gz?dir=nix"
+        );
+
+        let (_, url) = super::infer_flake_input_name_url(
+            api_addr.clone(),
+            "github:someorg/repo?dir=nix".to_string(),
+            None,
+            super::RefType::FlakeHub,
+            &super::LookupOptions::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(url.as_str(), "github:someorg/repo?dir=nix");
+
+        let res = super::infer_flake_input_name_url(
+            api_addr,
+            "someorg/repo?ref=main".to_string(),
+            None,
+            super::RefType::FlakeHub,
+            &super::LookupOptions::default(),
+        )
+        .await;
+        assert!(res.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_infer_archive_format() {
+        let test_server = axum_test::TestServer::new(test_router().into_make_service()).unwrap();
+        let api_addr: url::Url = test_server.server_address().parse().unwrap();
+
+        for (flake_ref, expected) in [
+            (
+                "someorg/repo",
+                "https://flakehub.com/f/someorg/repo/0.1.0.zip",
+            ),
+            (
+                "someorg/repo/0.2.*",
+                "https://flakehub.com/f/someorg/repo/0.2.*.zip",
+            ),
+            (
+                "someorg/repo/0.2.0.zip",
+                "https://flakehub.com/f/someorg/repo/0.2.0.zip",
+            ),
+        ] {
+            let (_, url) = super::infer_flake_input_name_url(
+                api_addr.clone(),
+                flake_ref.to_string(),
+                None,
+                super::RefType::FlakeHub,
+                &super::LookupOptions {
+                    archive_format: super::ArchiveFormat::Zip,
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+            assert_eq!(url.as_str(), expected);
+        }
+
+        assert_eq!(
+            "zip".parse::<super::ArchiveFormat>().unwrap(),
+            super::ArchiveFormat::Zip
+        );
+        assert_eq!(
+            "tar.gz".parse::<super::ArchiveFormat>().unwrap(),
+            super::ArchiveFormat::TarGz
+        );
+        assert!("tar.xz".parse::<super::ArchiveFormat>().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_infer_default_org() {
+        let test_server = axum_test::TestServer::new(test_router().into_make_service()).unwrap();
+        let api_addr: url::Url = test_server.server_address().parse().unwrap();
+
+        let (input_name, url) = super::infer_flake_input_name_url(
+            api_addr.clone(),
+            "repo".to_string(),
+            None,
+            super::RefType::FlakeHub,
+            &super::LookupOptions {
+                default_org: Some("someorg"),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(input_name, "repo");
+        assert_eq!(
+            url.as_str(),
+            "https://flakehub.com/f/someorg/repo/0.1.0.tar.gz"
+        );
+
+        // A reference with an org ignores the default one
+        let (_, url) = super::infer_flake_input_name_url(
+            api_addr.clone(),
+            "otherorg/repo".to_string(),
+            None,
+            super::RefType::FlakeHub,
+            &super::LookupOptions {
+                default_org: Some("someorg"),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            url.as_str(),
+            "https://flakehub.com/f/otherorg/repo/0.1.0.tar.gz"
+        );
+
+        let res = super::infer_flake_input_name_url(
+            api_addr,
+            "repo".to_string(),
+            None,
+            super::RefType::FlakeHub,
+            &super::LookupOptions::default(),
+        )
+        .await;
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn test_validate_download_url() {
+        let api_addr: url::Url = "https://api.flakehub.com".parse().unwrap();
+        for url in [
+            "https://flakehub.com/f/NixOS/nixpkgs/0.1.0.tar.gz",
+            "https://api.flakehub.com/f/NixOS/nixpkgs/0.1.0.tar.gz",
+        ] {
+            assert!(
+                super::validate_download_url(&api_addr, url.parse().unwrap()).is_ok(),
+                "{url}"
+            );
+        }
+        for url in [
+            "http://flakehub.com/f/NixOS/nixpkgs/0.1.0.tar.gz",
+            "https://flakehub.com.example.com/f/NixOS/nixpkgs/0.1.0.tar.gz",
+            "https://example.com/f/NixOS/nixpkgs/0.1.0.tar.gz",
+            "file:///etc/passwd",
+            "github:NixOS/nixpkgs",
+        ] {
+            assert!(
+                super::validate_download_url(&api_addr, url.parse().unwrap()).is_err(),
+                "{url}"
+            );
+        }
+
+        // A FlakeHub hosted elsewhere serves its flakes from its own domain
+        let api_addr: url::Url = "https://api.flakehub.example.com".parse().unwrap();
+        assert!(super::validate_download_url(
+            &api_addr,
+            "https://flakehub.example.com/f/NixOS/nixpkgs/0.1.0.tar.gz"
+                .parse()
+                .unwrap()
+        )
+        .is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_bogus_download_url_is_rejected() {
+        let router = axum::Router::new().route(
+            "/f/:org/:project",
+            axum::routing::get(|| async {
+                axum::Json(serde_json::json!({
+                    "project": "nixpkgs",
+                    "pretty_download_url": "http://evil.example.com/nixpkgs.tar.gz",
+                }))
+            }),
+        );
+        let test_server = axum_test::TestServer::new(router.into_make_service()).unwrap();
+        let api_addr = test_server.server_address().parse().unwrap();
+
+        let err = super::get_flakehub_project_and_url(
+            &api_addr,
+            "NixOS",
+            "nixpkgs",
+            None,
+            false,
+            super::ArchiveFormat::TarGz,
+            None,
+        )
+        .await
+        .unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("unexpected download URL `http://evil.example.com/nixpkgs.tar.gz`"),
+            "{err}"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_missing_project_error() {
+        let test_server =
+            axum_test::TestServer::new(axum::Router::new().into_make_service()).unwrap();
+        let api_addr: url::Url = test_server.server_address().parse().unwrap();
+
+        let err = super::get_flakehub_project_and_url(
+            &api_addr,
+            "someorg",
+            "nope",
+            None,
+            false,
+            super::ArchiveFormat::TarGz,
+            None,
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "no FlakeHub project found for someorg/nope (is it published?)"
+        );
+
+        let err = super::get_flakehub_project_and_url(
+            &api_addr,
+            "someorg",
+            "nope",
+            Some("0.1.*"),
+            false,
+            super::ArchiveFormat::TarGz,
+            None,
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "no version of someorg/nope matching `0.1.*` was found on FlakeHub (is it published?)"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_private_project_requires_authentication() {
+        let router = axum::Router::new().route(
+            "/f/:org/:project",
+            axum::routing::get(|| async { axum::http::StatusCode::UNAUTHORIZED }),
+        );
+        let test_server = axum_test::TestServer::new(router.into_make_service()).unwrap();
+        let api_addr: url::Url = test_server.server_address().parse().unwrap();
+
+        let err = super::get_flakehub_project_and_url(
+            &api_addr,
+            "someorg",
+            "private",
+            None,
+            false,
+            super::ArchiveFormat::TarGz,
+            None,
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<crate::cli::cmd::FhError>(),
+            Some(crate::cli::cmd::FhError::AuthenticationRequired)
+        ));
+        assert!(err.to_string().starts_with("Failed to fetch "), "{err}");
+    }
+
+    #[tokio::test]
+    async fn test_infer_input_name_from_flakehub_url() {
+        // Nothing is listening here, so these would fail if they tried to reach FlakeHub.
+        let api_addr: url::Url = "http://localhost:1".parse().unwrap();
+
+        for flake_ref in [
+            "https://flakehub.com/f/NixOS/nixpkgs/*.tar.gz",
+            "https://flakehub.com/f/NixOS/nixpkgs",
+        ] {
+            let (input_name, url) = super::infer_flake_input_name_url(
+                api_addr.clone(),
+                flake_ref.to_string(),
+                None,
+                super::RefType::FlakeHub,
+                &super::LookupOptions::default(),
+            )
+            .await
+            .unwrap();
+            assert_eq!(input_name, "nixpkgs");
+            assert_eq!(url.as_str(), flake_ref);
+        }
+
+        for flake_ref in [
+            "https://flakehub.com/flake/NixOS/nixpkgs",
+            "https://example.com/f/NixOS/nixpkgs",
+        ] {
+            let res = super::infer_flake_input_name_url(
+                api_addr.clone(),
+                flake_ref.to_string(),
+                None,
+                super::RefType::FlakeHub,
+                &super::LookupOptions::default(),
+            )
+            .await;
+            assert!(res.is_err(), "{flake_ref} should be rejected");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_infer_path_input() {
+        // Nothing is listening here, so these would fail if they tried to reach FlakeHub.
+        let api_addr: url::Url = "http://localhost:1".parse().unwrap();
+
+        for (flake_ref, input_name, expected_input_name, expected_url) in [
+            ("path:./libs/mylib", None, "mylib", "path:./libs/mylib"),
+            ("./libs/mylib/", None, "mylib", "path:./libs/mylib"),
+            ("../mylib", None, "mylib", "path:../mylib"),
+            ("/src/my-lib", None, "my-lib", "path:/src/my-lib"),
+            ("path:.", Some("self-lib"), "self-lib", "path:."),
+        ] {
+            let (inferred_input_name, url) = super::infer_flake_input_name_url(
+                api_addr.clone(),
+                flake_ref.to_string(),
+                input_name.map(ToString::to_string),
+                super::RefType::FlakeHub,
+                &super::LookupOptions::default(),
+            )
+            .await
+            .unwrap();
+            assert_eq!(inferred_input_name, expected_input_name, "{flake_ref}");
+            assert_eq!(url.as_str(), expected_url);
+        }
+
+        let res = super::infer_flake_input_name_url(
+            api_addr,
+            "path:.".to_string(),
+            None,
+            super::RefType::FlakeHub,
+            &super::LookupOptions::default(),
+        )
+        .await;
+        assert!(res.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_infer_with_ref_type() {
+        // Nothing is listening here, so these would fail if they tried to reach FlakeHub.
+        let api_addr: url::Url = "http://localhost:1".parse().unwrap();
+
+        let (input_name, url) = super::infer_flake_input_name_url(
+            api_addr.clone(),
+            "ipetkov/crane".to_string(),
+            None,
+            super::RefType::GitHub,
+            &super::LookupOptions::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(input_name, "crane");
+        assert_eq!(url.as_str(), "github:ipetkov/crane");
+
+        let (input_name, url) = super::infer_flake_input_name_url(
+            api_addr.clone(),
+            "git+https://example.com/crane.git?ref=main".to_string(),
+            Some("crane".to_string()),
+            super::RefType::Raw,
+            &super::LookupOptions::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(input_name, "crane");
+        assert_eq!(url.as_str(), "git+https://example.com/crane.git?ref=main");
+
+        let res = super::infer_flake_input_name_url(
+            api_addr.clone(),
+            "github:ipetkov/crane".to_string(),
+            None,
+            super::RefType::GitHub,
+            &super::LookupOptions::default(),
+        )
+        .await;
+        assert!(res.is_err());
+
+        let res = super::infer_flake_input_name_url(
+            api_addr,
+            "ipetkov/crane".to_string(),
+            None,
+            super::RefType::Raw,
+            &super::LookupOptions::default(),
+        )
+        .await;
+        assert!(res.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_write_flake_changed() {
+        const FLAKE: &str = "{ }\n";
+        const NEW_FLAKE: &str = "{ inputs.fh.url = \"github:DeterminateSystems/fh\"; }\n";
+        let flake_path = crate::cli::cmd::mock_flakehub::temp_flake("write-flake", FLAKE).await;
+        let cli = crate::cli::config::Config::default()
+            .parse_cli([
+                "fh",
+                "add",
+                "--flake-path",
+                flake_path.to_str().unwrap(),
+                "--dry-run",
+                "--print-changed",
+                "--no-lock",
+                "DeterminateSystems/fh",
+            ])
+            .unwrap();
+        let crate::cli::cmd::FhSubcommands::Add(mut add) = cli.subcommand else {
+            panic!("expected `fh add`");
+        };
+
+        // With `--dry-run`, the flake.nix would have changed, but isn't written
+        let changed = add.write_flake(FLAKE, NEW_FLAKE.into()).await.unwrap();
+        assert_eq!(changed, std::slice::from_ref(&flake_path));
+        assert_eq!(tokio::fs::read_to_string(&flake_path).await.unwrap(), FLAKE);
+
+        let changed = add.write_flake(FLAKE, FLAKE.into()).await.unwrap();
+        assert!(changed.is_empty());
+
+        add.dry_run = false;
+        let changed = add.write_flake(FLAKE, NEW_FLAKE.into()).await.unwrap();
+        assert_eq!(changed, std::slice::from_ref(&flake_path));
+        assert_eq!(
+            tokio::fs::read_to_string(&flake_path).await.unwrap(),
+            NEW_FLAKE
+        );
+
+        crate::cli::cmd::mock_flakehub::remove_temp_flake(&flake_path).await;
+    }
+
+    #[tokio::test]
+    async fn test_changed_flake_lock() {
+        let flake_path = crate::cli::cmd::mock_flakehub::temp_flake("changed-lock", "{ }").await;
+        let flake_lock_path = super::flake_lock_path(&flake_path);
+        // Stands in for `nix flake lock`
+        let lock = |contents: &'static str| {
+            let flake_lock_path = flake_lock_path.clone();
+            async move {
+                tokio::fs::write(flake_lock_path, contents).await?;
+                Ok(())
+            }
+        };
+
+        for (contents, expected) in [
+            // A new flake.lock
+            ("{}", Some(flake_lock_path.clone())),
+            ("{}", None),
+            ("{ \"version\": 7 }", Some(flake_lock_path.clone())),
+        ] {
+            let changed = super::changed_flake_lock(&flake_path, lock(contents))
+                .await
+                .unwrap();
+            assert_eq!(changed, expected, "{contents}");
+        }
+
+        let res = super::changed_flake_lock(&flake_path, async {
+            Err(color_eyre::eyre::eyre!("`nix flake lock` failed"))
+        })
+        .await;
+        assert!(res.is_err());
+
+        crate::cli::cmd::mock_flakehub::remove_temp_flake(&flake_path).await;
+    }
+
+    #[tokio::test]
+    async fn test_load_flake_from_stdin() {
+        const FLAKE: &str = "{\n  inputs.nixpkgs.url = \"github:NixOS/nixpkgs\";\n}\n";
+        let stdio: std::path::PathBuf = "-".into();
+
+        assert!(super::flake_path_is_stdio(&stdio));
+        let (contents, _) = super::load_flake_from(&stdio, FLAKE.as_bytes())
+            .await
+            .unwrap();
+        assert_eq!(contents, FLAKE);
+
+        // Nothing on stdin is like an empty flake.nix
+        let (contents, _) = super::load_flake_from(&stdio, &b""[..]).await.unwrap();
+        assert_eq!(contents, super::FALLBACK_FLAKE_CONTENTS);
+
+        // Only `-` itself means stdin, not a file named `-` in some directory
+        let flake_path = crate::cli::cmd::mock_flakehub::temp_flake("load-flake", "{ }").await;
+        let other_path = flake_path.with_file_name("-");
+        tokio::fs::write(&other_path, FLAKE).await.unwrap();
+        assert!(!super::flake_path_is_stdio(&other_path));
+        let (contents, _) = super::load_flake_from(&other_path, &b"{ }"[..])
+            .await
+            .unwrap();
+        assert_eq!(contents, FLAKE);
+
+        crate::cli::cmd::mock_flakehub::remove_temp_flake(&flake_path).await;
+    }
 }
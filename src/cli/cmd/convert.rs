@@ -1,12 +1,19 @@
 use std::collections::VecDeque;
-use std::path::PathBuf;
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
 use std::process::{ExitCode, Stdio};
 
 use clap::Parser;
+use color_eyre::eyre::WrapErr;
+use futures::{StreamExt, TryStreamExt};
+use indicatif::{ProgressBar, ProgressStyle};
 use once_cell::sync::Lazy;
-use tracing::{span, Level};
+use owo_colors::OwoColorize;
+use tabled::{Table, Tabled};
+use tracing::{span, Instrument, Level};
 
-use super::CommandExecute;
+use super::list::{bold, display_option};
+use super::{CommandExecute, DEFAULT_STYLE};
 
 // match {nixos,nixpkgs,release}-YY.MM branches
 static RELEASE_BRANCH_REGEX: Lazy<regex::Regex> = Lazy::new(|| {
@@ -15,8 +22,11 @@ static RELEASE_BRANCH_REGEX: Lazy<regex::Regex> = Lazy::new(|| {
 });
 
 const NIXPKGS_IMPLICIT_INPUT_NAME: &str = "nixpkgs";
+/// How many inputs to look up on FlakeHub at once.
+const MAX_CONCURRENT_LOOKUPS: usize = 8;
 const SHELL_NIX: &str = "shell.nix";
 const DEFAULT_NIX: &str = "default.nix";
+const FLAKE_COMPAT_INPUT_URL: &str = "github:edolstra/flake-compat";
 const FLAKE_COMPAT_MARKER: &str = "https://github.com/edolstra/flake-compat/archive";
 
 const FLAKE_COMPAT_CONTENTS_PREFIX: &str = r#"(import
@@ -31,76 +41,568 @@ const FLAKE_COMPAT_CONTENTS_PREFIX: &str = r#"(import
 )"#;
 
 /// Convert flake inputs to FlakeHub when possible.
-#[derive(Debug, Parser)]
+#[derive(Debug, Clone, Parser)]
+#[command(mut_arg("flake_path", |arg| arg.help("The flake.nix to convert, or `-` to read it from stdin and write the result to stdout")))]
 pub(crate) struct ConvertSubcommand {
-    /// The flake.nix to convert.
-    #[clap(long, default_value = "./flake.nix")]
-    pub(crate) flake_path: PathBuf,
+    #[clap(flatten)]
+    pub(crate) flake: super::FlakePathArgs,
 
     /// Print to stdout the new flake.nix contents instead of writing it to disk.
     #[clap(long)]
     pub(crate) dry_run: bool,
 
+    /// Print a unified diff of the changes instead of the whole new flake.nix. Implies
+    /// `--dry-run`.
+    #[clap(long)]
+    pub(crate) diff: bool,
+
+    /// Don't run `nix flake lock` after writing the converted flake.nix contents.
+    #[clap(long)]
+    pub(crate) no_lock: bool,
+
+    /// Only update the inputs that were converted in flake.lock (with `nix flake lock
+    /// --update-input`), rather than locking the whole flake.
+    #[clap(long, conflicts_with = "no_lock")]
+    pub(crate) lock: bool,
+
+    /// Don't contact FlakeHub: only convert inputs pinned to an exact release (e.g.
+    /// `github:NixOS/nixpkgs/v1.2.3`) by rewriting their URL, and skip everything else.
+    #[clap(long)]
+    pub(crate) offline: bool,
+
+    /// Also convert `https://github.com/...` and `git+https://github.com/...` inputs, which are
+    /// otherwise skipped.
+    #[clap(long)]
+    pub(crate) include_https: bool,
+
+    /// Write the converted flake.nix contents to this file instead, leaving the original
+    /// untouched. Neither `nix flake lock` nor the flake-compat fixups of `default.nix` and
+    /// `shell.nix` are run in this case.
+    #[clap(long)]
+    pub(crate) output_path: Option<PathBuf>,
+
+    /// Save the original flake.nix to `flake.nix.bak` before overwriting it.
+    #[clap(long)]
+    pub(crate) backup: bool,
+
+    /// What to do when the backup file already exists: `overwrite` it, or pick the next free
+    /// `numbered` name (`flake.nix.bak.1`, `flake.nix.bak.2`, ...).
+    #[clap(long, default_value_t = crate::cli::cmd::add::BackupMode::Overwrite)]
+    pub(crate) backup_mode: crate::cli::cmd::add::BackupMode,
+
+    /// Don't rewrite `default.nix` and `shell.nix` to use the flake-compat input pinned in the
+    /// flake. The flake-compat input itself is still converted to FlakeHub.
+    #[clap(long)]
+    pub(crate) no_flake_compat_fixup: bool,
+
+    /// Only convert the named input, leaving all others untouched. May be specified multiple
+    /// times.
+    #[clap(long = "input")]
+    pub(crate) input_names: Vec<String>,
+
+    /// Convert a GitHub repository to a FlakeHub project with a different name, in the form of
+    /// `<github org>/<repo>=<flakehub org>/<project>` (e.g. for a fork or a renamed project). May be
+    /// specified multiple times.
+    #[clap(long, value_parser = parse_project_mapping)]
+    pub(crate) map: Vec<ProjectMapping>,
+
+    /// Only convert inputs whose URL uses this scheme (e.g. `github` or `git+https`), leaving all
+    /// others untouched. May be specified multiple times.
+    #[clap(long = "only-scheme")]
+    pub(crate) only_schemes: Vec<String>,
+
+    /// Exit with a non-zero status if any input couldn't be converted. Inputs left out with
+    /// `--input` or `--only-scheme` don't count.
+    #[clap(long)]
+    pub(crate) fail_on_skip: bool,
+
+    /// Write a JSON report of what happened to each input (its original URL, its new URL, and why
+    /// it was skipped, if it was) to this file. Written even with `--dry-run`.
+    #[clap(long)]
+    pub(crate) report: Option<PathBuf>,
+
+    /// Also convert the flakes in the subdirectories of the flake's directory (e.g. subflakes
+    /// referenced by a relative path), skipping directories ignored by `.gitignore`. The
+    /// flake-compat fixups of `default.nix` and `shell.nix` are only run for the top-level flake.
+    #[clap(long, conflicts_with_all = ["output_path", "report"])]
+    pub(crate) recursive: bool,
+
+    /// Print the absolute path of each file that was modified (one per line), including
+    /// `default.nix` and `shell.nix`, e.g. for passing to `git add`. With `--dry-run`, print the
+    /// files that would be modified instead of the new flake.nix contents.
+    #[clap(long)]
+    pub(crate) print_changed: bool,
+
+    /// Don't ask for confirmation before writing the converted flake. Only needed when stdin and
+    /// stdout are terminals, since otherwise there's nobody to ask.
+    #[clap(long, short = 'y')]
+    pub(crate) assume_yes: bool,
+
+    /// Leave the `.tar.gz` suffix off of the new FlakeHub URLs, e.g.
+    /// `https://flakehub.com/f/NixOS/nixpkgs/0.2305.*`. Otherwise, it's only left off if the
+    /// installed Nix supports such URLs.
+    #[clap(long)]
+    pub(crate) bare_urls: bool,
+
+    /// The archive format of the new FlakeHub URLs: `tar.gz` or `zip`. Only tarball URLs have their
+    /// suffix left off automatically.
+    #[clap(long, default_value_t = crate::cli::cmd::add::ArchiveFormat::TarGz, conflicts_with = "bare_urls")]
+    pub(crate) archive_format: crate::cli::cmd::add::ArchiveFormat,
+
+    /// Only print a table of each input's URL and the FlakeHub URL it would be converted to (or
+    /// why it wouldn't be), without writing anything.
+    #[clap(long, conflicts_with_all = ["dry_run", "diff", "output_path", "print_changed", "report"])]
+    pub(crate) preview_url: bool,
+
+    /// Don't convert Nixpkgs release branches older than this release (in the form of `YY.MM`, e.g.
+    /// `23.05`), warning about them instead. 20.03 was the first release with a flake.nix.
+    #[clap(long, default_value_t = NixpkgsRelease::FIRST_FLAKE, value_parser = parse_nixpkgs_release)]
+    pub(crate) min_nixpkgs_release: NixpkgsRelease,
+
     #[clap(from_global)]
     api_addr: url::Url,
+
+    #[clap(from_global)]
+    token: Option<String>,
+
+    #[clap(from_global)]
+    verbose: u8,
+}
+
+/// A GitHub repository that lives on FlakeHub under a different name, from `--map`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ProjectMapping {
+    github: (String, String),
+    flakehub: (String, String),
+}
+
+fn parse_project_mapping(mapping: &str) -> Result<ProjectMapping, String> {
+    let split_project = |project: &str| match project.split_once('/') {
+        Some((org, project))
+            if !org.is_empty() && !project.is_empty() && !project.contains('/') =>
+        {
+            Some((org.to_string(), project.to_string()))
+        }
+        _ => None,
+    };
+
+    mapping
+        .split_once('=')
+        .and_then(|(github, flakehub)| {
+            Some(ProjectMapping {
+                github: split_project(github)?,
+                flakehub: split_project(flakehub)?,
+            })
+        })
+        .ok_or_else(|| {
+            format!(
+                "`{mapping}` was not of the form `<org>/<repo>=<org>/<project>` (e.g. `someone/nixpkgs-fork=NixOS/nixpkgs`)"
+            )
+        })
+}
+
+/// A Nixpkgs release like 23.05, from `--min-nixpkgs-release` or a `nixos-23.05` branch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct NixpkgsRelease {
+    year: u64,
+    month: u64,
+}
+
+impl NixpkgsRelease {
+    /// NixOS 20.03 and later have a flake.nix.
+    pub(crate) const FIRST_FLAKE: Self = Self { year: 20, month: 3 };
+}
+
+impl std::fmt::Display for NixpkgsRelease {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:02}.{:02}", self.year, self.month)
+    }
+}
+
+fn parse_nixpkgs_release(release: &str) -> Result<NixpkgsRelease, String> {
+    let two_digits = |part: &str| {
+        (part.len() == 2 && part.chars().all(|c| c.is_ascii_digit()))
+            .then(|| part.parse::<u64>().ok())
+            .flatten()
+    };
+
+    release
+        .split_once('.')
+        .and_then(|(year, month)| {
+            Some(NixpkgsRelease {
+                year: two_digits(year)?,
+                month: two_digits(month).filter(|month| (1..=12).contains(month))?,
+            })
+        })
+        .ok_or_else(|| {
+            format!("`{release}` was not a Nixpkgs release of the form `YY.MM` (e.g. `23.05`)")
+        })
+}
+
+/// What `fh convert` did with an input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum InputStatus {
+    /// Converted to FlakeHub.
+    Converted,
+    /// Couldn't be converted to FlakeHub.
+    Skipped,
+    /// Already pointed at FlakeHub.
+    Unchanged,
+    /// Left out with `--input` or `--only-scheme`.
+    Excluded,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub(crate) struct InputReport {
+    pub(crate) name: String,
+    pub(crate) status: InputStatus,
+    pub(crate) original_url: Option<String>,
+    pub(crate) new_url: Option<url::Url>,
+    pub(crate) reason: Option<String>,
+}
+
+/// What `fh convert` did with each of the flake's inputs.
+#[derive(Debug, Default, serde::Serialize)]
+pub(crate) struct ConversionReport {
+    pub(crate) inputs: Vec<InputReport>,
+}
+
+impl ConversionReport {
+    /// Records an input, returning its entry so that the new URL or the reason can be filled in.
+    fn add(
+        &mut self,
+        name: String,
+        status: InputStatus,
+        original_url: Option<String>,
+    ) -> &mut InputReport {
+        self.inputs.push(InputReport {
+            name,
+            status,
+            original_url,
+            new_url: None,
+            reason: None,
+        });
+        self.inputs.last_mut().expect("an input was just pushed")
+    }
+
+    /// The (sorted) names of the inputs with the given status.
+    pub(crate) fn names(&self, status: InputStatus) -> Vec<&str> {
+        let mut names: Vec<&str> = self
+            .inputs
+            .iter()
+            .filter(|input| input.status == status)
+            .map(|input| input.name.as_str())
+            .collect();
+        names.sort();
+        names
+    }
+
+    /// A row per input for `--preview-url`.
+    fn preview_rows(&self) -> Vec<UrlPreviewRow> {
+        self.inputs
+            .iter()
+            .map(|input| UrlPreviewRow {
+                input: input.name.clone(),
+                original_url: input.original_url.clone(),
+                flakehub_url: input.new_url.as_ref().map(ToString::to_string),
+                skip_reason: match input.status {
+                    InputStatus::Converted => None,
+                    InputStatus::Skipped => input.reason.clone(),
+                    InputStatus::Unchanged => Some("already on FlakeHub".into()),
+                    InputStatus::Excluded => Some("not selected".into()),
+                },
+            })
+            .collect()
+    }
+
+    fn print_summary(&self) {
+        for (label, status) in [
+            ("Converted to FlakeHub", InputStatus::Converted),
+            ("Already on FlakeHub", InputStatus::Unchanged),
+            ("Skipped", InputStatus::Skipped),
+            ("Not selected", InputStatus::Excluded),
+        ] {
+            let names = self.names(status);
+            if !names.is_empty() {
+                eprintln!("{label} ({}): {}", names.len(), names.join(", "));
+            }
+        }
+    }
+}
+
+#[derive(Tabled, serde::Serialize)]
+struct UrlPreviewRow {
+    #[tabled(rename = "Input", display_with = "bold")]
+    #[serde(rename = "Input")]
+    input: String,
+    #[tabled(rename = "Original URL", display_with = "display_option")]
+    #[serde(rename = "Original URL")]
+    original_url: Option<String>,
+    #[tabled(rename = "FlakeHub URL", display_with = "display_option")]
+    #[serde(rename = "FlakeHub URL")]
+    flakehub_url: Option<String>,
+    #[tabled(rename = "Skip reason", display_with = "display_option")]
+    #[serde(rename = "Skip reason")]
+    skip_reason: Option<String>,
 }
 
 #[async_trait::async_trait]
 impl CommandExecute for ConvertSubcommand {
     #[tracing::instrument(skip_all)]
     async fn execute(self) -> color_eyre::Result<ExitCode> {
-        if !self.flake_path.exists() {
+        if self.recursive {
+            self.convert_recursively().await
+        } else {
+            self.convert().await
+        }
+    }
+}
+
+impl ConvertSubcommand {
+    async fn convert(self) -> color_eyre::Result<ExitCode> {
+        let use_stdio = crate::cli::cmd::add::flake_path_is_stdio(&self.flake.path);
+        let dry_run = self.dry_run || self.diff || use_stdio;
+        if !use_stdio && !self.flake.path.exists() {
             return Err(color_eyre::eyre::eyre!(
                 "the flake at {} did not exist",
-                self.flake_path.display()
+                self.flake.path.display()
             ));
         }
 
-        let (flake_contents, parsed) = crate::cli::cmd::add::load_flake(&self.flake_path).await?;
-        let (new_flake_contents, flake_compat_input_name) = self
-            .convert_inputs_to_flakehub(&parsed.expression, &flake_contents)
-            .await?;
-        let new_flake_contents = self
-            .make_implicit_nixpkgs_explicit(&parsed.expression, &new_flake_contents)
-            .await?;
+        let (flake_contents, parsed) = crate::cli::cmd::add::load_flake(&self.flake.path).await?;
+        let strip_tar_gz = self.bare_urls
+            || (self.archive_format == crate::cli::cmd::add::ArchiveFormat::TarGz
+                && crate::cli::cmd::add::nix_supports_bare_flakehub_urls().await);
+        let lookup = FlakeHubLookup::new(
+            &self.api_addr,
+            strip_tar_gz,
+            self.token.as_deref(),
+            self.offline,
+        )
+        .with_mappings(&self.map)
+        .with_archive_format(self.archive_format)
+        .with_min_nixpkgs_release(self.min_nixpkgs_release);
+        let (new_flake_contents, flake_compat_input_name, mut report) = self
+            .convert_inputs_to_flakehub(&parsed.expression, &flake_contents, &lookup)
+            .await
+            .map_err(|err| {
+                crate::cli::cmd::add::flake::with_source_context(err, &flake_contents)
+            })?;
+        let new_flake_contents = if self.should_convert(NIXPKGS_IMPLICIT_INPUT_NAME) {
+            self.make_implicit_nixpkgs_explicit(&parsed.expression, &new_flake_contents, &lookup)
+                .await
+                .map_err(|err| {
+                    crate::cli::cmd::add::flake::with_source_context(err, &flake_contents)
+                })?
+        } else {
+            new_flake_contents
+        };
+        let flake_compat_input_name = match flake_compat_input_name {
+            Some(name) if self.offline => {
+                tracing::warn!(
+                    "not converting the flake-compat input, since that requires looking it up on FlakeHub"
+                );
+                report
+                    .add(
+                        name,
+                        InputStatus::Skipped,
+                        Some(FLAKE_COMPAT_INPUT_URL.to_string()),
+                    )
+                    .reason = Some("looking it up on FlakeHub isn't possible offline".into());
+                None
+            }
+            name => name,
+        };
+        let mut fixup_flake_compat_nix_files = false;
         let new_flake_contents = if let Some(flake_compat_input_name) = flake_compat_input_name {
-            let new_flake_contents = self
-                .fixup_flake_compat_input(&new_flake_contents, flake_compat_input_name)
+            let (new_flake_contents, new_url) = self
+                .fixup_flake_compat_input(
+                    &new_flake_contents,
+                    flake_compat_input_name.clone(),
+                    &lookup,
+                )
                 .await?;
+            report
+                .add(
+                    flake_compat_input_name,
+                    InputStatus::Converted,
+                    Some(FLAKE_COMPAT_INPUT_URL.to_string()),
+                )
+                .new_url = Some(new_url);
 
-            if !self.dry_run {
-                self.fixup_flake_compat_nix_files().await?;
-            }
+            // There's no directory to fix up the files in when the flake comes from stdin, and
+            // they'd refer to the original flake when writing it elsewhere.
+            fixup_flake_compat_nix_files = !use_stdio
+                && (!dry_run || self.print_changed)
+                && self.output_path.is_none()
+                && !self.no_flake_compat_fixup;
 
             new_flake_contents
         } else {
             new_flake_contents
         };
 
-        if self.dry_run {
-            println!("{new_flake_contents}");
+        let new_flake_contents =
+            crate::cli::cmd::add::match_trailing_newline(&flake_contents, new_flake_contents);
+
+        if self.preview_url {
+            let rows = report.preview_rows();
+            if rows.is_empty() {
+                eprintln!("No inputs");
+            } else if std::io::stdout().is_terminal() {
+                let mut table = Table::new(rows);
+                table.with(DEFAULT_STYLE.clone());
+                println!("{table}");
+            } else {
+                let mut writer = csv::Writer::from_writer(std::io::stdout());
+                for row in rows {
+                    writer.serialize(row)?;
+                }
+            }
+
+            return Ok(ExitCode::SUCCESS);
+        }
+
+        if !dry_run || self.verbose > 0 {
+            report.print_summary();
+        }
+
+        if !dry_run && new_flake_contents != flake_contents && !self.confirm(&report) {
+            eprintln!("Not converting {}", self.flake.path.display());
+            return Ok(ExitCode::FAILURE);
+        }
+
+        let mut fixed_up = Vec::new();
+        if fixup_flake_compat_nix_files {
+            fixed_up = self
+                .fixup_flake_compat_nix_files(Path::new(""), dry_run)
+                .await?;
+        }
+
+        if let Some(report_path) = &self.report {
+            tokio::fs::write(report_path, serde_json::to_string_pretty(&report)?)
+                .await
+                .wrap_err_with(|| format!("Failed to write {}", report_path.display()))?;
+        }
+
+        let mut changed = Vec::new();
+        if dry_run {
+            if new_flake_contents != flake_contents && self.output_path.is_none() {
+                changed.push(self.flake.path.clone());
+            }
+            if self.diff {
+                print_diff(&self.flake.path, &flake_contents, &new_flake_contents);
+            } else if use_stdio || !self.print_changed {
+                println!("{new_flake_contents}");
+            }
         } else {
-            tokio::fs::write(self.flake_path, new_flake_contents).await?;
-            tokio::process::Command::new("nix")
-                .args(["--extra-experimental-features", "nix-command flakes"])
-                .arg("flake")
-                .arg("lock")
-                .status()
+            changed = self
+                .write_flake(
+                    &flake_contents,
+                    new_flake_contents,
+                    &report.names(InputStatus::Converted),
+                )
                 .await?;
         }
+        changed.extend(fixed_up);
+
+        if self.print_changed && !use_stdio {
+            crate::cli::cmd::add::print_changed_paths(&changed)?;
+        }
+
+        let skipped = report.names(InputStatus::Skipped);
+        if self.fail_on_skip && !skipped.is_empty() {
+            eprintln!(
+                "{} input(s) couldn't be converted to FlakeHub: {}",
+                skipped.len(),
+                skipped.join(", ")
+            );
+            return Ok(ExitCode::FAILURE);
+        }
 
         Ok(ExitCode::SUCCESS)
     }
-}
 
-impl ConvertSubcommand {
+    /// Converts the flake and then every flake below it, one after the other, printing which one
+    /// each summary belongs to.
+    async fn convert_recursively(self) -> color_eyre::Result<ExitCode> {
+        if crate::cli::cmd::add::flake_path_is_stdio(&self.flake.path) {
+            return Err(color_eyre::eyre::eyre!(
+                "`--recursive` can't be used with `--flake-path -`"
+            ));
+        }
+        if !self.flake.path.exists() {
+            return Err(color_eyre::eyre::eyre!(
+                "the flake at {} did not exist",
+                self.flake.path.display()
+            ));
+        }
+
+        let mut exit_code = ExitCode::SUCCESS;
+        for (n, flake_path) in find_flakes(&self.flake.path)?.into_iter().enumerate() {
+            eprintln!("{}:", flake_path.display());
+
+            let convert = ConvertSubcommand {
+                flake: super::FlakePathArgs { path: flake_path },
+                recursive: false,
+                no_flake_compat_fixup: self.no_flake_compat_fixup || n > 0,
+                ..self.clone()
+            };
+            if convert.convert().await? == ExitCode::FAILURE {
+                exit_code = ExitCode::FAILURE;
+            }
+        }
+
+        Ok(exit_code)
+    }
+
+    /// Lists the inputs about to be converted and asks whether to go ahead, unless `--assume-yes`
+    /// was passed or there's no terminal to ask on.
+    fn confirm(&self, report: &ConversionReport) -> bool {
+        if self.assume_yes || !std::io::stdin().is_terminal() || !std::io::stdout().is_terminal() {
+            return true;
+        }
+
+        eprintln!("About to convert:");
+        for input in report
+            .inputs
+            .iter()
+            .filter(|input| input.status == InputStatus::Converted)
+        {
+            match (&input.original_url, &input.new_url) {
+                (Some(original_url), Some(new_url)) => {
+                    eprintln!("  {}: {original_url} -> {new_url}", input.name)
+                }
+                _ => eprintln!("  {}", input.name),
+            }
+        }
+
+        crate::cli::cmd::init::prompt::Prompt::bool_with_default(
+            &format!("Write the converted {}?", self.flake.path.display()),
+            false,
+        )
+    }
+
+    /// Whether the input is among the ones the user asked to convert (if they asked for any).
+    fn should_convert(&self, input_name: &str) -> bool {
+        self.input_names.is_empty() || self.input_names.iter().any(|name| name == input_name)
+    }
+
+    /// Whether inputs with this URL scheme are among the ones the user asked to convert (if they
+    /// asked for any).
+    fn should_convert_scheme(&self, scheme: &str) -> bool {
+        self.only_schemes.is_empty() || self.only_schemes.iter().any(|only| only == scheme)
+    }
+
     #[tracing::instrument(skip_all)]
     async fn convert_inputs_to_flakehub(
         &self,
         expr: &nixel::Expression,
         flake_contents: &str,
-    ) -> color_eyre::Result<(String, Option<String>)> {
-        let mut new_flake_contents = flake_contents.to_string();
+        lookup: &FlakeHubLookup<'_>,
+    ) -> color_eyre::Result<(String, Option<String>, ConversionReport)> {
+        let mut report = ConversionReport::default();
 
         let all_toplevel_inputs = crate::cli::cmd::add::flake::find_all_attrsets_by_path(
             expr,
@@ -110,36 +612,42 @@ impl ConvertSubcommand {
         let all_inputs = crate::cli::cmd::add::flake::collect_all_inputs(all_toplevel_inputs)?;
         tracing::trace!("Collected inputs: {:#?}", all_inputs);
         let mut flake_compat_input_name = None;
+        let mut lookups = Vec::new();
+        let mut seen_input_names = Vec::new();
 
-        for input in all_inputs.iter() {
+        for (idx, input) in all_inputs.iter().enumerate() {
             tracing::trace!("Examining input: {:#?}", input);
-            let Some(input_name) = input.from.iter().find_map(|part| match part {
-                nixel::Part::Raw(raw) => {
-                    let content = raw.content.trim().to_string();
-
-                    if ["inputs", "url"].contains(&content.as_ref()) {
-                        None
-                    } else {
-                        Some(content)
-                    }
-                }
-                _ => None,
-            }) else {
+            let Some(input_name) = input.name() else {
                 tracing::debug!("couldn't get input name from attrpath, skipping");
                 continue;
             };
 
+            seen_input_names.push(input_name.clone());
+            if !self.should_convert(&input_name) {
+                tracing::debug!("{input_name} was not selected with `--input`, skipping");
+                report.add(input_name, InputStatus::Excluded, None).reason =
+                    Some("not selected with `--input`".into());
+                continue;
+            }
+
             let span = span!(Level::DEBUG, "processing_input", %input_name);
             let _span_guard = span.enter();
 
-            let url = find_input_value_by_path(&input.to, ["url".into()].into())?;
+            let url = find_input_value_by_path(&input.binding.to, ["url".into()].into())?;
             tracing::debug!("Current input's `url` value: {:?}", url);
+            let original_url = url.clone();
 
             let url = match url {
                 Some(url) => {
-                    if url == "github:edolstra/flake-compat" {
-                        // Save the flake-compat input name for later (so we can find it again)
-                        flake_compat_input_name = Some(input_name.clone());
+                    if url == FLAKE_COMPAT_INPUT_URL {
+                        if self.should_convert_scheme("github") {
+                            // Save the flake-compat input name for later (so we can find it again)
+                            flake_compat_input_name = Some(input_name.clone());
+                        } else {
+                            report
+                                .add(input_name, InputStatus::Excluded, original_url)
+                                .reason = Some("`github` not selected with `--only-scheme`".into());
+                        }
                         continue;
                     }
 
@@ -159,34 +667,133 @@ impl ConvertSubcommand {
             let maybe_parsed_url = url.and_then(|u| u.parse::<url::Url>().ok());
             tracing::trace!("Parsed URL: {:?}", maybe_parsed_url);
 
-            let new_input_url = match maybe_parsed_url {
-                Some(parsed_url) => convert_input_to_flakehub(&self.api_addr, parsed_url).await?,
-                None => None,
+            match maybe_parsed_url {
+                Some(parsed_url)
+                    if parsed_url.host() == Some(url::Host::Domain("flakehub.com")) =>
+                {
+                    report.add(input_name, InputStatus::Unchanged, original_url);
+                }
+                Some(parsed_url) if !self.should_convert_scheme(parsed_url.scheme()) => {
+                    tracing::debug!(
+                        "{input_name} uses the `{}` scheme, which wasn't selected with `--only-scheme`, skipping",
+                        parsed_url.scheme()
+                    );
+                    report
+                        .add(input_name, InputStatus::Excluded, original_url)
+                        .reason = Some(format!(
+                        "`{}` not selected with `--only-scheme`",
+                        parsed_url.scheme()
+                    ));
+                }
+                Some(parsed_url) => lookups.push((idx, input_name, original_url, parsed_url)),
+                None => {
+                    let reason = if original_url.is_some() {
+                        "its `url` isn't a valid URL"
+                    } else {
+                        "it has no literal `url`"
+                    };
+                    report
+                        .add(input_name, InputStatus::Skipped, original_url)
+                        .reason = Some(reason.into());
+                }
+            }
+        }
+
+        for input_name in self.input_names.iter() {
+            // An implicit `nixpkgs` is handled by `make_implicit_nixpkgs_explicit`.
+            if !seen_input_names.contains(input_name) && input_name != NIXPKGS_IMPLICIT_INPUT_NAME {
+                tracing::warn!("there is no input named `{input_name}` in the flake; skipping it");
+            }
+        }
+
+        // Drawn on stderr, so it doesn't get in the way of `--dry-run` output.
+        let pb = ProgressBar::new(lookups.len() as u64);
+        pb.set_style(ProgressStyle::with_template(
+            "{spinner} resolving input {msg} ({pos}/{len})",
+        )?);
+
+        // Resolve the inputs concurrently; the edits are only applied once all of them have been
+        // resolved, so the order they finish in doesn't matter.
+        let resolved = futures::stream::iter(lookups)
+            .map(|(idx, input_name, original_url, parsed_url)| {
+                let span = span!(Level::DEBUG, "processing_input", %input_name);
+                let pb = &pb;
+                async move {
+                    pb.set_message(input_name.clone());
+                    let new_input_url =
+                        convert_input_to_flakehub(lookup, parsed_url, self.include_https).await?;
+                    pb.inc(1);
+                    Ok::<_, color_eyre::Report>((idx, input_name, original_url, new_input_url))
+                }
+                .instrument(span)
+            })
+            .buffer_unordered(MAX_CONCURRENT_LOOKUPS)
+            .try_collect::<Vec<_>>()
+            .await;
+        let resolved = match resolved {
+            Ok(resolved) => resolved,
+            Err(e) => {
+                pb.finish_and_clear();
+                return Err(e);
+            }
+        };
+
+        let converted = resolved
+            .iter()
+            .filter(|(_, _, _, url)| url.is_some())
+            .count();
+        pb.set_style(ProgressStyle::with_template("{msg}")?);
+        pb.finish_with_message(format!(
+            "Converted {converted} of {} inputs to FlakeHub ({} skipped)",
+            resolved.len(),
+            resolved.len() - converted
+        ));
+
+        // Collect the edits first and apply them all at once, so that editing one input doesn't
+        // invalidate the spans of the others (even when several of them share a line).
+        let mut edits = Vec::new();
+        for (idx, input_name, original_url, new_input_url) in resolved {
+            let Some(new_input_url) = new_input_url else {
+                report
+                    .add(input_name, InputStatus::Skipped, original_url)
+                    .reason = Some("no FlakeHub equivalent of its `url` was found".into());
+                continue;
             };
 
-            if let Some(new_input_url) = new_input_url {
-                let input_attr_path: VecDeque<String> =
-                    ["inputs".into(), input_name.clone(), "url".into()].into();
-                let Some(attr) = crate::cli::cmd::add::flake::find_first_attrset_by_path(
-                    expr,
-                    Some(input_attr_path),
-                )?
-                else {
-                    return Err(color_eyre::eyre::eyre!(
-                        "there was no `inputs.{input_name}.url` attribute, but there should have been; \
-                        please report this"
-                    ));
-                };
-                new_flake_contents = crate::cli::cmd::add::flake::update_flake_input(
-                    attr,
-                    input_name,
-                    new_input_url,
-                    new_flake_contents,
-                )?;
+            let Some(url_value) = all_inputs[idx].url_value() else {
+                return Err(color_eyre::eyre::eyre!(
+                    "there was no `inputs.{input_name}.url` attribute, but there should have been; \
+                    please report this"
+                ));
+            };
+            match crate::cli::cmd::add::flake::url_edit(url_value, &input_name, &new_input_url) {
+                Ok(edit) => {
+                    edits.extend(edit);
+                    report
+                        .add(input_name, InputStatus::Converted, original_url)
+                        .new_url = Some(new_input_url);
+                }
+                Err(e) => {
+                    if e.downcast_ref::<crate::cli::cmd::add::flake::InterpolatedUrl>()
+                        .is_none()
+                    {
+                        return Err(e);
+                    }
+
+                    tracing::warn!("not converting {input_name}: {e}");
+                    report
+                        .add(input_name, InputStatus::Skipped, original_url)
+                        .reason = Some("its `url` uses string interpolation".into());
+                }
             }
         }
 
-        Ok((new_flake_contents, flake_compat_input_name))
+        // The inputs were resolved concurrently, so put them back into a stable order.
+        report.inputs.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let new_flake_contents = crate::cli::cmd::add::flake::apply_edits(flake_contents, edits)?;
+
+        Ok((new_flake_contents, flake_compat_input_name, report))
     }
 
     #[tracing::instrument(skip_all)]
@@ -194,6 +801,7 @@ impl ConvertSubcommand {
         &self,
         expr: &nixel::Expression,
         flake_contents: &str,
+        lookup: &FlakeHubLookup<'_>,
     ) -> color_eyre::Result<String> {
         let mut new_flake_contents = flake_contents.to_string();
         let input_name = String::from(NIXPKGS_IMPLICIT_INPUT_NAME);
@@ -224,13 +832,13 @@ impl ConvertSubcommand {
                             .iter()
                             .any(|arg| *arg.identifier == input_name) =>
                     {
-                        let (_, flakehub_url) = crate::cli::cmd::add::get_flakehub_project_and_url(
-                            &self.api_addr,
-                            "nixos",
-                            &input_name,
-                            None,
-                        )
-                        .await?;
+                        let (_, flakehub_url) =
+                            match lookup.project_and_url("nixos", &input_name, None).await {
+                                Ok(resolved) => resolved,
+                                // The lookup already warned about it
+                                Err(_) if lookup.offline => return Ok(new_flake_contents),
+                                Err(e) => return Err(e),
+                            };
 
                         new_flake_contents = crate::cli::cmd::add::flake::insert_flake_input(
                             expr,
@@ -238,6 +846,7 @@ impl ConvertSubcommand {
                             flakehub_url.clone(),
                             new_flake_contents,
                             crate::cli::cmd::add::flake::InputsInsertionLocation::Top,
+                            None,
                         )?;
                     }
                     _ => {}
@@ -253,7 +862,8 @@ impl ConvertSubcommand {
         &self,
         flake_contents: &str,
         input_name: String,
-    ) -> color_eyre::Result<String> {
+        lookup: &FlakeHubLookup<'_>,
+    ) -> color_eyre::Result<(String, url::Url)> {
         let mut new_flake_contents = flake_contents.to_string();
 
         // Re-parse the contents since we might have added an input, and that will screw up offset calculations.
@@ -266,31 +876,22 @@ impl ConvertSubcommand {
         // This expect is safe because we already know there
         .unwrap_or_else(|| panic!("inputs.{input_name} disappeared from flake.nix"));
 
-        let (_, flake_input_value) = crate::cli::cmd::add::get_flakehub_project_and_url(
-            &self.api_addr,
-            "edolstra",
-            "flake-compat",
-            None,
-        )
-        .await?;
+        let (_, flake_input_value) = lookup
+            .project_and_url("edolstra", "flake-compat", None)
+            .await?;
 
         let (from_span, to_span) = crate::cli::cmd::add::flake::kv_to_span(&input);
 
-        let indentation = crate::cli::cmd::add::flake::indentation_from_from_span(
-            &new_flake_contents,
-            &from_span,
-        )?;
+        let index = crate::cli::cmd::add::flake::LineIndex::new(&new_flake_contents);
+        let indentation =
+            crate::cli::cmd::add::flake::indentation_from_from_span(&index, &from_span)?;
         let insertion_pos = nixel::Position {
             line: from_span.start.line,
             column: indentation.len() + 1, // since the indentation is already there
         };
-        let offset =
-            crate::cli::cmd::add::flake::position_to_offset(&new_flake_contents, &insertion_pos)?;
-
-        let start =
-            crate::cli::cmd::add::flake::position_to_offset(&new_flake_contents, &from_span.start)?;
-        let end =
-            crate::cli::cmd::add::flake::position_to_offset(&new_flake_contents, &to_span.end)?;
+        let offset = index.offset(&insertion_pos)?;
+        let start = index.offset(&from_span.start)?;
+        let end = index.offset(&to_span.end)?;
         new_flake_contents.replace_range(start..=end, "");
 
         let inputs_attr = crate::cli::cmd::add::flake::find_first_attrset_by_path(
@@ -318,16 +919,66 @@ impl ConvertSubcommand {
             }
         }
 
-        Ok(new_flake_contents)
+        Ok((new_flake_contents, flake_input_value))
+    }
+
+    /// Writes the converted flake (to `--output-path`, if given) and locks it, updating the
+    /// `converted` inputs with `--lock`. Returns the files that changed.
+    async fn write_flake(
+        &self,
+        flake_contents: &str,
+        new_flake_contents: String,
+        converted: &[&str],
+    ) -> color_eyre::Result<Vec<PathBuf>> {
+        if let Some(output_path) = &self.output_path {
+            tokio::fs::write(output_path, new_flake_contents)
+                .await
+                .wrap_err_with(|| format!("Failed to write {}", output_path.display()))?;
+            return Ok(vec![output_path.clone()]);
+        }
+
+        let mut changed = Vec::new();
+        if new_flake_contents != flake_contents {
+            changed.push(self.flake.path.clone());
+        }
+
+        if self.backup {
+            crate::cli::cmd::add::backup_flake(&self.flake.path, flake_contents, self.backup_mode)
+                .await?;
+        }
+        tokio::fs::write(&self.flake.path, new_flake_contents).await?;
+
+        let lock = async {
+            if self.lock {
+                if !converted.is_empty() {
+                    crate::cli::cmd::add::lock_flake_inputs(&self.flake.path, converted).await?;
+                }
+            } else if !self.no_lock {
+                crate::cli::cmd::add::lock_flake(&self.flake.path).await?;
+            }
+            Ok(())
+        };
+        changed.extend(crate::cli::cmd::add::changed_flake_lock(&self.flake.path, lock).await?);
+
+        Ok(changed)
     }
 
-    async fn fixup_flake_compat_nix_files(&self) -> color_eyre::Result<()> {
-        let shell_nix_path = PathBuf::from(SHELL_NIX);
-        let default_nix_path = PathBuf::from(DEFAULT_NIX);
+    /// Fixes up the `default.nix` and `shell.nix` in `dir` (the working directory, if it's empty).
+    /// Returns the files that were rewritten, or would have been with `dry_run`.
+    async fn fixup_flake_compat_nix_files(
+        &self,
+        dir: &Path,
+        dry_run: bool,
+    ) -> color_eyre::Result<Vec<PathBuf>> {
+        let shell_nix_path = dir.join(SHELL_NIX);
+        let default_nix_path = dir.join(DEFAULT_NIX);
         let mut shell_nix_clean = true;
         let mut default_nix_clean = true;
+        let mut rewritten = Vec::new();
 
         let git_toplevel = tokio::process::Command::new("git")
+            .arg("-C")
+            .arg(dir)
             .args(["rev-parse", "--show-toplevel"])
             .stdout(Stdio::null())
             .stderr(Stdio::null())
@@ -338,21 +989,27 @@ impl ConvertSubcommand {
 
         if is_a_git_repo {
             let files = tokio::process::Command::new("git")
-                .args(["ls-files ", "--modified ", "--full-name"])
+                .arg("-C")
+                .arg(dir)
+                .args(GIT_LS_MODIFIED_ARGS)
                 .stdout(Stdio::piped())
                 .stderr(Stdio::piped())
                 .stdin(Stdio::null())
                 .output()
                 .await?;
-            let output = std::str::from_utf8(&files.stdout)?;
-
-            for line in output.lines() {
-                if line.contains("shell.nix") {
-                    shell_nix_clean = false;
-                }
-                if line.contains("default.nix") {
-                    default_nix_clean = false;
-                }
+            if files.status.success() {
+                let output = std::str::from_utf8(&files.stdout)?;
+                shell_nix_clean = !is_modified(output, SHELL_NIX);
+                default_nix_clean = !is_modified(output, DEFAULT_NIX);
+            } else {
+                // Don't risk clobbering uncommitted changes if we can't tell whether there are any.
+                tracing::warn!(
+                    "`git {}` failed: {}",
+                    GIT_LS_MODIFIED_ARGS.join(" "),
+                    String::from_utf8_lossy(&files.stderr).trim()
+                );
+                shell_nix_clean = false;
+                default_nix_clean = false;
             }
         }
 
@@ -366,7 +1023,10 @@ impl ConvertSubcommand {
                         "We recommend you update the contents of your {SHELL_NIX} to use the flake-compat pinned in your flake:\n{contents}"
                     );
                 } else {
-                    tokio::fs::write(shell_nix_path, contents).await?;
+                    if !dry_run {
+                        tokio::fs::write(&shell_nix_path, contents).await?;
+                    }
+                    rewritten.push(shell_nix_path);
                 }
             }
         }
@@ -381,57 +1041,274 @@ impl ConvertSubcommand {
                         "We recommend you update the contents of your {DEFAULT_NIX} to use the flake-compat pinned in your flake:\n{contents}"
                     );
                 } else {
-                    tokio::fs::write(default_nix_path, contents).await?;
+                    if !dry_run {
+                        tokio::fs::write(&default_nix_path, contents).await?;
+                    }
+                    rewritten.push(default_nix_path);
                 }
             }
         }
 
-        Ok(())
+        Ok(rewritten)
     }
 }
 
-// FIXME: only supports strings for now
-#[tracing::instrument(skip_all)]
-// TODO: return the span as well
-pub(crate) fn find_input_value_by_path(
-    expr: &nixel::Expression,
-    attr_path: VecDeque<String>,
-    // FIXME: return a url::Url...?
-) -> color_eyre::Result<Option<String>> {
-    let mut found_value = None;
+/// The flake at `flake_path`, followed by the flake.nix files in the subdirectories of its
+/// directory (sorted by path). Symlinks aren't followed, so there's no way to loop back around, and
+/// directories ignored by `.gitignore` (or hidden ones, like `.git`) are skipped.
+fn find_flakes(flake_path: &Path) -> color_eyre::Result<Vec<PathBuf>> {
+    let flake_dir = flake_path
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or_else(|| Path::new("."));
+    let toplevel = flake_path.canonicalize()?;
+
+    let mut subflakes = Vec::new();
+    for entry in ignore::WalkBuilder::new(flake_dir)
+        .follow_links(false)
+        .require_git(false)
+        .build()
+    {
+        let entry = entry?;
+        if entry.depth() == 0
+            || entry.file_name() != "flake.nix"
+            || !entry
+                .file_type()
+                .is_some_and(|file_type| file_type.is_file())
+        {
+            continue;
+        }
+        if entry.path().canonicalize()? == toplevel {
+            continue;
+        }
 
-    match expr {
-        nixel::Expression::Map(map) => {
-            for binding in map.bindings.iter() {
-                match binding {
-                    nixel::Binding::KeyValue(kv) => {
-                        // Transform `inputs.nixpkgs.url` into `["inputs", "nixpkgs", "url"]`
-                        let mut this_attr_path: VecDeque<(String, &nixel::PartRaw)> = kv
-                            .from
-                            .iter()
-                            .filter_map(|attr| match attr {
-                                nixel::Part::Raw(raw) => Some((raw.content.to_string(), raw)),
-                                _ => None,
-                            })
-                            .collect();
+        subflakes.push(entry.into_path());
+    }
+    subflakes.sort();
 
-                        let mut search_attr_path = attr_path.clone();
-                        let mut most_recent_attr_matched = false;
+    Ok([flake_path.to_path_buf()]
+        .into_iter()
+        .chain(subflakes)
+        .collect())
+}
 
-                        // Find the correct attr path to modify
-                        while let Some(attr1) = search_attr_path.pop_front() {
-                            if let Some((attr2, attr2_raw)) = this_attr_path.pop_front() {
-                                // For every key in the attr path we're searching for we check that
-                                // we have a matching attr key in the current attrset.
-                                if attr1 != attr2 {
-                                    most_recent_attr_matched = false;
+/// Lists the files with uncommitted changes in the current directory (and below).
+const GIT_LS_MODIFIED_ARGS: [&str; 3] = ["ls-files", "--modified", "--full-name"];
 
-                                    // We want `this_attr_path` to contain all the attr path keys
-                                    // that didn't match the attr path we're looking for, so we can
-                                    // know when it matched as many of the attr paths as possible
-                                    // (when `this_attr_path` is empty).
-                                    this_attr_path.push_front((attr2, attr2_raw));
-                                } else {
+/// Whether a file named `file_name` appears in the output of `git ls-files --modified`.
+fn is_modified(ls_files_output: &str, file_name: &str) -> bool {
+    ls_files_output
+        .lines()
+        .any(|line| Path::new(line).file_name() == Some(file_name.as_ref()))
+}
+
+/// Looks up projects on FlakeHub on behalf of `fh convert` (or resolves them without FlakeHub, with
+/// `--offline`).
+pub(crate) struct FlakeHubLookup<'a> {
+    api_addr: &'a url::Url,
+    strip_tar_gz: bool,
+    token: Option<&'a str>,
+    offline: bool,
+    mappings: &'a [ProjectMapping],
+    archive_format: crate::cli::cmd::add::ArchiveFormat,
+    min_nixpkgs_release: NixpkgsRelease,
+}
+
+impl<'a> FlakeHubLookup<'a> {
+    pub(crate) fn new(
+        api_addr: &'a url::Url,
+        strip_tar_gz: bool,
+        token: Option<&'a str>,
+        offline: bool,
+    ) -> Self {
+        Self {
+            api_addr,
+            strip_tar_gz,
+            token,
+            offline,
+            mappings: &[],
+            archive_format: crate::cli::cmd::add::ArchiveFormat::TarGz,
+            min_nixpkgs_release: NixpkgsRelease::FIRST_FLAKE,
+        }
+    }
+
+    /// Looks the GitHub repositories in `mappings` up under their FlakeHub names instead.
+    pub(crate) fn with_mappings(mut self, mappings: &'a [ProjectMapping]) -> Self {
+        self.mappings = mappings;
+        self
+    }
+
+    /// Builds URLs of archives in `archive_format` instead of tarballs.
+    pub(crate) fn with_archive_format(
+        mut self,
+        archive_format: crate::cli::cmd::add::ArchiveFormat,
+    ) -> Self {
+        self.archive_format = archive_format;
+        self
+    }
+
+    /// Leaves Nixpkgs release branches older than `min_nixpkgs_release` alone.
+    pub(crate) fn with_min_nixpkgs_release(mut self, min_nixpkgs_release: NixpkgsRelease) -> Self {
+        self.min_nixpkgs_release = min_nixpkgs_release;
+        self
+    }
+
+    /// The FlakeHub org and project of the GitHub repository `org/project`, which are the same
+    /// unless `--map` says otherwise. GitHub names are case-insensitive, so the match is too.
+    fn flakehub_project<'b>(&'b self, org: &'b str, project: &'b str) -> (&'b str, &'b str) {
+        self.mappings
+            .iter()
+            .find(|mapping| {
+                mapping.github.0.eq_ignore_ascii_case(org)
+                    && mapping.github.1.eq_ignore_ascii_case(project)
+            })
+            .map(|mapping| (mapping.flakehub.0.as_str(), mapping.flakehub.1.as_str()))
+            .unwrap_or((org, project))
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub(crate) async fn project_and_url(
+        &self,
+        org: &str,
+        project: &str,
+        version: Option<&str>,
+    ) -> color_eyre::Result<(String, url::Url)> {
+        if self.offline {
+            return self.offline_project_and_url(org, project, version);
+        }
+
+        crate::cli::cmd::add::get_flakehub_project_and_url(
+            self.api_addr,
+            org,
+            project,
+            version,
+            self.strip_tar_gz,
+            self.archive_format,
+            self.token,
+        )
+        .await
+    }
+
+    /// Builds the URL of an exact release without asking FlakeHub. Anything else (e.g. the latest
+    /// version, or a version requirement) can only be resolved by FlakeHub, so it's an error.
+    fn offline_project_and_url(
+        &self,
+        org: &str,
+        project: &str,
+        version: Option<&str>,
+    ) -> color_eyre::Result<(String, url::Url)> {
+        let Some(version) = version.filter(|version| semver::Version::parse(version).is_ok())
+        else {
+            tracing::warn!(
+                "not converting {org}/{project}, since that requires looking it up on FlakeHub"
+            );
+            return Err(color_eyre::eyre::eyre!(
+                "cannot resolve {org}/{project} without contacting FlakeHub"
+            ));
+        };
+
+        let mut url = format!("https://flakehub.com/f/{org}/{project}/{version}");
+        if !self.strip_tar_gz {
+            url.push_str(self.archive_format.suffix());
+        }
+
+        Ok((project.to_string(), url.parse()?))
+    }
+
+    /// Asks FlakeHub which published version, if any, the Git tag `tag` corresponds to.
+    #[tracing::instrument(skip(self))]
+    pub(crate) async fn resolve_tag(
+        &self,
+        org: &str,
+        project: &str,
+        tag: &str,
+    ) -> color_eyre::Result<Option<String>> {
+        let client =
+            crate::cli::cmd::FlakeHubClient::with_saved_token(self.api_addr, self.token).await?;
+
+        Ok(client.resolve_tag(org, project, tag).await?)
+    }
+}
+
+/// Renders a unified diff between the original and converted flake.nix contents.
+fn unified_diff(flake_path: &Path, old_contents: &str, new_contents: &str) -> String {
+    let path = flake_path.display().to_string();
+
+    similar::TextDiff::from_lines(old_contents, new_contents)
+        .unified_diff()
+        .header(&path, &path)
+        .to_string()
+}
+
+fn print_diff(flake_path: &Path, old_contents: &str, new_contents: &str) {
+    let diff = unified_diff(flake_path, old_contents, new_contents);
+
+    if !std::io::stdout().is_terminal() {
+        print!("{diff}");
+        return;
+    }
+
+    for line in diff.lines() {
+        if line.starts_with("+++") || line.starts_with("---") {
+            println!("{}", line.bold());
+        } else if line.starts_with('+') {
+            println!("{}", line.green());
+        } else if line.starts_with('-') {
+            println!("{}", line.red());
+        } else if line.starts_with("@@") {
+            println!("{}", line.cyan());
+        } else {
+            println!("{line}");
+        }
+    }
+}
+
+/// Finds the literal string value at `attr_path` inside `expr`.
+///
+/// Values that can't be known without evaluating the flake (e.g. interpolated strings or
+/// variables) are reported with a warning and treated as not found, so that callers can skip those
+/// inputs instead of aborting.
+#[tracing::instrument(skip_all)]
+// TODO: return the span as well
+pub(crate) fn find_input_value_by_path(
+    expr: &nixel::Expression,
+    attr_path: VecDeque<String>,
+    // FIXME: return a url::Url...?
+) -> color_eyre::Result<Option<String>> {
+    let mut found_value = None;
+
+    match crate::cli::cmd::add::flake::unwrap_expression(expr) {
+        nixel::Expression::Map(map) => {
+            for binding in map.bindings.iter() {
+                match binding {
+                    nixel::Binding::KeyValue(kv) => {
+                        // Transform `inputs.nixpkgs.url` into `["inputs", "nixpkgs", "url"]`
+                        let mut this_attr_path: VecDeque<(String, &nixel::Part)> = kv
+                            .from
+                            .iter()
+                            .filter_map(|attr| {
+                                crate::cli::cmd::add::flake::attr_name(attr)
+                                    .map(|name| (name.to_string(), attr))
+                            })
+                            .collect();
+
+                        let mut search_attr_path = attr_path.clone();
+                        let mut most_recent_attr_matched = false;
+
+                        // Find the correct attr path to modify
+                        while let Some(attr1) = search_attr_path.pop_front() {
+                            if let Some((attr2, attr2_raw)) = this_attr_path.pop_front() {
+                                // For every key in the attr path we're searching for we check that
+                                // we have a matching attr key in the current attrset.
+                                if attr1 != attr2 {
+                                    most_recent_attr_matched = false;
+
+                                    // We want `this_attr_path` to contain all the attr path keys
+                                    // that didn't match the attr path we're looking for, so we can
+                                    // know when it matched as many of the attr paths as possible
+                                    // (when `this_attr_path` is empty).
+                                    this_attr_path.push_front((attr2, attr2_raw));
+                                } else {
                                     most_recent_attr_matched = true;
                                 }
                             } else {
@@ -460,50 +1337,69 @@ pub(crate) fn find_input_value_by_path(
                             continue;
                         }
                     }
-                    nixel::Binding::Inherit(inherit) => {
-                        let start = &inherit.span.start;
-                        return Err(color_eyre::eyre::eyre!(
-                            "`inherit` not supported (at {}:{})",
-                            start.line,
-                            start.column
-                        ));
-                    }
+                    nixel::Binding::Inherit(inherit) => match attr_path.front() {
+                        Some(attr)
+                            if crate::cli::cmd::add::flake::inherit_binds_attr(inherit, attr) =>
+                        {
+                            return Err(crate::cli::cmd::add::flake::UnsupportedExpression::new(
+                                format!("`{attr}` is bound with `inherit`, which is not supported"),
+                                (*inherit.span.start).clone(),
+                            )
+                            .into());
+                        }
+                        _ => {
+                            tracing::debug!("Skipping unrelated `inherit` binding");
+                        }
+                    },
                 }
             }
         }
-        nixel::Expression::String(s) => {
-            found_value = s.parts.first().and_then(|part| match part {
-                nixel::Part::Raw(raw) => Some(raw.content.trim().to_string()),
-                _ => None,
-            });
-        }
-        nixel::Expression::IndentedString(s) => {
-            found_value = s.parts.first().and_then(|part| match part {
-                nixel::Part::Raw(raw) => Some(raw.content.trim().to_string()),
-                _ => None,
-            });
+        nixel::Expression::String(nixel::String_ { parts, span })
+        | nixel::Expression::IndentedString(nixel::IndentedString { parts, span }) => {
+            found_value = literal_string_value(parts);
+
+            if found_value.is_none() {
+                tracing::warn!(
+                    "skipping a string that uses interpolation, which is not supported (at {}:{})",
+                    span.start.line,
+                    span.start.column
+                );
+            }
         }
         nixel::Expression::Uri(u) => {
             found_value = Some(u.uri.trim().to_string());
         }
         t => {
             let start = t.start();
-            return Err(color_eyre::eyre::eyre!(
-                "unsupported expression type {} (at {}:{})",
+            tracing::warn!(
+                "skipping unsupported expression type {} (at {}:{})",
                 t.variant_name(),
                 start.line,
                 start.column
-            ));
+            );
         }
     }
 
     Ok(found_value)
 }
 
+/// The contents of a string, if it is made up solely of literal (i.e. non-interpolated) parts.
+fn literal_string_value(parts: &[nixel::Part]) -> Option<String> {
+    parts
+        .iter()
+        .map(|part| match part {
+            nixel::Part::Raw(raw) => Some(&*raw.content),
+            _ => None,
+        })
+        .collect::<Option<String>>()
+        .map(|value| value.trim().to_string())
+}
+
 #[tracing::instrument(skip_all)]
 async fn convert_input_to_flakehub(
-    api_addr: &url::Url,
+    lookup: &FlakeHubLookup<'_>,
     parsed_url: url::Url,
+    include_https: bool,
 ) -> color_eyre::Result<Option<url::Url>> {
     let mut url = None;
 
@@ -515,11 +1411,35 @@ async fn convert_input_to_flakehub(
                 mod_url.set_host(Some("flakehub.com"))?;
                 url = Some(mod_url);
             } else {
-                match parsed_url.scheme() {
-                    "https" => {
-                        tracing::debug!("https://... urls are not yet implented");
+                match (parsed_url.scheme(), host) {
+                    ("https" | "git+https", url::Host::Domain("github.com")) => {
+                        if include_https {
+                            url =
+                                convert_github_https_input_to_flakehub(&parsed_url, lookup).await?;
+                        } else {
+                            tracing::warn!(
+                                "not converting {parsed_url} without `--include-https`, skipping"
+                            );
+                        }
                     }
-                    scheme => {
+                    ("ssh" | "git+ssh", url::Host::Domain("github.com")) => {
+                        url = convert_github_https_input_to_flakehub(&parsed_url, lookup).await?;
+
+                        // Inputs fetched over SSH are often private, and thus likely not on
+                        // FlakeHub, so let the user know why they were left alone.
+                        if url.is_none() {
+                            tracing::warn!(
+                                "couldn't convert {parsed_url} to FlakeHub (it may be private, or not published there), skipping"
+                            );
+                        }
+                    }
+                    ("git+https", url::Host::Domain("git.sr.ht")) => {
+                        url = convert_sourcehut_input_to_flakehub(&parsed_url, lookup).await?;
+                    }
+                    ("https", _) => {
+                        tracing::debug!("https://... urls are only implemented for github.com");
+                    }
+                    (scheme, _) => {
                         tracing::debug!("unimplemented url scheme {scheme}");
                     }
                 }
@@ -528,7 +1448,14 @@ async fn convert_input_to_flakehub(
         // A URL like `github:nixos/nixpkgs`
         None => match parsed_url.scheme() {
             "github" => {
-                url = convert_github_input_to_flakehub(parsed_url, api_addr).await?;
+                url = convert_github_input_to_flakehub(parsed_url, lookup).await?;
+            }
+            "sourcehut" => {
+                url = convert_sourcehut_input_to_flakehub(&parsed_url, lookup).await?;
+            }
+            // A URL like `flake:nixpkgs`, which refers to the flake registry
+            "flake" => {
+                url = convert_registry_input_to_flakehub(&parsed_url, lookup).await?;
             }
             scheme => {
                 tracing::debug!("unimplemented flake input scheme {scheme}");
@@ -539,27 +1466,225 @@ async fn convert_input_to_flakehub(
     Ok(url)
 }
 
+#[tracing::instrument(skip_all)]
+async fn convert_sourcehut_input_to_flakehub(
+    parsed_url: &url::Url,
+    lookup: &FlakeHubLookup<'_>,
+) -> color_eyre::Result<Option<url::Url>> {
+    let (owner, project, mut maybe_version) = match parsed_url
+        .path()
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .collect::<Vec<_>>()[..]
+    {
+        // `sourcehut:~owner/repo/v1.0.0`
+        [owner, project, version] => (owner, project, Some(version.to_string())),
+        // `sourcehut:~owner/repo` or `git+https://git.sr.ht/~owner/repo`
+        [owner, project] => (owner, project, None),
+        _ => {
+            tracing::debug!("sourcehut url was not of the form ~[owner]/[project], skipping");
+            return Ok(None);
+        }
+    };
+
+    let mut dir = None;
+    for (key, value) in parsed_url.query_pairs() {
+        match key.as_ref() {
+            "ref" => maybe_version = Some(value.into_owned()),
+            "dir" => dir = Some(value.into_owned()),
+            other => {
+                tracing::debug!("unsupported query parameter '{other}', skipping");
+                return Ok(None);
+            }
+        }
+    }
+
+    // Sourcehut owners are prefixed with a `~`, FlakeHub orgs aren't.
+    let org = owner.strip_prefix('~').unwrap_or(owner);
+    let project = project.strip_suffix(".git").unwrap_or(project);
+
+    let version = match maybe_version {
+        Some(version_or_branch) => {
+            let version = version_or_branch
+                .strip_prefix('v')
+                .unwrap_or(&version_or_branch);
+            match semver::Version::parse(version) {
+                Ok(version) => Some(version.to_string()),
+                Err(_) => {
+                    tracing::debug!(
+                        "input was not of the form ~[owner]/[project]/[semver], skipping"
+                    );
+                    return Ok(None);
+                }
+            }
+        }
+        None => None,
+    };
+
+    match lookup
+        .project_and_url(org, project, version.as_deref())
+        .await
+    {
+        Ok((_, mut flakehub_url)) => {
+            // Preserve the subflake the input pointed to, if any
+            if let Some(dir) = dir {
+                flakehub_url.query_pairs_mut().append_pair("dir", &dir);
+            }
+
+            Ok(Some(flakehub_url))
+        }
+        Err(e) => {
+            tracing::warn!(
+                "{org}/{project} doesn't seem to be published on FlakeHub, skipping: {e}"
+            );
+            Ok(None)
+        }
+    }
+}
+
+#[tracing::instrument(skip_all)]
+async fn convert_github_https_input_to_flakehub(
+    parsed_url: &url::Url,
+    lookup: &FlakeHubLookup<'_>,
+) -> color_eyre::Result<Option<url::Url>> {
+    let Some(path_segments) = parsed_url.path_segments() else {
+        return Ok(None);
+    };
+
+    let (org, project) = match path_segments
+        .filter(|segment| !segment.is_empty())
+        .collect::<Vec<_>>()[..]
+    {
+        // `https://github.com/nixos/nixpkgs`, `git+https://github.com/nixos/nixpkgs.git`, or
+        // `git+ssh://git@github.com/nixos/nixpkgs.git`
+        [org, project] => (org, project.strip_suffix(".git").unwrap_or(project)),
+        _ => {
+            tracing::debug!("github.com url was not of the form [org]/[project], skipping");
+            return Ok(None);
+        }
+    };
+
+    let mut git_ref = None;
+    let mut rev = None;
+    let mut dir = None;
+    for (key, value) in parsed_url.query_pairs() {
+        match key.as_ref() {
+            "ref" => git_ref = Some(value.into_owned()),
+            "rev" => rev = Some(value.into_owned()),
+            "dir" => dir = Some(value.into_owned()),
+            // e.g. `submodules=1`, which FlakeHub tarballs can't honor
+            other => {
+                tracing::debug!("unsupported query parameter '{other}', skipping");
+                return Ok(None);
+            }
+        }
+    }
+
+    // A `rev` pins an exact commit, so it takes precedence over any `ref`.
+    let version_or_branch = rev.or(git_ref.map(|git_ref| {
+        git_ref
+            .trim_start_matches("refs/heads/")
+            .trim_start_matches("refs/tags/")
+            .to_string()
+    }));
+
+    let mut github_url = format!("github:{org}/{project}");
+    if let Some(version_or_branch) = version_or_branch {
+        github_url.push('/');
+        github_url.push_str(&version_or_branch);
+    }
+    let mut github_url: url::Url = github_url.parse()?;
+    if let Some(dir) = dir {
+        github_url.query_pairs_mut().append_pair("dir", &dir);
+    }
+
+    convert_github_input_to_flakehub(github_url, lookup).await
+}
+
+/// Well-known names from the default flake registry, and the GitHub repos they point at.
+const REGISTRY_FLAKES: &[(&str, &str)] = &[
+    ("nixpkgs", "NixOS/nixpkgs"),
+    ("nix", "NixOS/nix"),
+    ("flake-utils", "numtide/flake-utils"),
+    ("home-manager", "nix-community/home-manager"),
+    ("nix-darwin", "LnL7/nix-darwin"),
+];
+
+#[tracing::instrument(skip_all)]
+async fn convert_registry_input_to_flakehub(
+    parsed_url: &url::Url,
+    lookup: &FlakeHubLookup<'_>,
+) -> color_eyre::Result<Option<url::Url>> {
+    // `flake:nixpkgs` or `flake:nixpkgs/nixos-23.05`
+    let (name, rest) = parsed_url
+        .path()
+        .split_once('/')
+        .map_or((parsed_url.path(), None), |(name, rest)| (name, Some(rest)));
+
+    let Some((_, repo)) = REGISTRY_FLAKES
+        .iter()
+        .find(|(registry_name, _)| *registry_name == name)
+    else {
+        tracing::warn!(
+            "don't know which flake the registry name `{name}` refers to, not converting {parsed_url}"
+        );
+        return Ok(None);
+    };
+
+    let mut github_url = format!("github:{repo}");
+    if let Some(rest) = rest {
+        github_url.push('/');
+        github_url.push_str(rest);
+    }
+    let mut github_url: url::Url = github_url.parse()?;
+    github_url.set_query(parsed_url.query());
+
+    convert_github_input_to_flakehub(github_url, lookup).await
+}
+
 #[tracing::instrument(skip_all)]
 async fn convert_github_input_to_flakehub(
     parsed_url: url::Url,
-    api_addr: &url::Url,
+    lookup: &FlakeHubLookup<'_>,
 ) -> color_eyre::Result<Option<url::Url>> {
     let mut url = None;
 
+    // `github:nixos/nixpkgs?ref=nixos-23.05`; as with GitHub URLs, a `rev` takes precedence
+    let query_version_or_branch = ["rev", "ref"].iter().find_map(|key| {
+        parsed_url
+            .query_pairs()
+            .find(|(query_key, _)| query_key == key)
+            .map(|(_, value)| {
+                value
+                    .trim_start_matches("refs/heads/")
+                    .trim_start_matches("refs/tags/")
+                    .to_string()
+            })
+    });
+
     let (org, project, maybe_version_or_branch) =
         match parsed_url.path().split('/').collect::<Vec<_>>()[..] {
             // `nixos/nixpkgs/nixos-23.05`
             [org, project, maybe_version_or_branch] => {
                 (org, project, Some(maybe_version_or_branch))
             }
-            // `nixos/nixpkgs`
-            [org, project] => (org, project, None),
+            // `nixos/nixpkgs`, or `nixos/nixpkgs?ref=nixos-23.05`
+            [org, project] => (org, project, query_version_or_branch.as_deref()),
             _ => Err(color_eyre::eyre::eyre!(
                 "flakehub input did not match the expected format of `org/project` or
                 `org/project/version`"
             ))?,
         };
 
+    // The GitHub names decide how to convert the input (e.g. whether it's Nixpkgs), but it's looked
+    // up on FlakeHub under the name it has there
+    let (flakehub_org, flakehub_project) = lookup.flakehub_project(org, project);
+
+    // A commit isn't a tag, so there's no point in asking FlakeHub about it
+    let pinned_to_rev = parsed_url.query_pairs().any(|(key, _)| key == "rev")
+        || maybe_version_or_branch
+            .is_some_and(|rev| rev.len() == 40 && rev.chars().all(|c| c.is_ascii_hexdigit()));
+
     match maybe_version_or_branch {
         Some(version_or_branch) => {
             // github:{org}/{repo}/{something} if {something} parses as a semver tag -> flakehub.com/{org}/{repo}/{something}.tar.gz
@@ -568,16 +1693,16 @@ async fn convert_github_input_to_flakehub(
                     .strip_prefix('v')
                     .unwrap_or(version_or_branch),
             ) {
-                if let Ok((_, flakehub_url)) = crate::cli::cmd::add::get_flakehub_project_and_url(
-                    api_addr,
-                    org,
-                    project,
-                    Some(&version.to_string()),
-                )
-                .await
+                if let Ok((_, flakehub_url)) = lookup
+                    .project_and_url(flakehub_org, flakehub_project, Some(&version.to_string()))
+                    .await
                 {
                     url = Some(flakehub_url);
                 }
+            } else if lookup.offline {
+                tracing::warn!(
+                    "not converting {org}/{project}/{version_or_branch}, since resolving a branch requires looking it up on FlakeHub"
+                );
             // - has nixpkgs:
             } else if (org.to_lowercase().as_ref(), project.to_lowercase().as_ref())
                 == ("nixos", "nixpkgs")
@@ -593,38 +1718,34 @@ async fn convert_github_input_to_flakehub(
                 match branch {
                     //   - nixpkgs-unstable and nixos-unstable -> flakehub.com/f/nixos/nixpkgs/0.1.0.tar.gz
                     "nixpkgs-unstable" | "nixos-unstable" => {
-                        if let Ok((_, flakehub_url)) =
-                            crate::cli::cmd::add::get_flakehub_project_and_url(
-                                api_addr,
-                                org,
-                                project,
-                                Some("0.1.0"),
-                            )
+                        if let Ok((_, flakehub_url)) = lookup
+                            .project_and_url(flakehub_org, flakehub_project, Some("0.1.0"))
                             .await
                         {
                             url = Some(flakehub_url);
                         }
                     }
                     _ => {
-                        //   - nixos-{yy}.{mm} -> flakehub.com/f/nixos/nixpkgs/0.{yymm}.0.tar.gz IFF {yymm} >= 2003
+                        //   - nixos-{yy}.{mm} -> flakehub.com/f/nixos/nixpkgs/0.{yymm}.0.tar.gz IFF {yy}.{mm} >= --min-nixpkgs-release
                         if let Some(captures) = release_branch_captures {
                             // Unwraps here are safe because we're guaranteed to have them if
                             // the captures object is Some(_)
                             let year_str = captures.name("year").unwrap().as_str();
                             let month_str = captures.name("month").unwrap().as_str();
-                            let year: u64 = year_str.parse()?;
-                            let month: u64 = month_str.parse()?;
-
-                            // NixOS 20.03 and later have a flake.nix
-                            if year >= 20 && month >= 3 {
+                            let release = NixpkgsRelease {
+                                year: year_str.parse()?,
+                                month: month_str.parse()?,
+                            };
+
+                            if release < lookup.min_nixpkgs_release {
+                                tracing::warn!(
+                                    "not converting {org}/{project}/{version_or_branch}, since Nixpkgs {release} is older than --min-nixpkgs-release {}",
+                                    lookup.min_nixpkgs_release
+                                );
+                            } else {
                                 let version = format!("0.{year_str}{month_str}.0");
-                                if let Ok((_, flakehub_url)) =
-                                    crate::cli::cmd::add::get_flakehub_project_and_url(
-                                        api_addr,
-                                        org,
-                                        project,
-                                        Some(&version),
-                                    )
+                                if let Ok((_, flakehub_url)) = lookup
+                                    .project_and_url(flakehub_org, flakehub_project, Some(&version))
                                     .await
                                 {
                                     url = Some(flakehub_url);
@@ -637,16 +1758,32 @@ async fn convert_github_input_to_flakehub(
                         }
                     }
                 }
+            } else if pinned_to_rev {
+                tracing::debug!("input was pinned to a commit, skipping");
             } else {
-                // github:{org}/{repo}/{something} fallthrough -> warn and do nothing
-                tracing::debug!("input was not of the form [org]/[project]/[semver], skipping");
+                // github:{org}/{repo}/{tag} where FlakeHub published {tag} as some version -> flakehub.com/f/{org}/{repo}/{version}.tar.gz
+                match lookup.resolve_tag(flakehub_org, flakehub_project, version_or_branch).await {
+                    Ok(Some(version)) => {
+                        if let Ok((_, flakehub_url)) =
+                            lookup.project_and_url(flakehub_org, flakehub_project, Some(&version)).await
+                        {
+                            url = Some(flakehub_url);
+                        }
+                    }
+                    Ok(None) => tracing::warn!(
+                        "not converting {org}/{project}/{version_or_branch}, since it isn't a version published on FlakeHub"
+                    ),
+                    Err(e) => tracing::warn!(
+                        "not converting {org}/{project}/{version_or_branch}, since looking it up on FlakeHub failed: {e}"
+                    ),
+                }
             }
         }
         None => {
             // github:{org}/{repo} -> flakehub.com/f/{org}/{repo}/x.y.z.tar.gz (where x.y.z is the currently-latest version)
-            if let Ok((_, flakehub_url)) =
-                crate::cli::cmd::add::get_flakehub_project_and_url(api_addr, org, project, None)
-                    .await
+            if let Ok((_, flakehub_url)) = lookup
+                .project_and_url(flakehub_org, flakehub_project, None)
+                .await
             {
                 url = Some(flakehub_url);
             } else {
@@ -655,6 +1792,13 @@ async fn convert_github_input_to_flakehub(
         }
     }
 
+    // Preserve the subflake the input pointed to, if any
+    if let Some(url) = url.as_mut() {
+        if let Some((_, dir)) = parsed_url.query_pairs().find(|(key, _)| key == "dir") {
+            url.query_pairs_mut().append_pair("dir", &dir);
+        }
+    }
+
     Ok(url)
 }
 
@@ -662,12 +1806,77 @@ async fn convert_github_input_to_flakehub(
 mod test {
     use axum::{extract::Path, response::IntoResponse};
 
+    #[test]
+    fn test_is_modified() {
+        let output = "shell.nix\nnix/default.nix\n";
+        assert!(super::is_modified(output, super::SHELL_NIX));
+        assert!(super::is_modified(output, super::DEFAULT_NIX));
+        assert!(!super::is_modified(
+            "my-shell.nix\nflake.nix\n",
+            super::SHELL_NIX
+        ));
+        assert!(!super::is_modified("", super::DEFAULT_NIX));
+    }
+
+    #[test]
+    fn test_git_ls_modified_args() {
+        let dir = std::env::temp_dir().join(format!("fh-ls-files-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let git = |args: &[&str]| {
+            std::process::Command::new("git")
+                .args(["-c", "user.name=fh", "-c", "user.email=fh@example.com"])
+                .args(args)
+                .current_dir(&dir)
+                .output()
+                .unwrap()
+        };
+
+        git(&["init", "--quiet"]);
+        std::fs::write(dir.join(super::SHELL_NIX), "old").unwrap();
+        std::fs::write(dir.join(super::DEFAULT_NIX), "old").unwrap();
+        git(&["add", "."]);
+        git(&["commit", "--quiet", "-m", "init"]);
+        std::fs::write(dir.join(super::SHELL_NIX), "new").unwrap();
+
+        let output = git(&super::GIT_LS_MODIFIED_ARGS);
+        assert!(output.status.success());
+        let output = String::from_utf8(output.stdout).unwrap();
+        assert!(super::is_modified(&output, super::SHELL_NIX));
+        assert!(!super::is_modified(&output, super::DEFAULT_NIX));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_find_flakes() {
+        let dir = std::env::temp_dir().join(format!("fh-find-flakes-test-{}", std::process::id()));
+        for subdir in ["libs/a", "libs/b/nested", "result/x", ".hidden"] {
+            std::fs::create_dir_all(dir.join(subdir)).unwrap();
+            std::fs::write(dir.join(subdir).join("flake.nix"), "{ }").unwrap();
+        }
+        std::fs::write(dir.join("flake.nix"), "{ }").unwrap();
+        std::fs::write(dir.join(".gitignore"), "result/\n").unwrap();
+        std::os::unix::fs::symlink(&dir, dir.join("libs/a/loop")).unwrap();
+
+        let flakes = super::find_flakes(&dir.join("flake.nix")).unwrap();
+        assert_eq!(
+            flakes,
+            [
+                dir.join("flake.nix"),
+                dir.join("libs/a/flake.nix"),
+                dir.join("libs/b/nested/flake.nix"),
+            ]
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
     async fn version(
         Path((org, project, version)): Path<(String, String, String)>,
     ) -> axum::response::Response {
         axum::Json(serde_json::json!({
             "project": project,
-            "pretty_download_url": format!("http://flakehub-localhost/f/{org}/{project}/{version}.tar.gz"),
+            "pretty_download_url": format!("https://flakehub.com/f/{org}/{project}/{version}.tar.gz"),
         }))
         .into_response()
     }
@@ -675,11 +1884,20 @@ mod test {
     async fn no_version(Path((org, project)): Path<(String, String)>) -> axum::response::Response {
         axum::Json(serde_json::json!({
             "project": project,
-            "pretty_download_url": format!("http://flakehub-localhost/f/{org}/{project}/*.tar.gz"),
+            "pretty_download_url": format!("https://flakehub.com/f/{org}/{project}/*.tar.gz"),
         }))
         .into_response()
     }
 
+    /// Only `release-2023-11` was published, as 1.4.0.
+    async fn tag(Path((_, _, tag)): Path<(String, String, String)>) -> axum::response::Response {
+        if tag == "release-2023-11" {
+            axum::Json(serde_json::json!({ "version": "1.4.0" })).into_response()
+        } else {
+            axum::http::StatusCode::NOT_FOUND.into_response()
+        }
+    }
+
     fn test_router() -> axum::Router {
         axum::Router::new()
             .route(
@@ -687,6 +1905,7 @@ mod test {
                 axum::routing::get(version),
             )
             .route("/f/:org/:project", axum::routing::get(no_version))
+            .route("/f/:org/:project/tag/:tag", axum::routing::get(tag))
     }
 
     #[tokio::test]
@@ -696,11 +1915,15 @@ mod test {
         let server_url = server_addr.parse().unwrap();
 
         let input_url = url::Url::parse("github:someorg/somerepo").unwrap();
-        let tarball_url = super::convert_input_to_flakehub(&server_url, input_url)
-            .await
-            .ok()
-            .flatten()
-            .unwrap();
+        let tarball_url = super::convert_input_to_flakehub(
+            &super::FlakeHubLookup::new(&server_url, false, None, false),
+            input_url,
+            false,
+        )
+        .await
+        .ok()
+        .flatten()
+        .unwrap();
         assert_eq!(tarball_url.path(), "/f/someorg/somerepo/*.tar.gz");
     }
 
@@ -711,77 +1934,317 @@ mod test {
         let server_url = server_addr.parse().unwrap();
 
         let input_url = url::Url::parse("github:nixos/nixpkgs/nixos-23.05").unwrap();
-        let tarball_url = super::convert_input_to_flakehub(&server_url, input_url)
-            .await
-            .ok()
-            .flatten()
-            .unwrap();
+        let tarball_url = super::convert_input_to_flakehub(
+            &super::FlakeHubLookup::new(&server_url, false, None, false),
+            input_url,
+            false,
+        )
+        .await
+        .ok()
+        .flatten()
+        .unwrap();
         assert_eq!(tarball_url.path(), "/f/nixos/nixpkgs/0.2305.0.tar.gz");
     }
 
     #[tokio::test]
-    async fn test_flake1_convert() {
+    async fn nixpkgs_release_keeps_resolved_version() {
+        // FlakeHub answers with the latest release of the requested version
+        async fn resolve_version(
+            axum::extract::Path((org, project, _)): axum::extract::Path<(String, String, String)>,
+        ) -> axum::Json<serde_json::Value> {
+            axum::Json(serde_json::json!({
+                "project": project,
+                "pretty_download_url": format!("https://flakehub.com/f/{org}/{project}/0.2305.490449.tar.gz"),
+            }))
+        }
+
+        let router = axum::Router::new().route(
+            "/version/:org/:project/:version",
+            axum::routing::get(resolve_version),
+        );
+        let test_server = axum_test::TestServer::new(router.into_make_service()).unwrap();
+        let server_url = test_server.server_address().parse().unwrap();
+
+        let input_url = url::Url::parse("github:nixos/nixpkgs/nixos-23.05").unwrap();
+        let tarball_url = super::convert_input_to_flakehub(
+            &super::FlakeHubLookup::new(&server_url, false, None, false),
+            input_url,
+            false,
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(tarball_url.path(), "/f/nixos/nixpkgs/0.2305.490449.tar.gz");
+    }
+
+    #[tokio::test]
+    async fn github_https_to_flakehub() {
         let test_server = axum_test::TestServer::new(test_router().into_make_service()).unwrap();
         let server_addr = test_server.server_address();
         let server_url = server_addr.parse().unwrap();
 
-        let convert = super::ConvertSubcommand {
-            flake_path: "".into(),
-            dry_run: true,
-            api_addr: server_url,
-        };
-        let flake_contents = include_str!(concat!(
-            env!("CARGO_MANIFEST_DIR"),
-            "/samples/flake1.test.nix"
-        ));
-        let flake_contents = flake_contents.to_string();
-        let parsed = nixel::parse(flake_contents.clone());
+        let lookup = super::FlakeHubLookup::new(&server_url, false, None, false);
 
-        let (new_flake_contents, flake_compat_input_name) = convert
-            .convert_inputs_to_flakehub(&parsed.expression, &flake_contents)
-            .await
-            .unwrap();
-        let new_flake_contents = convert
-            .make_implicit_nixpkgs_explicit(&parsed.expression, &new_flake_contents)
+        let input_url = url::Url::parse("https://github.com/someorg/somerepo").unwrap();
+        let tarball_url = super::convert_input_to_flakehub(&lookup, input_url.clone(), true)
             .await
+            .ok()
+            .flatten()
             .unwrap();
-        let new_flake_contents = convert
-            .fixup_flake_compat_input(&new_flake_contents, flake_compat_input_name.unwrap())
+        assert_eq!(tarball_url.path(), "/f/someorg/somerepo/*.tar.gz");
+
+        // Only converted with `--include-https`
+        let tarball_url = super::convert_input_to_flakehub(&lookup, input_url, false)
             .await
             .unwrap();
+        assert!(tarball_url.is_none());
+    }
 
-        assert!(new_flake_contents.contains(
-            r#"flake-compat.url = "http://flakehub-localhost/f/edolstra/flake-compat/*.tar.gz";"#
-        ));
-        assert!(new_flake_contents.contains("f/nixos/nixpkgs/0.2305.0.tar.gz"));
+    #[tokio::test]
+    async fn git_https_with_ref_and_dir_to_flakehub() {
+        let test_server = axum_test::TestServer::new(test_router().into_make_service()).unwrap();
+        let server_addr = test_server.server_address();
+        let server_url = server_addr.parse().unwrap();
 
-        let nixpkgs_url_lines: Vec<_> = new_flake_contents
-            .lines()
-            .filter(|line| {
-                line.contains("nixpkgs.url") && line.contains("f/nixos/nixpkgs/0.2305.0.tar.gz")
-            })
-            .collect();
-        let num_nixpkgs_url_lines = nixpkgs_url_lines.len();
-        assert_eq!(num_nixpkgs_url_lines, 1);
+        let input_url =
+            url::Url::parse("git+https://github.com/nixos/nixpkgs.git?ref=nixos-23.05&dir=lib")
+                .unwrap();
+        let tarball_url = super::convert_input_to_flakehub(
+            &super::FlakeHubLookup::new(&server_url, false, None, false),
+            input_url,
+            true,
+        )
+        .await
+        .ok()
+        .flatten()
+        .unwrap();
+        assert_eq!(tarball_url.path(), "/f/nixos/nixpkgs/0.2305.0.tar.gz");
+        assert_eq!(tarball_url.query(), Some("dir=lib"));
     }
 
     #[tokio::test]
-    async fn test_nixpkgs_from_registry() {
+    async fn git_ssh_to_flakehub() {
         let test_server = axum_test::TestServer::new(test_router().into_make_service()).unwrap();
         let server_addr = test_server.server_address();
         let server_url = server_addr.parse().unwrap();
+        let lookup = super::FlakeHubLookup::new(&server_url, false, None, false);
 
-        let convert = super::ConvertSubcommand {
-            flake_path: "".into(),
-            dry_run: true,
-            api_addr: server_url,
-        };
-        let flake_contents = r#"
-{
-  description = "cole-h's NixOS configuration";
+        let input_url =
+            url::Url::parse("git+ssh://git@github.com/someorg/somerepo.git?ref=v1.2.3").unwrap();
+        let tarball_url = super::convert_input_to_flakehub(&lookup, input_url, false)
+            .await
+            .ok()
+            .flatten()
+            .unwrap();
+        assert_eq!(tarball_url.path(), "/f/someorg/somerepo/1.2.3.tar.gz");
 
-  inputs = {
-    nixpkgs.url = "nixpkgs";
+        let input_url = url::Url::parse("ssh://git@github.com/someorg/somerepo").unwrap();
+        let tarball_url = super::convert_input_to_flakehub(&lookup, input_url, false)
+            .await
+            .ok()
+            .flatten()
+            .unwrap();
+        assert_eq!(tarball_url.path(), "/f/someorg/somerepo/*.tar.gz");
+    }
+
+    #[tokio::test]
+    async fn offline_converts_only_releases() {
+        // Nothing is listening here, so these would fail if they tried to reach FlakeHub.
+        let api_addr: url::Url = "http://localhost:1".parse().unwrap();
+        let lookup = super::FlakeHubLookup::new(&api_addr, false, None, true);
+
+        let input_url = url::Url::parse("github:someorg/somerepo/v1.2.3").unwrap();
+        let tarball_url = super::convert_input_to_flakehub(&lookup, input_url, false)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            tarball_url.as_str(),
+            "https://flakehub.com/f/someorg/somerepo/1.2.3.tar.gz"
+        );
+
+        let zip_lookup = super::FlakeHubLookup::new(&api_addr, false, None, true)
+            .with_archive_format(crate::cli::cmd::add::ArchiveFormat::Zip);
+        let input_url = url::Url::parse("github:someorg/somerepo/v1.2.3").unwrap();
+        let zip_url = super::convert_input_to_flakehub(&zip_lookup, input_url, false)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            zip_url.as_str(),
+            "https://flakehub.com/f/someorg/somerepo/1.2.3.zip"
+        );
+
+        for input_url in [
+            "github:someorg/somerepo",
+            "github:someorg/somerepo/main",
+            "github:NixOS/nixpkgs/nixos-23.05",
+        ] {
+            let input_url = url::Url::parse(input_url).unwrap();
+            let tarball_url = super::convert_input_to_flakehub(&lookup, input_url, false)
+                .await
+                .unwrap();
+            assert!(tarball_url.is_none());
+        }
+    }
+
+    #[tokio::test]
+    async fn git_https_with_rev_is_skipped() {
+        let test_server = axum_test::TestServer::new(test_router().into_make_service()).unwrap();
+        let server_addr = test_server.server_address();
+        let server_url = server_addr.parse().unwrap();
+
+        for input_url in [
+            "git+https://github.com/someorg/somerepo?rev=2f47650c2f28d87f86ab807b8a339c684d91ec56",
+            "git+https://github.com/wez/wezterm.git?submodules=1",
+        ] {
+            let input_url = url::Url::parse(input_url).unwrap();
+            let tarball_url = super::convert_input_to_flakehub(
+                &super::FlakeHubLookup::new(&server_url, false, None, false),
+                input_url,
+                true,
+            )
+            .await
+            .unwrap();
+            assert!(tarball_url.is_none());
+        }
+    }
+
+    #[tokio::test]
+    async fn sourcehut_to_flakehub() {
+        let test_server = axum_test::TestServer::new(test_router().into_make_service()).unwrap();
+        let server_addr = test_server.server_address();
+        let server_url = server_addr.parse().unwrap();
+        let lookup = super::FlakeHubLookup::new(&server_url, false, None, false);
+
+        let input_url = url::Url::parse("sourcehut:~someorg/somerepo").unwrap();
+        let tarball_url = super::convert_input_to_flakehub(&lookup, input_url, false)
+            .await
+            .ok()
+            .flatten()
+            .unwrap();
+        assert_eq!(tarball_url.path(), "/f/someorg/somerepo/*.tar.gz");
+
+        let input_url =
+            url::Url::parse("git+https://git.sr.ht/~someorg/somerepo?ref=v1.2.3").unwrap();
+        let tarball_url = super::convert_input_to_flakehub(&lookup, input_url, false)
+            .await
+            .ok()
+            .flatten()
+            .unwrap();
+        assert_eq!(tarball_url.path(), "/f/someorg/somerepo/1.2.3.tar.gz");
+
+        let input_url = url::Url::parse("sourcehut:~someorg/somerepo?dir=nix").unwrap();
+        let tarball_url = super::convert_input_to_flakehub(&lookup, input_url, false)
+            .await
+            .ok()
+            .flatten()
+            .unwrap();
+        assert_eq!(tarball_url.path(), "/f/someorg/somerepo/*.tar.gz");
+        assert_eq!(tarball_url.query(), Some("dir=nix"));
+
+        let input_url = url::Url::parse("sourcehut:~someorg/somerepo/main").unwrap();
+        let tarball_url = super::convert_input_to_flakehub(&lookup, input_url, false)
+            .await
+            .unwrap();
+        assert!(tarball_url.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_flake1_convert() {
+        let test_server = axum_test::TestServer::new(test_router().into_make_service()).unwrap();
+        let server_addr = test_server.server_address();
+        let server_url = server_addr.parse().unwrap();
+
+        let convert = convert_subcommand(&server_url);
+        let lookup = super::FlakeHubLookup::new(&convert.api_addr, false, None, false);
+        let flake_contents = include_str!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/samples/flake1.test.nix"
+        ));
+        let flake_contents = flake_contents.to_string();
+        let parsed = nixel::parse(flake_contents.clone());
+
+        let (new_flake_contents, flake_compat_input_name, _) = convert
+            .convert_inputs_to_flakehub(&parsed.expression, &flake_contents, &lookup)
+            .await
+            .unwrap();
+        let new_flake_contents = convert
+            .make_implicit_nixpkgs_explicit(&parsed.expression, &new_flake_contents, &lookup)
+            .await
+            .unwrap();
+        let (new_flake_contents, _) = convert
+            .fixup_flake_compat_input(
+                &new_flake_contents,
+                flake_compat_input_name.unwrap(),
+                &lookup,
+            )
+            .await
+            .unwrap();
+
+        assert!(new_flake_contents.contains(
+            r#"flake-compat.url = "https://flakehub.com/f/edolstra/flake-compat/*.tar.gz";"#
+        ));
+        assert!(new_flake_contents.contains("f/nixos/nixpkgs/0.2305.0.tar.gz"));
+
+        let nixpkgs_url_lines: Vec<_> = new_flake_contents
+            .lines()
+            .filter(|line| {
+                line.contains("nixpkgs.url") && line.contains("f/nixos/nixpkgs/0.2305.0.tar.gz")
+            })
+            .collect();
+        let num_nixpkgs_url_lines = nixpkgs_url_lines.len();
+        assert_eq!(num_nixpkgs_url_lines, 1);
+    }
+
+    #[tokio::test]
+    async fn test_implicit_nixpkgs_with_empty_inputs() {
+        let test_server = axum_test::TestServer::new(test_router().into_make_service()).unwrap();
+        let server_addr = test_server.server_address();
+        let server_url = server_addr.parse().unwrap();
+
+        let convert = convert_subcommand(&server_url);
+        let lookup = super::FlakeHubLookup::new(&convert.api_addr, false, None, false);
+        let flake_contents = r#"{
+  inputs = { };
+
+  outputs = { self, nixpkgs, ... }: { };
+}
+"#;
+        let parsed = nixel::parse(flake_contents.to_string());
+
+        let new_flake_contents = convert
+            .make_implicit_nixpkgs_explicit(&parsed.expression, flake_contents, &lookup)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            new_flake_contents,
+            r#"{
+  inputs = {
+    nixpkgs.url = "https://flakehub.com/f/nixos/nixpkgs/*.tar.gz";
+  };
+
+  outputs = { self, nixpkgs, ... }: { };
+}
+"#
+        );
+    }
+
+    #[tokio::test]
+    async fn test_nixpkgs_from_registry() {
+        let test_server = axum_test::TestServer::new(test_router().into_make_service()).unwrap();
+        let server_addr = test_server.server_address();
+        let server_url = server_addr.parse().unwrap();
+
+        let convert = convert_subcommand(&server_url);
+        let lookup = super::FlakeHubLookup::new(&convert.api_addr, false, None, false);
+        let flake_contents = r#"
+{
+  description = "cole-h's NixOS configuration";
+
+  inputs = {
+    nixpkgs.url = "nixpkgs";
   };
 
   outputs = { self, ... } @ tes: { };
@@ -790,13 +2253,523 @@ mod test {
         let flake_contents = flake_contents.to_string();
         let parsed = nixel::parse(flake_contents.clone());
 
-        let (new_flake_contents, _) = convert
-            .convert_inputs_to_flakehub(&parsed.expression, &flake_contents)
+        let (new_flake_contents, _, _) = convert
+            .convert_inputs_to_flakehub(&parsed.expression, &flake_contents, &lookup)
+            .await
+            .unwrap();
+
+        assert!(new_flake_contents
+            .contains(r#"nixpkgs.url = "https://flakehub.com/f/NixOS/nixpkgs/*.tar.gz";"#));
+    }
+
+    #[tokio::test]
+    async fn test_convert_preserves_follows_single_line() {
+        let test_server = axum_test::TestServer::new(test_router().into_make_service()).unwrap();
+        let server_addr = test_server.server_address();
+        let server_url = server_addr.parse().unwrap();
+
+        let convert = convert_subcommand(&server_url);
+        let lookup = super::FlakeHubLookup::new(&convert.api_addr, false, None, false);
+        let flake_contents = r#"
+{
+  inputs.foo = { url = "github:someorg/foo"; inputs.nixpkgs.follows = "nixpkgs"; };
+  inputs = { bar.url = "github:someorg/bar"; baz = { url = "github:someorg/baz"; inputs.nixpkgs.follows = "nixpkgs"; }; };
+
+  outputs = { self, ... } @ tes: { };
+}
+"#;
+        let flake_contents = flake_contents.to_string();
+        let parsed = nixel::parse(flake_contents.clone());
+
+        let (new_flake_contents, _, _) = convert
+            .convert_inputs_to_flakehub(&parsed.expression, &flake_contents, &lookup)
+            .await
+            .unwrap();
+
+        assert!(new_flake_contents.contains(
+            r#"inputs.foo = { url = "https://flakehub.com/f/someorg/foo/*.tar.gz"; inputs.nixpkgs.follows = "nixpkgs"; };"#
+        ));
+        assert!(new_flake_contents.contains(
+            r#"inputs = { bar.url = "https://flakehub.com/f/someorg/bar/*.tar.gz"; baz = { url = "https://flakehub.com/f/someorg/baz/*.tar.gz"; inputs.nixpkgs.follows = "nixpkgs"; }; };"#
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_convert_only_selected_inputs() {
+        let test_server = axum_test::TestServer::new(test_router().into_make_service()).unwrap();
+        let server_addr = test_server.server_address();
+        let server_url = server_addr.parse().unwrap();
+
+        let convert = super::ConvertSubcommand {
+            input_names: vec!["bar".into(), "baz".into(), "nope".into()],
+            ..convert_subcommand(&server_url)
+        };
+        let lookup = super::FlakeHubLookup::new(&convert.api_addr, false, None, false);
+        let flake_contents = r#"
+{
+  inputs.foo.url = "github:someorg/foo";
+  inputs.bar.url = "github:someorg/bar";
+  inputs.baz.url = "gitlab:someorg/baz";
+
+  outputs = { self, nixpkgs, ... } @ inputs: { };
+}
+"#;
+        let flake_contents = flake_contents.to_string();
+        let parsed = nixel::parse(flake_contents.clone());
+
+        let (new_flake_contents, _, report) = convert
+            .convert_inputs_to_flakehub(&parsed.expression, &flake_contents, &lookup)
+            .await
+            .unwrap();
+
+        assert_eq!(report.names(super::InputStatus::Converted), ["bar"]);
+        assert_eq!(report.names(super::InputStatus::Excluded), ["foo"]);
+        // Only inputs that were selected but couldn't be converted count as skipped
+        assert_eq!(report.names(super::InputStatus::Skipped), ["baz"]);
+        assert!(report.names(super::InputStatus::Unchanged).is_empty());
+
+        let bar = report
+            .inputs
+            .iter()
+            .find(|input| input.name == "bar")
+            .unwrap();
+        assert_eq!(bar.original_url.as_deref(), Some("github:someorg/bar"));
+        assert_eq!(
+            bar.new_url.as_ref().map(url::Url::as_str),
+            Some("https://flakehub.com/f/someorg/bar/*.tar.gz")
+        );
+        assert!(bar.reason.is_none());
+        let baz = report
+            .inputs
+            .iter()
+            .find(|input| input.name == "baz")
+            .unwrap();
+        assert_eq!(baz.original_url.as_deref(), Some("gitlab:someorg/baz"));
+        assert!(baz.new_url.is_none());
+        assert!(baz.reason.is_some());
+        assert!(new_flake_contents.contains(r#"inputs.foo.url = "github:someorg/foo";"#));
+        assert!(new_flake_contents
+            .contains(r#"inputs.bar.url = "https://flakehub.com/f/someorg/bar/*.tar.gz";"#));
+        assert!(!convert.should_convert("nixpkgs"));
+
+        let rows = report.preview_rows();
+        let row = |name: &str| rows.iter().find(|row| row.input == name).unwrap();
+        assert_eq!(
+            row("bar").flakehub_url.as_deref(),
+            Some("https://flakehub.com/f/someorg/bar/*.tar.gz")
+        );
+        assert!(row("bar").skip_reason.is_none());
+        assert_eq!(row("baz").skip_reason, baz.reason);
+        assert_eq!(row("foo").skip_reason.as_deref(), Some("not selected"));
+        assert!(row("foo").flakehub_url.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_convert_mixed_input_styles() {
+        let test_server = axum_test::TestServer::new(test_router().into_make_service()).unwrap();
+        let server_addr = test_server.server_address();
+        let server_url = server_addr.parse().unwrap();
+
+        let convert = convert_subcommand(&server_url);
+        let lookup = super::FlakeHubLookup::new(&convert.api_addr, false, None, false);
+        let flake_contents = include_str!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/samples/flake9.test.nix"
+        ));
+        let flake_contents = flake_contents.to_string();
+        let parsed = nixel::parse(flake_contents.clone());
+
+        let (new_flake_contents, _, report) = convert
+            .convert_inputs_to_flakehub(&parsed.expression, &flake_contents, &lookup)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            report.names(super::InputStatus::Converted),
+            ["crane", "fenix", "flake-utils", "nixpkgs"]
+        );
+        for expected in [
+            r#"nixpkgs.url = "https://flakehub.com/f/NixOS/nixpkgs/0.2305.0.tar.gz";"#,
+            r#"url = "https://flakehub.com/f/ipetkov/crane/*.tar.gz";"#,
+            r#"inputs.flake-utils.url = "https://flakehub.com/f/numtide/flake-utils/*.tar.gz";"#,
+            r#"url = "https://flakehub.com/f/nix-community/fenix/*.tar.gz";"#,
+        ] {
+            assert!(
+                new_flake_contents.contains(expected),
+                "{expected} not in {new_flake_contents}"
+            );
+        }
+        assert!(!new_flake_contents.contains("github:"));
+        assert_eq!(
+            new_flake_contents
+                .matches(r#"inputs.nixpkgs.follows = "nixpkgs";"#)
+                .count(),
+            2
+        );
+    }
+
+    #[tokio::test]
+    async fn test_convert_quoted_input_names() {
+        let test_server = axum_test::TestServer::new(test_router().into_make_service()).unwrap();
+        let server_addr = test_server.server_address();
+        let server_url = server_addr.parse().unwrap();
+
+        let convert = super::ConvertSubcommand {
+            input_names: vec!["my-crane".into(), "flake.utils".into()],
+            ..convert_subcommand(&server_url)
+        };
+        let lookup = super::FlakeHubLookup::new(&convert.api_addr, false, None, false);
+        let flake_contents = include_str!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/samples/flake10.test.nix"
+        ));
+        let flake_contents = flake_contents.to_string();
+        let parsed = nixel::parse(flake_contents.clone());
+
+        let (new_flake_contents, _, report) = convert
+            .convert_inputs_to_flakehub(&parsed.expression, &flake_contents, &lookup)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            report.names(super::InputStatus::Converted),
+            ["flake.utils", "my-crane"]
+        );
+        assert_eq!(
+            report.names(super::InputStatus::Excluded),
+            ["nixpkgs-23.05"]
+        );
+        for expected in [
+            r#""nixpkgs-23.05".url = "github:NixOS/nixpkgs/nixos-23.05";"#,
+            r#"url = "https://flakehub.com/f/ipetkov/crane/*.tar.gz";"#,
+            r#"inputs."flake.utils".url = "https://flakehub.com/f/numtide/flake-utils/*.tar.gz";"#,
+        ] {
+            assert!(
+                new_flake_contents.contains(expected),
+                "{expected} not in {new_flake_contents}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_convert_inputs_sharing_lines() {
+        let test_server = axum_test::TestServer::new(test_router().into_make_service()).unwrap();
+        let server_addr = test_server.server_address();
+        let server_url = server_addr.parse().unwrap();
+
+        let convert = convert_subcommand(&server_url);
+        let lookup = super::FlakeHubLookup::new(&convert.api_addr, false, None, false);
+        let flake_contents = r#"{
+  inputs = { foo.url = "github:someorg/foo"; bar = { url = github:someorg/bar; }; baz.url = "gitlab:someorg/baz"; };
+  inputs.qux.url = ''github:someorg/qux''; inputs.quux.url = "github:someorg/quux";
+
+  outputs = { self, ... } @ inputs: { };
+}
+"#;
+        let parsed = nixel::parse(flake_contents.to_string());
+
+        let (new_flake_contents, _, report) = convert
+            .convert_inputs_to_flakehub(&parsed.expression, flake_contents, &lookup)
             .await
             .unwrap();
 
+        assert_eq!(
+            report.names(super::InputStatus::Converted),
+            ["bar", "foo", "quux", "qux"]
+        );
+        assert_eq!(
+            new_flake_contents,
+            r#"{
+  inputs = { foo.url = "https://flakehub.com/f/someorg/foo/*.tar.gz"; bar = { url = "https://flakehub.com/f/someorg/bar/*.tar.gz"; }; baz.url = "gitlab:someorg/baz"; };
+  inputs.qux.url = ''https://flakehub.com/f/someorg/qux/*.tar.gz''; inputs.quux.url = "https://flakehub.com/f/someorg/quux/*.tar.gz";
+
+  outputs = { self, ... } @ inputs: { };
+}
+"#
+        );
+    }
+
+    #[tokio::test]
+    async fn test_convert_registry_inputs() {
+        let test_server = axum_test::TestServer::new(test_router().into_make_service()).unwrap();
+        let server_url = test_server.server_address().parse().unwrap();
+        let lookup = super::FlakeHubLookup::new(&server_url, false, None, false);
+
+        for (input_url, expected) in [
+            (
+                "flake:nixpkgs",
+                Some("https://flakehub.com/f/NixOS/nixpkgs/*.tar.gz"),
+            ),
+            (
+                "flake:nixpkgs/nixos-23.05",
+                Some("https://flakehub.com/f/NixOS/nixpkgs/0.2305.0.tar.gz"),
+            ),
+            (
+                "flake:flake-utils",
+                Some("https://flakehub.com/f/numtide/flake-utils/*.tar.gz"),
+            ),
+            ("flake:some-private-name", None),
+        ] {
+            let new_url =
+                super::convert_input_to_flakehub(&lookup, input_url.parse().unwrap(), false)
+                    .await
+                    .unwrap();
+            assert_eq!(
+                new_url.as_ref().map(url::Url::as_str),
+                expected,
+                "{input_url}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_convert_nixos_release_branches() {
+        let test_server = axum_test::TestServer::new(test_router().into_make_service()).unwrap();
+        let server_url = test_server.server_address().parse().unwrap();
+        let lookup = super::FlakeHubLookup::new(&server_url, false, None, false);
+
+        for (branch, version) in [
+            ("nixos-20.03", Some("0.2003.0")),
+            ("nixos-20.09", Some("0.2009.0")),
+            ("nixos-21.02", Some("0.2102.0")),
+            ("nixos-21.05-small", Some("0.2105.0")),
+            ("nixos-19.09", None),
+        ] {
+            let expected = version
+                .map(|version| format!("https://flakehub.com/f/NixOS/nixpkgs/{version}.tar.gz"));
+
+            for url in [
+                format!("github:NixOS/nixpkgs/{branch}"),
+                format!("github:NixOS/nixpkgs?ref={branch}"),
+                format!("github:NixOS/nixpkgs?ref=refs/heads/{branch}"),
+            ] {
+                let new_url =
+                    super::convert_github_input_to_flakehub(url.parse().unwrap(), &lookup)
+                        .await
+                        .unwrap();
+                assert_eq!(
+                    new_url.as_ref().map(url::Url::as_str),
+                    expected.as_deref(),
+                    "{url}"
+                );
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_convert_min_nixpkgs_release() {
+        let test_server = axum_test::TestServer::new(test_router().into_make_service()).unwrap();
+        let server_url = test_server.server_address().parse().unwrap();
+        let lookup = super::FlakeHubLookup::new(&server_url, false, None, false)
+            .with_min_nixpkgs_release(super::parse_nixpkgs_release("21.05").unwrap());
+
+        for (branch, version) in [
+            ("nixos-20.09", None),
+            ("nixos-21.02", None),
+            ("nixos-21.05", Some("0.2105.0")),
+            ("nixos-21.05-small", Some("0.2105.0")),
+            ("nixos-21.11", Some("0.2111.0")),
+            ("nixos-unstable", Some("0.1.0")),
+        ] {
+            let url = format!("github:NixOS/nixpkgs/{branch}");
+            let new_url = super::convert_github_input_to_flakehub(url.parse().unwrap(), &lookup)
+                .await
+                .unwrap();
+            assert_eq!(
+                new_url.as_ref().map(url::Url::as_str),
+                version
+                    .map(|version| format!("https://flakehub.com/f/NixOS/nixpkgs/{version}.tar.gz"))
+                    .as_deref(),
+                "{url}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_nixpkgs_release() {
+        let release = super::parse_nixpkgs_release("23.05").unwrap();
+        assert_eq!(release.to_string(), "23.05");
+        assert_eq!(
+            super::parse_nixpkgs_release("20.03").unwrap(),
+            super::NixpkgsRelease::FIRST_FLAKE
+        );
+        assert!(
+            super::parse_nixpkgs_release("19.09").unwrap() < super::NixpkgsRelease::FIRST_FLAKE
+        );
+        assert!(super::parse_nixpkgs_release("20.09").unwrap() < release);
+        assert!(super::parse_nixpkgs_release("23.11").unwrap() > release);
+
+        for release in [
+            "23",
+            "2305",
+            "23.5",
+            "2023.05",
+            "23.13",
+            "23.00",
+            "nixos-23.05",
+            "",
+        ] {
+            assert!(super::parse_nixpkgs_release(release).is_err(), "{release}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_convert_published_tags() {
+        let test_server = axum_test::TestServer::new(test_router().into_make_service()).unwrap();
+        let server_url = test_server.server_address().parse().unwrap();
+        let lookup = super::FlakeHubLookup::new(&server_url, false, None, false);
+
+        for (url, expected) in [
+            (
+                "github:someorg/somerepo/release-2023-11",
+                Some("https://flakehub.com/f/someorg/somerepo/1.4.0.tar.gz"),
+            ),
+            (
+                "github:someorg/somerepo?ref=refs/tags/release-2023-11",
+                Some("https://flakehub.com/f/someorg/somerepo/1.4.0.tar.gz"),
+            ),
+            ("github:someorg/somerepo/release-2023-12", None),
+            ("github:someorg/somerepo/main", None),
+        ] {
+            let new_url = super::convert_github_input_to_flakehub(url.parse().unwrap(), &lookup)
+                .await
+                .unwrap();
+            assert_eq!(new_url.as_ref().map(url::Url::as_str), expected, "{url}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_convert_mapped_projects() {
+        let test_server = axum_test::TestServer::new(test_router().into_make_service()).unwrap();
+        let server_url = test_server.server_address().parse().unwrap();
+        let mappings =
+            [super::parse_project_mapping("someone/nixpkgs-fork=NixOS/nixpkgs").unwrap()];
+        let lookup =
+            super::FlakeHubLookup::new(&server_url, false, None, false).with_mappings(&mappings);
+
+        for (url, expected) in [
+            (
+                "github:someone/nixpkgs-fork",
+                "https://flakehub.com/f/NixOS/nixpkgs/*.tar.gz",
+            ),
+            (
+                "github:Someone/Nixpkgs-Fork/v1.2.3",
+                "https://flakehub.com/f/NixOS/nixpkgs/1.2.3.tar.gz",
+            ),
+            (
+                "github:someorg/somerepo",
+                "https://flakehub.com/f/someorg/somerepo/*.tar.gz",
+            ),
+        ] {
+            let new_url = super::convert_github_input_to_flakehub(url.parse().unwrap(), &lookup)
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(new_url.as_str(), expected, "{url}");
+        }
+
+        for mapping in [
+            "someone/fork",
+            "someone/fork=NixOS",
+            "=NixOS/nixpkgs",
+            "a/b/c=d/e",
+        ] {
+            assert!(super::parse_project_mapping(mapping).is_err(), "{mapping}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_convert_keeps_inputs_pinned_to_a_rev() {
+        let test_server = axum_test::TestServer::new(test_router().into_make_service()).unwrap();
+        let server_url = test_server.server_address().parse().unwrap();
+        let lookup = super::FlakeHubLookup::new(&server_url, false, None, false);
+
+        // A commit isn't a release, so converting would lose the pin
+        for url in [
+            "github:someorg/somerepo?rev=9a333eaa80901efe01df07eade2c16d183761fa3",
+            "github:NixOS/nixpkgs?rev=9a333eaa80901efe01df07eade2c16d183761fa3",
+        ] {
+            let new_url = super::convert_github_input_to_flakehub(url.parse().unwrap(), &lookup)
+                .await
+                .unwrap();
+            assert_eq!(new_url, None, "{url}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_convert_only_selected_schemes() {
+        let test_server = axum_test::TestServer::new(test_router().into_make_service()).unwrap();
+        let server_addr = test_server.server_address();
+        let server_url = server_addr.parse().unwrap();
+
+        let convert = super::ConvertSubcommand {
+            only_schemes: vec!["github".into()],
+            ..convert_subcommand(&server_url)
+        };
+        let lookup = super::FlakeHubLookup::new(&convert.api_addr, false, None, false);
+        let flake_contents = r#"
+{
+  inputs.foo.url = "github:someorg/foo";
+  inputs.bar.url = "git+https://github.com/someorg/bar";
+
+  outputs = { self, nixpkgs, ... } @ inputs: { };
+}
+"#;
+        let flake_contents = flake_contents.to_string();
+        let parsed = nixel::parse(flake_contents.clone());
+
+        let (new_flake_contents, _, report) = convert
+            .convert_inputs_to_flakehub(&parsed.expression, &flake_contents, &lookup)
+            .await
+            .unwrap();
+
+        assert_eq!(report.names(super::InputStatus::Converted), ["foo"]);
+        assert_eq!(report.names(super::InputStatus::Excluded), ["bar"]);
+        assert!(new_flake_contents
+            .contains(r#"inputs.foo.url = "https://flakehub.com/f/someorg/foo/*.tar.gz";"#));
         assert!(new_flake_contents
-            .contains(r#"nixpkgs.url = "http://flakehub-localhost/f/NixOS/nixpkgs/*.tar.gz";"#));
+            .contains(r#"inputs.bar.url = "git+https://github.com/someorg/bar";"#));
+    }
+
+    #[tokio::test]
+    async fn test_convert_preserves_follows_multi_line() {
+        let test_server = axum_test::TestServer::new(test_router().into_make_service()).unwrap();
+        let server_addr = test_server.server_address();
+        let server_url = server_addr.parse().unwrap();
+
+        let convert = convert_subcommand(&server_url);
+        let lookup = super::FlakeHubLookup::new(&convert.api_addr, false, None, false);
+        let flake_contents = r#"
+{
+  inputs.foo = {
+    url = "github:someorg/foo";
+    inputs.nixpkgs.follows = "nixpkgs";
+  };
+
+  outputs = { self, ... } @ tes: { };
+}
+"#;
+        let flake_contents = flake_contents.to_string();
+        let parsed = nixel::parse(flake_contents.clone());
+
+        let (new_flake_contents, _, _) = convert
+            .convert_inputs_to_flakehub(&parsed.expression, &flake_contents, &lookup)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            new_flake_contents,
+            r#"
+{
+  inputs.foo = {
+    url = "https://flakehub.com/f/someorg/foo/*.tar.gz";
+    inputs.nixpkgs.follows = "nixpkgs";
+  };
+
+  outputs = { self, ... } @ tes: { };
+}
+"#
+        );
     }
 
     #[tokio::test]
@@ -807,11 +2780,15 @@ mod test {
 
         let input_url =
             url::Url::parse("https://api.flakehub.com/f/NixOS/nixpkgs/0.1.514192.tar.gz").unwrap();
-        let tarball_url = super::convert_input_to_flakehub(&server_url, input_url)
-            .await
-            .ok()
-            .flatten()
-            .unwrap();
+        let tarball_url = super::convert_input_to_flakehub(
+            &super::FlakeHubLookup::new(&server_url, false, None, false),
+            input_url,
+            false,
+        )
+        .await
+        .ok()
+        .flatten()
+        .unwrap();
         assert_eq!(
             tarball_url.host().unwrap(),
             url::Host::Domain("flakehub.com")
@@ -821,4 +2798,203 @@ mod test {
             url::Host::Domain("api.flakehub.com")
         );
     }
+
+    #[tokio::test]
+    async fn lookup_caches_responses() {
+        let hits = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let router = {
+            let hits = hits.clone();
+            axum::Router::new().route(
+                "/f/:org/:project",
+                axum::routing::get(move |path| {
+                    hits.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                    no_version(path)
+                }),
+            )
+        };
+        let test_server = axum_test::TestServer::new(router.into_make_service()).unwrap();
+        let server_url = test_server.server_address().parse().unwrap();
+
+        let lookup = super::FlakeHubLookup::new(&server_url, false, None, false);
+        let (_, first) = lookup
+            .project_and_url("nixos", "nixpkgs", None)
+            .await
+            .unwrap();
+        let (_, second) = lookup
+            .project_and_url("nixos", "nixpkgs", None)
+            .await
+            .unwrap();
+
+        assert_eq!(first, second);
+        assert_eq!(hits.load(std::sync::atomic::Ordering::SeqCst), 1);
+
+        // The responses are shared by the whole process, e.g. across the flakes of `--recursive`
+        let (_, third) = super::FlakeHubLookup::new(&server_url, false, None, false)
+            .project_and_url("nixos", "nixpkgs", None)
+            .await
+            .unwrap();
+        assert_eq!(first, third);
+        assert_eq!(hits.load(std::sync::atomic::Ordering::SeqCst), 1);
+
+        // ...but not between different requests
+        crate::cli::cmd::add::get_flakehub_project_and_url(
+            &server_url,
+            "nixos",
+            "nixpkgs",
+            None,
+            false,
+            crate::cli::cmd::add::ArchiveFormat::TarGz,
+            Some("token"),
+        )
+        .await
+        .unwrap();
+        assert_eq!(hits.load(std::sync::atomic::Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_unified_diff() {
+        let old = "{\n  inputs.foo.url = \"github:someorg/foo\";\n  inputs.bar.url = \"github:someorg/bar\";\n}\n";
+        let new = "{\n  inputs.foo.url = \"https://flakehub.com/f/someorg/foo/*.tar.gz\";\n  inputs.bar.url = \"github:someorg/bar\";\n}\n";
+
+        let diff = super::unified_diff(std::path::Path::new("flake.nix"), old, new);
+
+        assert!(diff.starts_with("--- flake.nix\n+++ flake.nix\n"));
+        assert!(diff.contains("-  inputs.foo.url = \"github:someorg/foo\";\n"));
+        assert!(
+            diff.contains("+  inputs.foo.url = \"https://flakehub.com/f/someorg/foo/*.tar.gz\";\n")
+        );
+        assert!(diff.contains("   inputs.bar.url = \"github:someorg/bar\";\n"));
+    }
+
+    #[test]
+    fn test_find_input_value_skips_non_literal_urls() {
+        let flake_contents = r#"
+{
+  inputs.foo.url = ''github:someorg/foo'';
+  inputs.bar.url = "github:someorg/${name}";
+  inputs.baz.url = barUrl;
+
+  outputs = { self, ... } @ inputs: { };
+}
+"#;
+        let parsed = nixel::parse(flake_contents.to_string());
+        let find = |input: &str| {
+            super::find_input_value_by_path(
+                &parsed.expression,
+                ["inputs".into(), input.into(), "url".into()].into(),
+            )
+            .unwrap()
+        };
+
+        assert_eq!(find("foo").as_deref(), Some("github:someorg/foo"));
+        assert_eq!(find("bar"), None);
+        assert_eq!(find("baz"), None);
+    }
+
+    /// `fh convert --dry-run --no-lock`, against the FlakeHub at `api_addr`.
+    fn convert_subcommand(api_addr: &url::Url) -> super::ConvertSubcommand {
+        let mut convert = parse_convert(&["--dry-run", "--no-lock"]);
+        convert.api_addr = api_addr.clone();
+
+        convert
+    }
+
+    fn parse_convert(args: &[&str]) -> super::ConvertSubcommand {
+        let cli = crate::cli::config::Config::default()
+            .parse_cli(["fh", "convert"].iter().chain(args))
+            .unwrap();
+        let crate::cli::cmd::FhSubcommands::Convert(convert) = cli.subcommand else {
+            panic!("expected `fh convert`");
+        };
+
+        convert
+    }
+
+    #[tokio::test]
+    async fn test_write_flake_changed() {
+        const FLAKE: &str = "{ inputs.nixpkgs.url = \"github:NixOS/nixpkgs\"; }\n";
+        const NEW_FLAKE: &str =
+            "{ inputs.nixpkgs.url = \"https://flakehub.com/f/NixOS/nixpkgs/*\"; }\n";
+        let flake_path = crate::cli::cmd::mock_flakehub::temp_flake("convert-write", FLAKE).await;
+        let flake_path_str = flake_path.to_str().unwrap();
+
+        let convert = parse_convert(&["--flake-path", flake_path_str, "--no-lock"]);
+        let changed = convert.write_flake(FLAKE, FLAKE.into(), &[]).await.unwrap();
+        assert!(changed.is_empty());
+        let changed = convert
+            .write_flake(FLAKE, NEW_FLAKE.into(), &["nixpkgs"])
+            .await
+            .unwrap();
+        assert_eq!(changed, std::slice::from_ref(&flake_path));
+
+        let output_path = flake_path.with_file_name("converted.nix");
+        let convert = parse_convert(&[
+            "--flake-path",
+            flake_path_str,
+            "--output-path",
+            output_path.to_str().unwrap(),
+        ]);
+        let changed = convert
+            .write_flake(NEW_FLAKE, NEW_FLAKE.into(), &[])
+            .await
+            .unwrap();
+        assert_eq!(changed, std::slice::from_ref(&output_path));
+        assert_eq!(
+            tokio::fs::read_to_string(&output_path).await.unwrap(),
+            NEW_FLAKE
+        );
+
+        crate::cli::cmd::mock_flakehub::remove_temp_flake(&flake_path).await;
+    }
+
+    #[tokio::test]
+    async fn test_fixup_flake_compat_nix_files_changed() {
+        let dir = std::env::temp_dir().join(format!("fh-fixup-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let git = |args: &[&str]| {
+            std::process::Command::new("git")
+                .args(["-c", "user.name=fh", "-c", "user.email=fh@example.com"])
+                .args(args)
+                .current_dir(&dir)
+                .output()
+                .unwrap()
+        };
+        let flake_compat = format!(
+            "import (fetchTarball \"{}/master.tar.gz\")",
+            super::FLAKE_COMPAT_MARKER
+        );
+
+        git(&["init", "--quiet"]);
+        std::fs::write(dir.join(super::SHELL_NIX), &flake_compat).unwrap();
+        std::fs::write(dir.join(super::DEFAULT_NIX), &flake_compat).unwrap();
+        git(&["add", "."]);
+        git(&["commit", "--quiet", "-m", "init"]);
+        let convert = parse_convert(&[]);
+
+        let changed = convert
+            .fixup_flake_compat_nix_files(&dir, true)
+            .await
+            .unwrap();
+        assert_eq!(
+            changed,
+            [dir.join(super::SHELL_NIX), dir.join(super::DEFAULT_NIX)]
+        );
+        assert_eq!(
+            std::fs::read_to_string(dir.join(super::DEFAULT_NIX)).unwrap(),
+            flake_compat
+        );
+
+        // Uncommitted changes are left alone
+        std::fs::write(dir.join(super::SHELL_NIX), format!("{flake_compat}\n")).unwrap();
+        let changed = convert
+            .fixup_flake_compat_nix_files(&dir, false)
+            .await
+            .unwrap();
+        assert_eq!(changed, [dir.join(super::DEFAULT_NIX)]);
+        assert!(std::fs::read_to_string(dir.join(super::DEFAULT_NIX))
+            .unwrap()
+            .starts_with(super::FLAKE_COMPAT_CONTENTS_PREFIX));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 }
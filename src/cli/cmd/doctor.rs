@@ -0,0 +1,259 @@
+use std::io::IsTerminal;
+use std::process::ExitCode;
+
+use clap::Parser;
+use indicatif::{ProgressBar, ProgressStyle};
+use tabled::{Table, Tabled};
+
+use super::list::{bold, display_option};
+use super::{print_json, CommandExecute, DEFAULT_STYLE};
+
+/// Checks that your flake's FlakeHub inputs still resolve on FlakeHub, and which of its GitHub
+/// inputs could be converted to FlakeHub.
+///
+/// Exits with a non-zero status if any input is broken.
+#[derive(Debug, Parser)]
+#[command(mut_arg("flake_path", |arg| arg.help("The flake.nix to check")))]
+pub(crate) struct DoctorSubcommand {
+    #[clap(flatten)]
+    pub(crate) flake: super::FlakePathArgs,
+
+    /// Output results as JSON.
+    #[clap(long)]
+    json: bool,
+
+    #[clap(from_global)]
+    api_addr: url::Url,
+
+    #[clap(from_global)]
+    token: Option<String>,
+}
+
+#[async_trait::async_trait]
+impl CommandExecute for DoctorSubcommand {
+    #[tracing::instrument(skip_all)]
+    async fn execute(self) -> color_eyre::Result<ExitCode> {
+        if !self.flake.path.exists() {
+            return Err(color_eyre::eyre::eyre!(
+                "the flake at {} did not exist",
+                self.flake.path.display()
+            ));
+        }
+
+        let pb = (!self.json).then(|| {
+            let pb = ProgressBar::new_spinner();
+            pb.set_style(ProgressStyle::default_spinner());
+            pb
+        });
+
+        let (flake_contents, parsed) = crate::cli::cmd::add::load_flake(&self.flake.path).await?;
+
+        let all_toplevel_inputs = crate::cli::cmd::add::flake::find_all_attrsets_by_path(
+            &parsed.expression,
+            Some(["inputs".into()].into()),
+        )
+        .map_err(|err| crate::cli::cmd::add::flake::with_source_context(err, &flake_contents))?;
+        let all_inputs = crate::cli::cmd::add::flake::collect_all_inputs(all_toplevel_inputs)?;
+
+        let mut rows = Vec::new();
+        for input in all_inputs.iter() {
+            let Some(input_name) = input.name() else {
+                tracing::debug!("couldn't get input name from attrpath, skipping");
+                continue;
+            };
+
+            let url = crate::cli::cmd::convert::find_input_value_by_path(
+                &input.binding.to,
+                ["url".into()].into(),
+            )
+            .map_err(|err| crate::cli::cmd::add::flake::with_source_context(err, &flake_contents))?
+            .and_then(|url| url.parse::<url::Url>().ok());
+
+            let (status, suggestion) = match url {
+                Some(url) => diagnose_input(&self.api_addr, self.token.as_deref(), &url).await,
+                None => (Status::Unmanaged, None),
+            };
+
+            rows.push(DoctorRow {
+                input: input_name,
+                status,
+                suggestion,
+            });
+        }
+
+        if let Some(pb) = pb {
+            pb.finish_and_clear();
+        }
+
+        let any_broken = rows.iter().any(|row| row.status == Status::Broken);
+
+        if rows.is_empty() {
+            eprintln!("No inputs");
+        } else if self.json {
+            print_json(&rows)?;
+        } else if std::io::stdout().is_terminal() {
+            let mut table = Table::new(rows);
+            table.with(DEFAULT_STYLE.clone());
+            println!("{table}");
+        } else {
+            let mut writer = csv::Writer::from_writer(std::io::stdout());
+            for row in rows {
+                writer.serialize(row)?;
+            }
+        }
+
+        if any_broken {
+            Ok(ExitCode::FAILURE)
+        } else {
+            Ok(ExitCode::SUCCESS)
+        }
+    }
+}
+
+/// Looks up an input's URL on FlakeHub, returning its status and what to do about it (if
+/// anything).
+#[tracing::instrument(skip_all, fields(url = %url))]
+async fn diagnose_input(
+    api_addr: &url::Url,
+    token: Option<&str>,
+    url: &url::Url,
+) -> (Status, Option<String>) {
+    if let Some((org, project, version, archive_format)) =
+        crate::cli::cmd::update::parse_flakehub_url(url)
+    {
+        return match crate::cli::cmd::add::get_flakehub_project_and_url(
+            api_addr,
+            &org,
+            &project,
+            version.as_deref(),
+            false,
+            archive_format,
+            token,
+        )
+        .await
+        {
+            // FlakeHub answers with the canonical names, so a rename (or a change in case) shows
+            // up as a different org or project
+            Ok((_, resolved_url)) => {
+                match crate::cli::cmd::update::parse_flakehub_url(&resolved_url) {
+                    Some((resolved_org, resolved_project, _, _))
+                        if resolved_org != org || resolved_project != project =>
+                    {
+                        (
+                            Status::Moved,
+                            Some(format!("use `{resolved_org}/{resolved_project}` instead")),
+                        )
+                    }
+                    _ => (Status::Ok, None),
+                }
+            }
+            Err(e) => (Status::Broken, Some(e.to_string())),
+        };
+    }
+
+    if url.scheme() == "github" {
+        if let [org, project, ..] = url.path().split('/').collect::<Vec<_>>()[..] {
+            if crate::cli::cmd::add::get_flakehub_project_and_url(
+                api_addr,
+                org,
+                project,
+                None,
+                false,
+                crate::cli::cmd::add::ArchiveFormat::TarGz,
+                token,
+            )
+            .await
+            .is_ok()
+            {
+                return (
+                    Status::Convertible,
+                    Some(String::from("run `fh convert` to use FlakeHub")),
+                );
+            }
+        }
+    }
+
+    (Status::Unmanaged, None)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+enum Status {
+    Ok,
+    Moved,
+    Broken,
+    Convertible,
+    Unmanaged,
+}
+
+impl std::fmt::Display for Status {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let status = match self {
+            Status::Ok => "ok",
+            Status::Moved => "moved",
+            Status::Broken => "broken",
+            Status::Convertible => "convertible to FlakeHub",
+            Status::Unmanaged => "unmanaged",
+        };
+
+        write!(f, "{status}")
+    }
+}
+
+#[derive(Tabled, serde::Serialize)]
+struct DoctorRow {
+    #[tabled(rename = "Input", display_with = "bold")]
+    #[serde(rename = "Input")]
+    input: String,
+    #[tabled(rename = "Status")]
+    #[serde(rename = "Status")]
+    status: Status,
+    #[tabled(rename = "Suggestion", display_with = "display_option")]
+    #[serde(rename = "Suggestion")]
+    suggestion: Option<String>,
+}
+
+#[cfg(test)]
+mod test {
+    use super::Status;
+    use crate::cli::cmd::mock_flakehub::MockFlakeHub;
+
+    #[tokio::test]
+    async fn test_diagnose_input() {
+        let flakehub = MockFlakeHub::new(&["NixOS/nixpkgs"]);
+        let api_addr = flakehub.api_addr().parse().unwrap();
+
+        for (url, expected) in [
+            (
+                "https://flakehub.com/f/NixOS/nixpkgs/0.2305.*.tar.gz",
+                Status::Ok,
+            ),
+            ("https://flakehub.com/f/NixOS/nixpkgs/*.tar.gz", Status::Ok),
+            (
+                "https://flakehub.com/f/NixOS/nixpkgs/0.2305.*.zip",
+                Status::Ok,
+            ),
+            // FlakeHub knows it as `NixOS/nixpkgs`
+            (
+                "https://flakehub.com/f/nixos/nixpkgs/0.2305.*.tar.gz",
+                Status::Moved,
+            ),
+            (
+                "https://flakehub.com/f/someorg/someflake/0.1.*.tar.gz",
+                Status::Broken,
+            ),
+            ("github:NixOS/nixpkgs/nixos-23.05", Status::Convertible),
+            ("github:someorg/someflake", Status::Unmanaged),
+            ("path:./some/flake", Status::Unmanaged),
+        ] {
+            let (status, suggestion) =
+                super::diagnose_input(&api_addr, None, &url.parse().unwrap()).await;
+            assert_eq!(status, expected, "{url}");
+            assert_eq!(
+                suggestion.is_some(),
+                status != Status::Ok && status != Status::Unmanaged,
+                "{url}"
+            );
+        }
+    }
+}
@@ -1,11 +1,8 @@
 use std::collections::VecDeque;
-use std::path::PathBuf;
 use std::process::ExitCode;
 
 use clap::Parser;
-use color_eyre::eyre::WrapErr;
 use once_cell::sync::Lazy;
-use reqwest::header::{HeaderValue, ACCEPT, AUTHORIZATION};
 use serde::Deserialize;
 use tracing::{span, Level};
 
@@ -19,10 +16,10 @@ static RELEASE_VERSION_REGEX: Lazy<regex::Regex> = Lazy::new(|| {
 
 /// Convert flake inputs from FlakeHub back to GitHub.
 #[derive(Debug, Parser)]
+#[command(mut_arg("flake_path", |arg| arg.help("The flake.nix to convert")))]
 pub(crate) struct EjectSubcommand {
-    /// The flake.nix to convert.
-    #[clap(long, default_value = "./flake.nix")]
-    pub(crate) flake_path: PathBuf,
+    #[clap(flatten)]
+    pub(crate) flake: crate::cli::cmd::FlakePathArgs,
 
     /// Print to stdout the new flake.nix contents instead of writing it to disk.
     #[clap(long)]
@@ -30,28 +27,36 @@ pub(crate) struct EjectSubcommand {
 
     #[clap(from_global)]
     api_addr: url::Url,
+
+    #[clap(from_global)]
+    token: Option<String>,
 }
 
 #[async_trait::async_trait]
 impl CommandExecute for EjectSubcommand {
     #[tracing::instrument(skip_all)]
     async fn execute(self) -> color_eyre::Result<ExitCode> {
-        if !self.flake_path.exists() {
+        if !self.flake.path.exists() {
             return Err(color_eyre::eyre::eyre!(
                 "the flake at {} did not exist",
-                self.flake_path.display()
+                self.flake.path.display()
             ));
         }
 
-        let (flake_contents, parsed) = crate::cli::cmd::add::load_flake(&self.flake_path).await?;
+        let (flake_contents, parsed) = crate::cli::cmd::add::load_flake(&self.flake.path).await?;
         let new_flake_contents = self
             .eject_inputs_to_github(&parsed.expression, &flake_contents)
-            .await?;
+            .await
+            .map_err(|err| {
+                crate::cli::cmd::add::flake::with_source_context(err, &flake_contents)
+            })?;
+        let new_flake_contents =
+            crate::cli::cmd::add::match_trailing_newline(&flake_contents, new_flake_contents);
 
         if self.dry_run {
             println!("{new_flake_contents}");
         } else {
-            tokio::fs::write(self.flake_path, new_flake_contents).await?;
+            tokio::fs::write(self.flake.path, new_flake_contents).await?;
             // NOTE: We don't auto-lock like we do in `fh convert` because this is a lossy process.
             // We don't know if the version was a tag like `v1.0.0` or if it was just `1.0.0` (or
             // any other format). So, we do a best effort attempt of assuming `1.0.0` and letting
@@ -81,18 +86,7 @@ impl EjectSubcommand {
 
         for input in all_inputs.iter() {
             tracing::trace!("Examining input: {:#?}", input);
-            let Some(input_name) = input.from.iter().find_map(|part| match part {
-                nixel::Part::Raw(raw) => {
-                    let content = raw.content.trim().to_string();
-
-                    if ["inputs", "url"].contains(&content.as_ref()) {
-                        None
-                    } else {
-                        Some(content)
-                    }
-                }
-                _ => None,
-            }) else {
+            let Some(input_name) = input.name() else {
                 tracing::debug!("couldn't get input name from attrpath, skipping");
                 continue;
             };
@@ -101,7 +95,7 @@ impl EjectSubcommand {
             let _span_guard = span.enter();
 
             let url = crate::cli::cmd::convert::find_input_value_by_path(
-                &input.to,
+                &input.binding.to,
                 ["url".into()].into(),
             )?;
             tracing::debug!("Current input's `url` value: {:?}", url);
@@ -110,7 +104,9 @@ impl EjectSubcommand {
             tracing::trace!("Parsed URL: {:?}", maybe_parsed_url);
 
             let new_input_url = match maybe_parsed_url {
-                Some(parsed_url) => eject_input_to_github(&self.api_addr, parsed_url).await?,
+                Some(parsed_url) => {
+                    eject_input_to_github(&self.api_addr, parsed_url, self.token.as_deref()).await?
+                }
                 None => None,
             };
 
@@ -144,13 +140,14 @@ impl EjectSubcommand {
 async fn eject_input_to_github(
     api_addr: &url::Url,
     parsed_url: url::Url,
+    token: Option<&str>,
 ) -> color_eyre::Result<Option<url::Url>> {
     let mut url = None;
 
     if let Some(host) = parsed_url.host() {
         // A URL like `https://flakehub.com/...`
         if host == url::Host::Domain("flakehub.com") {
-            url = Some(eject_flakehub_input_to_github(parsed_url, api_addr).await?);
+            url = Some(eject_flakehub_input_to_github(parsed_url, api_addr, token).await?);
         }
     }
 
@@ -161,11 +158,12 @@ async fn eject_input_to_github(
 async fn eject_flakehub_input_to_github(
     parsed_url: url::Url,
     api_addr: &url::Url,
+    token: Option<&str>,
 ) -> color_eyre::Result<url::Url> {
     let (org, project, version) = match parsed_url.path().split('/').collect::<Vec<_>>()[..] {
         // `/f/NixOS/nixpkgs/0.1.514192.tar.gz`
         ["", "f", org, project, version] => {
-            let version = version.strip_suffix(".tar.gz").unwrap_or(version);
+            let version = crate::cli::cmd::add::ArchiveFormat::strip_suffix(version);
             (org, project, version)
         }
         _ => Err(color_eyre::eyre::eyre!(
@@ -177,7 +175,7 @@ async fn eject_flakehub_input_to_github(
         source_github_owner_repo_pair,
         source_subdirectory,
         version,
-    } = get_metadata_from_flakehub(api_addr, org, project, version).await?;
+    } = get_metadata_from_flakehub(api_addr, org, project, version, token).await?;
 
     let maybe_version_or_branch = match source_github_owner_repo_pair.to_lowercase().as_str() {
         "nixos/nixpkgs" => {
@@ -250,29 +248,9 @@ async fn get_metadata_from_flakehub(
     org: &str,
     project: &str,
     version: &str,
+    token: Option<&str>,
 ) -> color_eyre::Result<ProjectMetadata> {
-    let mut headers = reqwest::header::HeaderMap::new();
-    headers.insert(ACCEPT, HeaderValue::from_static("application/json"));
-
-    let xdg = xdg::BaseDirectories::new()?;
-    // $XDG_CONFIG_HOME/fh/auth; basically ~/.config/fh/auth
-    let token_path = xdg.get_config_file("flakehub/auth");
-
-    if token_path.exists() {
-        let token = tokio::fs::read_to_string(&token_path)
-            .await
-            .wrap_err_with(|| format!("Could not open {}", token_path.display()))?;
-
-        headers.insert(
-            AUTHORIZATION,
-            HeaderValue::from_str(&format!("Bearer {token}"))?,
-        );
-    }
-
-    let client = reqwest::Client::builder()
-        .user_agent(crate::APP_USER_AGENT)
-        .default_headers(headers)
-        .build()?;
+    let client = crate::cli::cmd::add::flakehub_http_client(token).await?;
 
     let mut flakehub_json_url = api_addr.clone();
     {
@@ -287,12 +265,8 @@ async fn get_metadata_from_flakehub(
             .push(version);
     }
 
-    let res = client.get(&flakehub_json_url.to_string()).send().await?;
-
-    if let Err(e) = res.error_for_status_ref() {
-        let err_text = res.text().await?;
-        return Err(e).wrap_err(err_text)?;
-    };
+    let res = crate::cli::cmd::send_with_retries(client.get(flakehub_json_url)).await?;
+    let res = crate::cli::cmd::add::error_for_flakehub_status(res).await?;
 
     let res = res.json::<ProjectMetadata>().await?;
 
@@ -301,48 +275,33 @@ async fn get_metadata_from_flakehub(
 
 #[cfg(test)]
 mod test {
-    use axum::{extract::Path, response::IntoResponse};
-
-    async fn version(
-        Path((org, project, version)): Path<(String, String, String)>,
-    ) -> axum::response::Response {
-        let version = if version == "*" {
-            "0.1.0+rev-eeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeee"
-        } else {
-            &version
-        };
-
-        let (source_github_owner_repo_pair, source_subdirectory) =
-            if org == "edolstra" && project == "blender-bin" {
-                (format!("{org}/nix-warez"), Some("blender"))
-            } else {
-                (format!("{org}/{project}"), None)
-            };
-
-        axum::Json(serde_json::json!({
-            "source_github_owner_repo_pair": source_github_owner_repo_pair,
-            "source_subdirectory": source_subdirectory,
-            "version": version,
-        }))
-        .into_response()
-    }
-
-    fn test_router() -> axum::Router {
-        axum::Router::new().route(
-            "/version/:org/:project/:version",
-            axum::routing::get(version),
+    use crate::cli::cmd::mock_flakehub::MockFlakeHub;
+
+    fn mock_flakehub() -> MockFlakeHub {
+        MockFlakeHub::with_sources(
+            &[
+                "someorg/somerepo",
+                "NixOS/nixpkgs",
+                "DeterminateSystems/fh",
+                "edolstra/blender-bin",
+                "nix-community/home-manager",
+            ],
+            &[(
+                "edolstra/blender-bin",
+                "edolstra/nix-warez",
+                Some("blender"),
+            )],
         )
     }
 
     #[tokio::test]
     async fn flakehub_to_github() {
-        let test_server = axum_test::TestServer::new(test_router().into_make_service()).unwrap();
-        let server_addr = test_server.server_address();
-        let server_url = server_addr.parse().unwrap();
+        let flakehub = mock_flakehub();
+        let server_url = flakehub.api_addr().parse().unwrap();
 
         let input_url =
             url::Url::parse("https://flakehub.com/f/someorg/somerepo/*.tar.gz").unwrap();
-        let github_url = super::eject_input_to_github(&server_url, input_url)
+        let github_url = super::eject_input_to_github(&server_url, input_url, None)
             .await
             .ok()
             .flatten()
@@ -352,46 +311,48 @@ mod test {
 
     #[tokio::test]
     async fn versioned_flakehub_to_github() {
-        let test_server = axum_test::TestServer::new(test_router().into_make_service()).unwrap();
-        let server_addr = test_server.server_address();
-        let server_url = server_addr.parse().unwrap();
-
-        let input_url =
-            url::Url::parse("https://flakehub.com/f/someorg/somerepo/1.0.0.tar.gz").unwrap();
-        let github_url = super::eject_input_to_github(&server_url, input_url)
-            .await
-            .ok()
-            .flatten()
-            .unwrap();
-        assert_eq!(github_url.to_string(), "github:someorg/somerepo/1.0.0");
+        let flakehub = mock_flakehub();
+        let server_url = flakehub.api_addr().parse().unwrap();
+
+        for input_url in [
+            "https://flakehub.com/f/someorg/somerepo/1.0.0.tar.gz",
+            "https://flakehub.com/f/someorg/somerepo/1.0.0.zip",
+        ] {
+            let input_url = url::Url::parse(input_url).unwrap();
+            let github_url = super::eject_input_to_github(&server_url, input_url, None)
+                .await
+                .ok()
+                .flatten()
+                .unwrap();
+            assert_eq!(github_url.to_string(), "github:someorg/somerepo/1.0.0");
+        }
     }
 
     #[tokio::test]
     async fn flakehub_nixpkgs_to_github() {
-        let test_server = axum_test::TestServer::new(test_router().into_make_service()).unwrap();
-        let server_addr = test_server.server_address();
-        let server_url = server_addr.parse().unwrap();
+        let flakehub = mock_flakehub();
+        let server_url = flakehub.api_addr().parse().unwrap();
 
         let input_url =
             url::Url::parse("https://flakehub.com/f/nixos/nixpkgs/0.2305.*.tar.gz").unwrap();
-        let github_url = super::eject_input_to_github(&server_url, input_url)
+        let github_url = super::eject_input_to_github(&server_url, input_url, None)
             .await
             .ok()
             .flatten()
             .unwrap();
-        assert_eq!(github_url.to_string(), "github:nixos/nixpkgs/nixos-23.05");
+        assert_eq!(github_url.to_string(), "github:NixOS/nixpkgs/nixos-23.05");
     }
 
     #[tokio::test]
     async fn test_flake8_eject() {
-        let test_server = axum_test::TestServer::new(test_router().into_make_service()).unwrap();
-        let server_addr = test_server.server_address();
-        let server_url = server_addr.parse().unwrap();
+        let flakehub = mock_flakehub();
+        let server_url = flakehub.api_addr().parse().unwrap();
 
         let eject = super::EjectSubcommand {
-            flake_path: "".into(),
+            flake: crate::cli::cmd::FlakePathArgs { path: "".into() },
             dry_run: true,
             api_addr: server_url,
+            token: None,
         };
         let flake_contents = include_str!(concat!(
             env!("CARGO_MANIFEST_DIR"),
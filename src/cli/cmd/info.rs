@@ -0,0 +1,146 @@
+use std::io::IsTerminal;
+use std::process::ExitCode;
+
+use clap::Parser;
+use indicatif::{ProgressBar, ProgressStyle};
+use serde::{Deserialize, Serialize};
+use tabled::{Table, Tabled};
+
+use super::list::{bold, display_option, Flake};
+use super::{print_json, CommandExecute, FlakeHubClient, DEFAULT_STYLE};
+
+/// Shows information about a flake on FlakeHub, e.g. before adding it to your flake.nix.
+#[derive(Debug, Parser)]
+pub(crate) struct InfoSubcommand {
+    /// The flake to show information about, e.g. `NixOS/nixpkgs`.
+    flake: String,
+
+    /// Output results as JSON.
+    #[clap(long)]
+    json: bool,
+
+    #[clap(from_global)]
+    api_addr: url::Url,
+
+    #[clap(from_global)]
+    token: Option<String>,
+}
+
+/// The metadata FlakeHub has about the latest release of a flake.
+#[derive(Debug, Deserialize, Serialize)]
+pub(crate) struct ProjectMetadata {
+    pub(crate) project: String,
+    #[serde(default)]
+    pub(crate) description: Option<String>,
+    #[serde(default)]
+    pub(crate) simplified_version: Option<String>,
+    #[serde(default)]
+    pub(crate) spdx_identifier: Option<String>,
+    pub(crate) pretty_download_url: url::Url,
+}
+
+#[async_trait::async_trait]
+impl CommandExecute for InfoSubcommand {
+    async fn execute(self) -> color_eyre::Result<ExitCode> {
+        let pb = ProgressBar::new_spinner();
+        pb.set_style(ProgressStyle::default_spinner());
+
+        let client =
+            FlakeHubClient::with_saved_token(&self.api_addr, self.token.as_deref()).await?;
+        let flake = Flake::try_from(self.flake)?;
+
+        let metadata = client.project_metadata(&flake.org, &flake.project).await?;
+        let releases = client.releases(&flake.org, &flake.project).await?;
+
+        pb.finish_and_clear();
+
+        let info = FlakeInfo {
+            flake: flake.name(),
+            description: metadata.description,
+            latest_version: metadata.simplified_version,
+            versions: releases.len(),
+            license: metadata.spdx_identifier,
+            url: flake.url().to_string(),
+        };
+
+        if self.json {
+            print_json(&info)?;
+        } else if std::io::stdout().is_terminal() {
+            let mut table = Table::new([info]);
+            table.with(DEFAULT_STYLE.clone());
+            println!("{table}");
+        } else {
+            let mut writer = csv::Writer::from_writer(std::io::stdout());
+            writer.serialize(info)?;
+        }
+
+        Ok(ExitCode::SUCCESS)
+    }
+}
+
+#[derive(Tabled, Serialize)]
+struct FlakeInfo {
+    #[tabled(rename = "Flake", display_with = "bold")]
+    #[serde(rename = "Flake")]
+    flake: String,
+    #[tabled(rename = "Description", display_with = "display_option")]
+    #[serde(rename = "Description")]
+    description: Option<String>,
+    #[tabled(rename = "Latest version", display_with = "display_option")]
+    #[serde(rename = "Latest version")]
+    latest_version: Option<String>,
+    #[tabled(rename = "Versions")]
+    #[serde(rename = "Versions")]
+    versions: usize,
+    #[tabled(rename = "License", display_with = "display_option")]
+    #[serde(rename = "License")]
+    license: Option<String>,
+    #[tabled(rename = "FlakeHub URL")]
+    #[serde(rename = "FlakeHub URL")]
+    url: String,
+}
+
+#[cfg(test)]
+mod test {
+    use axum::extract::Path;
+
+    async fn project(Path((org, project)): Path<(String, String)>) -> axum::response::Response {
+        use axum::response::IntoResponse;
+
+        if org != "someorg" {
+            return axum::http::StatusCode::NOT_FOUND.into_response();
+        }
+
+        axum::Json(serde_json::json!({
+            "project": project,
+            "description": "Some flake",
+            "simplified_version": "0.1.2",
+            "spdx_identifier": "MIT",
+            "pretty_download_url": format!("https://flakehub.com/f/{org}/{project}/0.1.2.tar.gz"),
+        }))
+        .into_response()
+    }
+
+    #[tokio::test]
+    async fn test_project_metadata() {
+        let router = axum::Router::new().route("/f/:org/:project", axum::routing::get(project));
+        let test_server = axum_test::TestServer::new(router.into_make_service()).unwrap();
+        let api_addr = test_server.server_address().parse().unwrap();
+        let client = super::FlakeHubClient::new(&api_addr, None).unwrap();
+
+        let metadata = client
+            .project_metadata("someorg", "someflake")
+            .await
+            .unwrap();
+        assert_eq!(metadata.project, "someflake");
+        assert_eq!(metadata.description.as_deref(), Some("Some flake"));
+        assert_eq!(metadata.simplified_version.as_deref(), Some("0.1.2"));
+        assert_eq!(metadata.spdx_identifier.as_deref(), Some("MIT"));
+
+        let err = client
+            .project_metadata("otherorg", "someflake")
+            .await
+            .unwrap_err();
+        assert!(matches!(err, super::super::FhError::FlakeNotFound(_)));
+    }
+}
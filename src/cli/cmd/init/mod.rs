@@ -77,6 +77,9 @@ pub(crate) struct InitSubcommand {
 
     #[clap(from_global)]
     api_addr: url::Url,
+
+    #[clap(from_global)]
+    token: Option<String>,
 }
 
 #[async_trait::async_trait]
@@ -119,7 +122,7 @@ impl CommandExecute for InitSubcommand {
                 NIXPKGS_LATEST => FlakeHubUrl::latest("NixOS", "nixpkgs"),
                 NIXPKGS_23_05 => FlakeHubUrl::version("NixOS", "nixpkgs", "0.2305.*"),
                 NIXPKGS_UNSTABLE => FlakeHubUrl::unstable("NixOS", "nixpkgs"),
-                NIXPKGS_SPECIFIC => select_nixpkgs(&self.api_addr).await?,
+                NIXPKGS_SPECIFIC => select_nixpkgs(&self.api_addr, self.token.as_deref()).await?,
                 // Just in case
                 _ => return Err(FhError::Unreachable(String::from("nixpkgs selection")).into()),
             };
@@ -290,8 +293,8 @@ fn command_exists(cmd: &str) -> bool {
     Command::new(cmd).output().is_ok()
 }
 
-async fn select_nixpkgs(api_addr: &Url) -> Result<String, FhError> {
-    let client = &FlakeHubClient::new(api_addr)?;
+async fn select_nixpkgs(api_addr: &Url, token: Option<&str>) -> color_eyre::Result<String> {
+    let client = &FlakeHubClient::with_saved_token(api_addr, token).await?;
     let releases = client.releases("NixOS", "nixpkgs").await?;
     let releases: Vec<&str> = releases.iter().map(|r| r.version.as_str()).collect();
     let release = Prompt::select("Choose one of the following Nixpkgs releases:", &releases);
@@ -30,6 +30,18 @@ impl Prompt {
         }
     }
 
+    /// Like [`Prompt::bool`], but just pressing enter answers with `default`.
+    pub(crate) fn bool_with_default(msg: &str, default: bool) -> bool {
+        match Confirm::new(msg)
+            .with_default(default)
+            .with_render_config(*PROMPT_CONFIG)
+            .prompt()
+        {
+            Ok(b) => b,
+            Err(_) => exit(1),
+        }
+    }
+
     pub(crate) fn select(msg: &str, options: &[&str]) -> String {
         let result = Select::new(msg, options.to_vec())
             .with_render_config(*PROMPT_CONFIG)
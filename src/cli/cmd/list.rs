@@ -7,7 +7,7 @@ use std::process::ExitCode;
 use tabled::{Table, Tabled};
 use url::Url;
 
-use super::{print_json, FhError};
+use super::{print_json, search::SearchResult, FhError};
 use crate::cli::cmd::{FlakeHubClient, DEFAULT_STYLE};
 
 use super::CommandExecute;
@@ -26,6 +26,9 @@ pub(crate) struct ListSubcommand {
 
     #[arg(from_global)]
     api_addr: url::Url,
+
+    #[arg(from_global)]
+    token: Option<String>,
 }
 
 #[derive(Clone, Deserialize, Serialize)]
@@ -35,11 +38,11 @@ pub(crate) struct Flake {
 }
 
 impl Flake {
-    fn name(&self) -> String {
+    pub(crate) fn name(&self) -> String {
         format!("{}/{}", self.org, self.project)
     }
 
-    fn url(&self) -> Url {
+    pub(crate) fn url(&self) -> Url {
         let mut url = Url::parse(FLAKEHUB_WEB_ROOT)
             .expect("failed to parse flakehub web root url (this should never happen)");
         {
@@ -73,10 +76,18 @@ impl TryFrom<String> for Flake {
     }
 }
 
+#[derive(Deserialize, Serialize)]
+pub(crate) struct OrgFlake {
+    #[serde(flatten)]
+    pub(crate) flake: SearchResult,
+    #[serde(default)]
+    pub(crate) latest_version: Option<String>,
+}
+
 #[derive(Deserialize, Serialize)]
 pub(crate) struct Version {
-    version: semver::Version,
-    simplified_version: semver::Version,
+    pub(crate) version: semver::Version,
+    pub(crate) simplified_version: semver::Version,
 }
 
 #[derive(Deserialize, Serialize)]
@@ -87,6 +98,8 @@ pub(crate) struct Org {
 #[derive(Deserialize, Serialize)]
 pub(crate) struct Release {
     pub(crate) version: String,
+    #[serde(default)]
+    pub(crate) published_at: Option<String>,
 }
 
 #[derive(Subcommand)]
@@ -95,6 +108,11 @@ enum Subcommands {
     Flakes,
     /// Lists all public flakes with the provided label.
     Label { label: String },
+    /// Lists all public flakes published by the provided organization.
+    Org {
+        /// The organization whose flakes you want to list.
+        org: String,
+    },
     /// Lists all currently public organizations on FlakeHub.
     Orgs,
     /// List all releases for a specific flake on FlakeHub.
@@ -116,7 +134,8 @@ impl CommandExecute for ListSubcommand {
     async fn execute(self) -> color_eyre::Result<ExitCode> {
         use Subcommands::*;
 
-        let client = FlakeHubClient::new(&self.api_addr)?;
+        let client =
+            FlakeHubClient::with_saved_token(&self.api_addr, self.token.as_deref()).await?;
 
         match self.cmd {
             Flakes => {
@@ -182,6 +201,36 @@ impl CommandExecute for ListSubcommand {
                     Err(e) => return Err(e.into()),
                 }
             }
+            Org { org } => {
+                let pb = ProgressBar::new_spinner();
+                pb.set_style(ProgressStyle::default_spinner());
+
+                match client.flakes_by_org(&org).await {
+                    Ok(flakes) => {
+                        if flakes.is_empty() {
+                            eprintln!("No results");
+                        } else if self.json {
+                            print_json(&flakes)?;
+                        } else {
+                            let rows = flakes
+                                .into_iter()
+                                .map(Into::into)
+                                .collect::<Vec<OrgFlakeRow>>();
+                            if std::io::stdout().is_terminal() {
+                                let mut table = Table::new(rows);
+                                table.with(DEFAULT_STYLE.clone());
+                                println!("{table}");
+                            } else {
+                                let mut writer = csv::Writer::from_writer(std::io::stdout());
+                                for row in rows {
+                                    writer.serialize(row)?;
+                                }
+                            }
+                        }
+                    }
+                    Err(e) => return Err(e.into()),
+                }
+            }
             Orgs => {
                 let pb = ProgressBar::new_spinner();
                 pb.set_style(ProgressStyle::default_spinner());
@@ -248,7 +297,7 @@ impl CommandExecute for ListSubcommand {
                 let flake = Flake::try_from(flake)?.clone();
 
                 match client
-                    .versions(&flake.org, &flake.project, &constraint)
+                    .resolve_versions(&flake.org, &flake.project, &constraint)
                     .await
                 {
                     Ok(versions) => {
@@ -384,6 +433,29 @@ impl From<Flake> for FlakeRow {
     }
 }
 
+#[derive(Tabled, serde::Serialize)]
+struct OrgFlakeRow {
+    #[tabled(rename = "Flake", display_with = "bold")]
+    #[serde(rename = "Flake")]
+    flake: String,
+    #[tabled(rename = "Latest version", display_with = "display_option")]
+    #[serde(rename = "Latest version")]
+    latest_version: Option<String>,
+    #[tabled(rename = "FlakeHub URL", display_with = "dimmed")]
+    #[serde(rename = "FlakeHub URL")]
+    flakehub_url: Url,
+}
+
+impl From<OrgFlake> for OrgFlakeRow {
+    fn from(value: OrgFlake) -> Self {
+        Self {
+            flake: value.flake.name(),
+            latest_version: value.latest_version,
+            flakehub_url: value.flake.url(),
+        }
+    }
+}
+
 #[derive(Tabled, serde::Serialize)]
 pub(crate) struct ReleaseRow {
     #[serde(rename = "Version")]
@@ -398,10 +470,80 @@ impl From<Release> for ReleaseRow {
     }
 }
 
-fn dimmed(v: impl ToString) -> String {
+pub(crate) fn dimmed(v: impl ToString) -> String {
     v.to_string().dimmed().to_string()
 }
 
-fn bold(v: impl ToString) -> String {
+pub(crate) fn bold(v: impl ToString) -> String {
     v.to_string().bold().to_string()
 }
+
+pub(crate) fn display_option(v: &Option<String>) -> String {
+    v.as_deref().unwrap_or("-").to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use axum::extract::Path;
+
+    use super::super::FhError;
+
+    async fn org_flakes(Path(org): Path<String>) -> axum::response::Response {
+        use axum::response::IntoResponse;
+
+        if org != "someorg" {
+            return axum::http::StatusCode::NOT_FOUND.into_response();
+        }
+
+        axum::Json(serde_json::json!([
+            { "org": "someorg", "project": "a", "latest_version": "0.1.0" },
+            { "org": "someorg", "project": "b", "description": "No releases yet" },
+        ]))
+        .into_response()
+    }
+
+    fn test_server() -> axum_test::TestServer {
+        let router = axum::Router::new().route("/orgs/:org/flakes", axum::routing::get(org_flakes));
+        axum_test::TestServer::new(router.into_make_service()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_flakes_by_org() {
+        let test_server = test_server();
+        let api_addr = test_server.server_address().parse().unwrap();
+        let client = super::FlakeHubClient::new(&api_addr, None).unwrap();
+
+        let rows: Vec<super::OrgFlakeRow> = client
+            .flakes_by_org("someorg")
+            .await
+            .unwrap()
+            .into_iter()
+            .map(Into::into)
+            .collect();
+        let rows: Vec<(&str, Option<&str>)> = rows
+            .iter()
+            .map(|row| (row.flake.as_str(), row.latest_version.as_deref()))
+            .collect();
+        assert_eq!(rows, [("someorg/a", Some("0.1.0")), ("someorg/b", None)]);
+
+        let res = client.flakes_by_org("otherorg").await;
+        assert!(matches!(res, Err(FhError::OrgNotFound(org)) if org == "otherorg"));
+    }
+
+    #[tokio::test]
+    async fn test_list_org_not_found() {
+        let test_server = test_server();
+        let api_addr = test_server.server_address().to_string();
+        let cli = crate::cli::config::Config::default()
+            .parse_cli(["fh", "--api-addr", &api_addr, "list", "org", "otherorg"])
+            .unwrap();
+
+        let err = super::super::CommandExecute::execute(cli.subcommand)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "organization otherorg was not found on FlakeHub"
+        );
+    }
+}
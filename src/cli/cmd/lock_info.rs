@@ -0,0 +1,200 @@
+use std::collections::BTreeMap;
+use std::io::IsTerminal;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use clap::Parser;
+use color_eyre::eyre::WrapErr;
+use serde::Deserialize;
+use tabled::{Table, Tabled};
+
+use super::list::{bold, dimmed, display_option};
+use super::{print_json, CommandExecute, DEFAULT_STYLE};
+
+/// Shows what each of your flake's inputs is locked to in flake.lock.
+#[derive(Debug, Parser)]
+pub(crate) struct LockInfoSubcommand {
+    /// The flake.lock to read.
+    #[clap(long, default_value = "./flake.lock")]
+    pub(crate) lock_path: PathBuf,
+
+    /// Output results as JSON.
+    #[clap(long)]
+    json: bool,
+}
+
+/// The parts of a flake.lock we care about.
+#[derive(Debug, Deserialize)]
+struct FlakeLock {
+    nodes: BTreeMap<String, LockNode>,
+    root: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct LockNode {
+    #[serde(default)]
+    locked: Option<LockedRef>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LockedRef {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    rev: Option<String>,
+    #[serde(default)]
+    nar_hash: Option<String>,
+    #[serde(default)]
+    last_modified: Option<i64>,
+}
+
+#[async_trait::async_trait]
+impl CommandExecute for LockInfoSubcommand {
+    #[tracing::instrument(skip_all)]
+    async fn execute(self) -> color_eyre::Result<ExitCode> {
+        if !self.lock_path.exists() {
+            return Err(color_eyre::eyre::eyre!(
+                "the lock file at {} did not exist",
+                self.lock_path.display()
+            ));
+        }
+
+        let contents = tokio::fs::read_to_string(&self.lock_path).await?;
+        let lock: FlakeLock = serde_json::from_str(&contents)
+            .wrap_err_with(|| format!("Failed to parse {}", self.lock_path.display()))?;
+        let rows = lock_info_rows(lock);
+
+        if rows.is_empty() {
+            eprintln!("No inputs");
+        } else if self.json {
+            print_json(&rows)?;
+        } else if std::io::stdout().is_terminal() {
+            let mut table = Table::new(rows);
+            table.with(DEFAULT_STYLE.clone());
+            println!("{table}");
+        } else {
+            let mut writer = csv::Writer::from_writer(std::io::stdout());
+            for row in rows {
+                writer.serialize(row)?;
+            }
+        }
+
+        Ok(ExitCode::SUCCESS)
+    }
+}
+
+/// One row per locked node, sorted by node name. The root node (the flake itself) is left out.
+fn lock_info_rows(lock: FlakeLock) -> Vec<LockInfoRow> {
+    lock.nodes
+        .into_iter()
+        .filter(|(name, _)| *name != lock.root)
+        .filter_map(|(name, node)| {
+            let locked = node.locked?;
+
+            Some(LockInfoRow {
+                input: name,
+                kind: locked.kind,
+                rev: locked.rev,
+                nar_hash: locked.nar_hash,
+                last_modified: locked
+                    .last_modified
+                    .and_then(|timestamp| chrono::DateTime::from_timestamp(timestamp, 0))
+                    .map(|last_modified| last_modified.format("%Y-%m-%d %H:%M:%S UTC").to_string()),
+            })
+        })
+        .collect()
+}
+
+#[derive(Tabled, serde::Serialize)]
+struct LockInfoRow {
+    #[tabled(rename = "Input", display_with = "bold")]
+    #[serde(rename = "Input")]
+    input: String,
+    #[tabled(rename = "Type")]
+    #[serde(rename = "Type")]
+    kind: String,
+    #[tabled(rename = "Locked rev", display_with = "display_option")]
+    #[serde(rename = "Locked rev")]
+    rev: Option<String>,
+    #[tabled(skip)]
+    #[serde(rename = "NAR hash")]
+    nar_hash: Option<String>,
+    #[tabled(rename = "Last modified", display_with = "last_modified")]
+    #[serde(rename = "Last modified")]
+    last_modified: Option<String>,
+}
+
+fn last_modified(v: &Option<String>) -> String {
+    dimmed(display_option(v))
+}
+
+#[cfg(test)]
+mod test {
+    const FLAKE_LOCK: &str = r#"{
+  "nodes": {
+    "crane": {
+      "locked": {
+        "lastModified": 1696384830,
+        "narHash": "sha256-j8ZsVqzmj5sOm5MW9cqwQJUZELFFwOislDmqDDEMl6k=",
+        "owner": "ipetkov",
+        "repo": "crane",
+        "rev": "f2143cd27f8bd09ee4f0121336c65015a2a0a19c",
+        "type": "github"
+      },
+      "original": {
+        "owner": "ipetkov",
+        "repo": "crane",
+        "type": "github"
+      }
+    },
+    "nixpkgs": {
+      "locked": {
+        "narHash": "sha256-Ul3rIdesWaiW56PS/Ak3UlJdkwBrD4UcagCmXZR9Z7Y=",
+        "rev": "9a333eaa80901efe01df07eade2c16d183761fa3",
+        "type": "tarball",
+        "url": "https://api.flakehub.com/f/pinned/NixOS/nixpkgs/0.2305.491812/source.tar.gz"
+      },
+      "original": {
+        "type": "tarball",
+        "url": "https://flakehub.com/f/NixOS/nixpkgs/0.2305.tar.gz"
+      }
+    },
+    "root": {
+      "inputs": {
+        "crane": "crane",
+        "nixpkgs": "nixpkgs"
+      }
+    }
+  },
+  "root": "root",
+  "version": 7
+}"#;
+
+    #[test]
+    fn test_lock_info_rows() {
+        let lock = serde_json::from_str(FLAKE_LOCK).unwrap();
+        let rows = super::lock_info_rows(lock);
+
+        assert_eq!(rows.len(), 2);
+
+        assert_eq!(rows[0].input, "crane");
+        assert_eq!(rows[0].kind, "github");
+        assert_eq!(
+            rows[0].rev.as_deref(),
+            Some("f2143cd27f8bd09ee4f0121336c65015a2a0a19c")
+        );
+        assert_eq!(
+            rows[0].last_modified.as_deref(),
+            Some("2023-10-04 02:00:30 UTC")
+        );
+
+        assert_eq!(rows[1].input, "nixpkgs");
+        assert_eq!(rows[1].kind, "tarball");
+        assert_eq!(
+            rows[1].nar_hash.as_deref(),
+            Some("sha256-Ul3rIdesWaiW56PS/Ak3UlJdkwBrD4UcagCmXZR9Z7Y=")
+        );
+        assert_eq!(rows[1].last_modified, None);
+    }
+}
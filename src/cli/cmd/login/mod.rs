@@ -2,6 +2,7 @@ use std::path::PathBuf;
 use std::process::ExitCode;
 
 use clap::Parser;
+use color_eyre::eyre::WrapErr;
 use tokio::io::AsyncWriteExt;
 
 use super::CommandExecute;
@@ -146,3 +147,20 @@ pub(crate) fn auth_token_path() -> color_eyre::Result<PathBuf> {
 
     Ok(token_path)
 }
+
+/// The token saved by `fh login`, if there is one.
+pub(crate) async fn saved_token() -> color_eyre::Result<Option<String>> {
+    let xdg = xdg::BaseDirectories::new()?;
+    // $XDG_CONFIG_HOME/fh/auth; basically ~/.config/fh/auth
+    let token_path = xdg.get_config_file("flakehub/auth");
+
+    if !token_path.exists() {
+        return Ok(None);
+    }
+
+    let token = tokio::fs::read_to_string(&token_path)
+        .await
+        .wrap_err_with(|| format!("Could not open {}", token_path.display()))?;
+
+    Ok(Some(token.trim().to_string()))
+}
@@ -0,0 +1,466 @@
+//! A stand-in for the FlakeHub API, so that subcommands can be run end to end in tests by pointing
+//! `--api-addr` at it.
+
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::Arc;
+
+use axum::extract::{Path as UrlPath, State};
+use axum::response::{IntoResponse, Response};
+
+use super::CommandExecute;
+use crate::cli::config::Config;
+
+pub(crate) struct MockFlakeHub {
+    server: axum_test::TestServer,
+}
+
+/// What the mock serves.
+struct Projects {
+    /// `org/project`, in their canonical case.
+    names: Vec<String>,
+    /// The GitHub repository (and subdirectory) that a project is published from, for the
+    /// projects that aren't published from the repository of the same name.
+    sources: Vec<(String, String, Option<String>)>,
+}
+
+impl MockFlakeHub {
+    /// Serves the given projects (as `org/project`, in their canonical case). Any other project
+    /// isn't found.
+    pub(crate) fn new(projects: &[&str]) -> Self {
+        Self::with_sources(projects, &[])
+    }
+
+    /// Like [`MockFlakeHub::new`], but with the `(project, repository, subdirectory)` that some of
+    /// the projects are published from.
+    pub(crate) fn with_sources(projects: &[&str], sources: &[(&str, &str, Option<&str>)]) -> Self {
+        let projects = Arc::new(Projects {
+            names: projects.iter().map(ToString::to_string).collect(),
+            sources: sources
+                .iter()
+                .map(|(project, repo, subdirectory)| {
+                    (
+                        project.to_string(),
+                        repo.to_string(),
+                        subdirectory.map(ToString::to_string),
+                    )
+                })
+                .collect(),
+        });
+        let router = axum::Router::new()
+            .route("/f/:org/:project", axum::routing::get(project))
+            .route(
+                "/version/:org/:project/:version",
+                axum::routing::get(project_version),
+            )
+            .with_state(projects);
+
+        Self {
+            server: axum_test::TestServer::new(router.into_make_service()).unwrap(),
+        }
+    }
+
+    pub(crate) fn api_addr(&self) -> String {
+        self.server.server_address().to_string()
+    }
+
+    /// Runs `fh` with `args` against this server, the way `main` would.
+    pub(crate) async fn run(&self, args: &[&str]) -> color_eyre::Result<ExitCode> {
+        let api_addr = self.api_addr();
+        let cli = Config::default().parse_cli(
+            ["fh", "--api-addr", &api_addr]
+                .into_iter()
+                .chain(args.iter().copied()),
+        )?;
+
+        cli.subcommand.execute().await
+    }
+}
+
+/// Answers with the canonical names and source of the project, like FlakeHub does, or a 404.
+fn project_metadata(projects: &Projects, org: &str, project: &str, version: &str) -> Response {
+    let Some(name) = projects.names.iter().find(|canonical| {
+        canonical
+            .split_once('/')
+            .is_some_and(|(canonical_org, canonical_project)| {
+                canonical_org.eq_ignore_ascii_case(org)
+                    && canonical_project.eq_ignore_ascii_case(project)
+            })
+    }) else {
+        return axum::http::StatusCode::NOT_FOUND.into_response();
+    };
+    let (org, project) = name.split_once('/').unwrap();
+    if semver::VersionReq::parse(version).is_err() {
+        return axum::http::StatusCode::NOT_FOUND.into_response();
+    }
+
+    // `*` follows the project's rolling releases, and other requirements resolve to a release
+    // that matches them
+    let version = if version == "*" {
+        String::from("0.1.0+rev-eeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeee")
+    } else {
+        version.replace('*', "7")
+    };
+    let (source_repo, source_subdirectory) = projects
+        .sources
+        .iter()
+        .find(|(source_project, _, _)| source_project == name)
+        .map_or((name.as_str(), None), |(_, repo, subdirectory)| {
+            (repo.as_str(), subdirectory.as_deref())
+        });
+
+    axum::Json(serde_json::json!({
+        "project": project,
+        "pretty_download_url": format!("https://flakehub.com/f/{org}/{project}/{version}.tar.gz"),
+        "version": version,
+        "source_github_owner_repo_pair": source_repo,
+        "source_subdirectory": source_subdirectory,
+    }))
+    .into_response()
+}
+
+async fn project(
+    State(projects): State<Arc<Projects>>,
+    UrlPath((org, project)): UrlPath<(String, String)>,
+) -> Response {
+    project_metadata(&projects, &org, &project, "0.1.0")
+}
+
+async fn project_version(
+    State(projects): State<Arc<Projects>>,
+    UrlPath((org, project, version)): UrlPath<(String, String, String)>,
+) -> Response {
+    project_metadata(&projects, &org, &project, &version)
+}
+
+/// Writes `contents` to a flake.nix in a fresh directory named after `test`.
+pub(crate) async fn temp_flake(test: &str, contents: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("fh-{test}-{}", std::process::id()));
+    tokio::fs::create_dir_all(&dir).await.unwrap();
+    let flake_path = dir.join("flake.nix");
+    tokio::fs::write(&flake_path, contents).await.unwrap();
+
+    flake_path
+}
+
+pub(crate) async fn remove_temp_flake(flake_path: &Path) {
+    tokio::fs::remove_dir_all(flake_path.parent().unwrap())
+        .await
+        .unwrap();
+}
+
+mod test {
+    use std::process::ExitCode;
+
+    use super::{remove_temp_flake, temp_flake, MockFlakeHub};
+
+    const FLAKE: &str = r#"{
+  inputs.nixpkgs.url = "github:NixOS/nixpkgs/nixos-23.05";
+
+  outputs = { self, nixpkgs }: { };
+}
+"#;
+
+    async fn tar_gz() -> &'static str {
+        if crate::cli::cmd::add::nix_supports_bare_flakehub_urls().await {
+            ""
+        } else {
+            ".tar.gz"
+        }
+    }
+
+    #[tokio::test]
+    async fn test_add() {
+        let flakehub = MockFlakeHub::new(&["DeterminateSystems/fh"]);
+        let flake_path = temp_flake("add", FLAKE).await;
+
+        let exit_code = flakehub
+            .run(&[
+                "add",
+                "--flake-path",
+                flake_path.to_str().unwrap(),
+                "--no-lock",
+                "determinatesystems/fh",
+            ])
+            .await
+            .unwrap();
+        assert_eq!(exit_code, ExitCode::SUCCESS);
+
+        let new_flake_contents = tokio::fs::read_to_string(&flake_path).await.unwrap();
+        let expected = format!(
+            r#"inputs.fh.url = "https://flakehub.com/f/DeterminateSystems/fh/0.1.0{}";"#,
+            tar_gz().await
+        );
+        assert!(
+            new_flake_contents.contains(&expected),
+            "{new_flake_contents}"
+        );
+        assert!(new_flake_contents.contains("outputs = { self, nixpkgs, fh }: { };"));
+
+        remove_temp_flake(&flake_path).await;
+    }
+
+    #[tokio::test]
+    async fn test_bare_urls() {
+        let flakehub = MockFlakeHub::new(&["DeterminateSystems/fh", "NixOS/nixpkgs"]);
+        let flake_path = temp_flake("bare-urls", FLAKE).await;
+        let flake_path_str = flake_path.to_str().unwrap();
+
+        for args in [
+            &[
+                "add",
+                "--flake-path",
+                flake_path_str,
+                "--no-lock",
+                "--bare-urls",
+                "DeterminateSystems/fh",
+            ][..],
+            &[
+                "convert",
+                "--flake-path",
+                flake_path_str,
+                "--no-lock",
+                "--no-flake-compat-fixup",
+                "--bare-urls",
+            ],
+        ] {
+            assert_eq!(flakehub.run(args).await.unwrap(), ExitCode::SUCCESS);
+        }
+
+        let new_flake_contents = tokio::fs::read_to_string(&flake_path).await.unwrap();
+        for url in [
+            r#""https://flakehub.com/f/DeterminateSystems/fh/0.1.0";"#,
+            r#""https://flakehub.com/f/NixOS/nixpkgs/0.2305.0";"#,
+        ] {
+            assert!(new_flake_contents.contains(url), "{new_flake_contents}");
+        }
+
+        remove_temp_flake(&flake_path).await;
+    }
+
+    #[tokio::test]
+    async fn test_add_dry_run_print_changed() {
+        let flakehub = MockFlakeHub::new(&["DeterminateSystems/fh"]);
+        let flake_path = temp_flake("add-print-changed", FLAKE).await;
+
+        let exit_code = flakehub
+            .run(&[
+                "add",
+                "--flake-path",
+                flake_path.to_str().unwrap(),
+                "--dry-run",
+                "--print-changed",
+                "DeterminateSystems/fh",
+            ])
+            .await
+            .unwrap();
+        assert_eq!(exit_code, ExitCode::SUCCESS);
+        assert_eq!(tokio::fs::read_to_string(&flake_path).await.unwrap(), FLAKE);
+
+        remove_temp_flake(&flake_path).await;
+    }
+
+    #[tokio::test]
+    async fn test_add_several() {
+        let flakehub = MockFlakeHub::new(&["DeterminateSystems/fh", "ipetkov/crane"]);
+        let flake_path = temp_flake("add-several", FLAKE).await;
+
+        let exit_code = flakehub
+            .run(&[
+                "add",
+                "--flake-path",
+                flake_path.to_str().unwrap(),
+                "--no-lock",
+                "DeterminateSystems/fh",
+                "ipetkov/crane/0.1.*",
+            ])
+            .await
+            .unwrap();
+        assert_eq!(exit_code, ExitCode::SUCCESS);
+
+        let new_flake_contents = tokio::fs::read_to_string(&flake_path).await.unwrap();
+        let tar_gz = tar_gz().await;
+        for expected in [
+            format!(
+                r#"inputs.fh.url = "https://flakehub.com/f/DeterminateSystems/fh/0.1.0{tar_gz}";"#
+            ),
+            format!(r#"inputs.crane.url = "https://flakehub.com/f/ipetkov/crane/0.1.*{tar_gz}";"#),
+            String::from("outputs = { self, nixpkgs, fh, crane }: { };"),
+        ] {
+            assert!(
+                new_flake_contents.contains(&expected),
+                "{new_flake_contents}"
+            );
+        }
+
+        remove_temp_flake(&flake_path).await;
+    }
+
+    #[tokio::test]
+    async fn test_add_several_one_fails() {
+        let flakehub = MockFlakeHub::new(&["DeterminateSystems/fh"]);
+        let flake_path = temp_flake("add-several-one-fails", FLAKE).await;
+
+        // The first ref is fine, but the second isn't on FlakeHub
+        let err = flakehub
+            .run(&[
+                "add",
+                "--flake-path",
+                flake_path.to_str().unwrap(),
+                "--no-lock",
+                "DeterminateSystems/fh",
+                "someorg/missing",
+            ])
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("someorg/missing"), "{err:?}");
+        assert_eq!(tokio::fs::read_to_string(&flake_path).await.unwrap(), FLAKE);
+
+        remove_temp_flake(&flake_path).await;
+    }
+
+    #[tokio::test]
+    async fn test_convert() {
+        let flakehub = MockFlakeHub::new(&["NixOS/nixpkgs"]);
+        let flake_path = temp_flake("convert", FLAKE).await;
+
+        let exit_code = flakehub
+            .run(&[
+                "convert",
+                "--flake-path",
+                flake_path.to_str().unwrap(),
+                "--no-lock",
+                "--no-flake-compat-fixup",
+            ])
+            .await
+            .unwrap();
+        assert_eq!(exit_code, ExitCode::SUCCESS);
+
+        let new_flake_contents = tokio::fs::read_to_string(&flake_path).await.unwrap();
+        assert_eq!(
+            new_flake_contents,
+            FLAKE.replace(
+                "github:NixOS/nixpkgs/nixos-23.05",
+                &format!(
+                    "https://flakehub.com/f/NixOS/nixpkgs/0.2305.0{}",
+                    tar_gz().await
+                )
+            )
+        );
+
+        remove_temp_flake(&flake_path).await;
+    }
+
+    #[tokio::test]
+    async fn test_convert_output_path() {
+        let flakehub = MockFlakeHub::new(&["NixOS/nixpkgs"]);
+        let flake_path = temp_flake("convert-output-path", FLAKE).await;
+        let output_path = flake_path.with_file_name("converted.nix");
+
+        // Locking is skipped, since the flake.lock would belong to the original flake.nix
+        let exit_code = flakehub
+            .run(&[
+                "convert",
+                "--flake-path",
+                flake_path.to_str().unwrap(),
+                "--output-path",
+                output_path.to_str().unwrap(),
+            ])
+            .await
+            .unwrap();
+        assert_eq!(exit_code, ExitCode::SUCCESS);
+
+        assert_eq!(tokio::fs::read_to_string(&flake_path).await.unwrap(), FLAKE);
+        let converted = tokio::fs::read_to_string(&output_path).await.unwrap();
+        assert_eq!(
+            converted,
+            FLAKE.replace(
+                "github:NixOS/nixpkgs/nixos-23.05",
+                &format!(
+                    "https://flakehub.com/f/NixOS/nixpkgs/0.2305.0{}",
+                    tar_gz().await
+                )
+            )
+        );
+        assert!(!flake_path.with_file_name("flake.lock").exists());
+
+        remove_temp_flake(&flake_path).await;
+    }
+
+    #[tokio::test]
+    async fn test_convert_preview_url() {
+        let flakehub = MockFlakeHub::new(&["NixOS/nixpkgs"]);
+        let flake_path = temp_flake("convert-preview-url", FLAKE).await;
+
+        let exit_code = flakehub
+            .run(&[
+                "convert",
+                "--flake-path",
+                flake_path.to_str().unwrap(),
+                "--preview-url",
+            ])
+            .await
+            .unwrap();
+        assert_eq!(exit_code, ExitCode::SUCCESS);
+        assert_eq!(tokio::fs::read_to_string(&flake_path).await.unwrap(), FLAKE);
+
+        remove_temp_flake(&flake_path).await;
+    }
+
+    #[tokio::test]
+    async fn test_convert_recursive() {
+        let flakehub = MockFlakeHub::new(&["NixOS/nixpkgs"]);
+        let flake_path = temp_flake("convert-recursive", FLAKE).await;
+        let subflake_path = flake_path.with_file_name("sub").join("flake.nix");
+        tokio::fs::create_dir_all(subflake_path.parent().unwrap())
+            .await
+            .unwrap();
+        tokio::fs::write(&subflake_path, FLAKE).await.unwrap();
+
+        let exit_code = flakehub
+            .run(&[
+                "convert",
+                "--flake-path",
+                flake_path.to_str().unwrap(),
+                "--no-lock",
+                "--recursive",
+            ])
+            .await
+            .unwrap();
+        assert_eq!(exit_code, ExitCode::SUCCESS);
+
+        for path in [&flake_path, &subflake_path] {
+            let new_flake_contents = tokio::fs::read_to_string(path).await.unwrap();
+            assert!(
+                new_flake_contents.contains("https://flakehub.com/f/NixOS/nixpkgs/0.2305.0"),
+                "{new_flake_contents}"
+            );
+        }
+
+        remove_temp_flake(&flake_path).await;
+    }
+
+    #[tokio::test]
+    async fn test_add_missing_project() {
+        let flakehub = MockFlakeHub::new(&[]);
+        let flake_path = temp_flake("add-missing", FLAKE).await;
+
+        let err = flakehub
+            .run(&[
+                "add",
+                "--flake-path",
+                flake_path.to_str().unwrap(),
+                "--no-lock",
+                "someorg/missing",
+            ])
+            .await
+            .unwrap_err();
+        assert!(
+            format!("{err:#}").contains("no FlakeHub project found for someorg/missing"),
+            "{err}"
+        );
+        assert_eq!(tokio::fs::read_to_string(&flake_path).await.unwrap(), FLAKE);
+
+        remove_temp_flake(&flake_path).await;
+    }
+}
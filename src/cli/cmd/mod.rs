@@ -1,12 +1,25 @@
 pub(crate) mod add;
 pub(crate) mod completion;
 pub(crate) mod convert;
+pub(crate) mod doctor;
 pub(crate) mod eject;
+pub(crate) mod info;
 pub(crate) mod init;
 pub(crate) mod list;
+pub(crate) mod lock_info;
 pub(crate) mod login;
+#[cfg(test)]
+pub(crate) mod mock_flakehub;
+pub(crate) mod outdated;
+pub(crate) mod remove;
 pub(crate) mod search;
 pub(crate) mod status;
+pub(crate) mod update;
+pub(crate) mod versions;
+pub(crate) mod whoami;
+
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::Duration;
 
 use once_cell::sync::Lazy;
 use reqwest::Client as HttpClient;
@@ -17,8 +30,10 @@ use tabled::settings::{
 };
 
 use self::{
-    list::{Flake, Org, Release, Version},
+    info::ProjectMetadata,
+    list::{Flake, Org, OrgFlake, Release, Version},
     search::SearchResult,
+    whoami::User,
 };
 
 #[allow(clippy::type_complexity)]
@@ -58,6 +73,52 @@ pub(crate) enum FhSubcommands {
     Login(login::LoginSubcommand),
     Status(status::StatusSubcommand),
     Eject(eject::EjectSubcommand),
+    Remove(remove::RemoveSubcommand),
+    Update(update::UpdateSubcommand),
+    Versions(versions::VersionsSubcommand),
+    Outdated(outdated::OutdatedSubcommand),
+    Info(info::InfoSubcommand),
+    LockInfo(lock_info::LockInfoSubcommand),
+    Doctor(doctor::DoctorSubcommand),
+    Whoami(whoami::WhoamiSubcommand),
+}
+
+/// The `--flake-path` of the subcommands that read a flake, each of which describes what it does
+/// with the flake by overriding the help with `mut_arg`.
+#[derive(Debug, Clone, clap::Args)]
+pub(crate) struct FlakePathArgs {
+    #[clap(
+        id = "flake_path",
+        long = "flake-path",
+        value_name = "FLAKE_PATH",
+        default_value = "./flake.nix"
+    )]
+    pub(crate) path: std::path::PathBuf,
+}
+
+#[async_trait::async_trait]
+impl CommandExecute for FhSubcommands {
+    async fn execute(self) -> color_eyre::Result<std::process::ExitCode> {
+        match self {
+            FhSubcommands::Add(add) => add.execute().await,
+            FhSubcommands::Init(init) => init.execute().await,
+            FhSubcommands::List(list) => list.execute().await,
+            FhSubcommands::Search(search) => search.execute().await,
+            FhSubcommands::Completion(completion) => completion.execute().await,
+            FhSubcommands::Convert(convert) => convert.execute().await,
+            FhSubcommands::Login(login) => login.execute().await,
+            FhSubcommands::Status(status) => status.execute().await,
+            FhSubcommands::Eject(eject) => eject.execute().await,
+            FhSubcommands::Remove(remove) => remove.execute().await,
+            FhSubcommands::Update(update) => update.execute().await,
+            FhSubcommands::Versions(versions) => versions.execute().await,
+            FhSubcommands::Outdated(outdated) => outdated.execute().await,
+            FhSubcommands::Info(info) => info.execute().await,
+            FhSubcommands::LockInfo(lock_info) => lock_info.execute().await,
+            FhSubcommands::Doctor(doctor) => doctor.execute().await,
+            FhSubcommands::Whoami(whoami) => whoami.execute().await,
+        }
+    }
 }
 
 pub(crate) struct FlakeHubClient {
@@ -67,15 +128,26 @@ pub(crate) struct FlakeHubClient {
 
 #[derive(Debug, thiserror::Error)]
 pub(crate) enum FhError {
+    #[error(
+        "authentication required; run `fh login`, or pass a token with `--token` or `FH_TOKEN`"
+    )]
+    AuthenticationRequired,
+
     #[error("file error: {0}")]
     Filesystem(#[from] std::io::Error),
 
+    #[error("flake {0} was not found on FlakeHub")]
+    FlakeNotFound(String),
+
     #[error("flake name parsing error: {0}")]
     FlakeParse(String),
 
     #[error("http error: {0}")]
     Http(#[from] reqwest::Error),
 
+    #[error("invalid FlakeHub token: {0}")]
+    InvalidToken(#[from] reqwest::header::InvalidHeaderValue),
+
     #[error("interactive initializer error: {0}")]
     Interactive(#[from] inquire::InquireError),
 
@@ -88,6 +160,9 @@ pub(crate) enum FhError {
     #[error("the flake has no inputs")]
     NoInputs,
 
+    #[error("organization {0} was not found on FlakeHub")]
+    OrgNotFound(String),
+
     #[error("template error: {0}")]
     Render(#[from] handlebars::RenderError),
 
@@ -102,12 +177,17 @@ pub(crate) enum FhError {
 }
 
 impl FlakeHubClient {
-    pub(crate) fn new(api_addr: &url::Url) -> Result<Self, FhError> {
+    pub(crate) fn new(api_addr: &url::Url, token: Option<&str>) -> Result<Self, FhError> {
         let mut headers = reqwest::header::HeaderMap::new();
         headers.insert(
             "Accept",
             reqwest::header::HeaderValue::from_static("application/json"),
         );
+        if let Some(token) = token {
+            let mut value = reqwest::header::HeaderValue::from_str(&format!("Bearer {token}"))?;
+            value.set_sensitive(true);
+            headers.insert(reqwest::header::AUTHORIZATION, value);
+        }
 
         let client = reqwest::Client::builder()
             .user_agent(crate::APP_USER_AGENT)
@@ -120,16 +200,38 @@ impl FlakeHubClient {
         })
     }
 
+    /// Like [`FlakeHubClient::new`], but without a `token` it authenticates with the token saved
+    /// by `fh login`, if any.
+    pub(crate) async fn with_saved_token(
+        api_addr: &url::Url,
+        token: Option<&str>,
+    ) -> color_eyre::Result<Self> {
+        let token = match token {
+            Some(token) => Some(token.to_string()),
+            None => login::saved_token().await?,
+        };
+
+        Ok(Self::new(api_addr, token.as_deref())?)
+    }
+
+    /// Sends the request, turning a 401 or 403 into [`FhError::AuthenticationRequired`].
+    async fn send(request: reqwest::RequestBuilder) -> Result<reqwest::Response, FhError> {
+        let res = send_with_retries(request).await?;
+
+        match res.status() {
+            reqwest::StatusCode::UNAUTHORIZED | reqwest::StatusCode::FORBIDDEN => {
+                Err(FhError::AuthenticationRequired)
+            }
+            _ => Ok(res),
+        }
+    }
+
     pub(crate) async fn search(&self, query: String) -> Result<Vec<SearchResult>, FhError> {
         let params = [("q", query)];
 
         let endpoint = self.api_addr.join("search")?;
 
-        let results = self
-            .client
-            .get(endpoint)
-            .query(&params)
-            .send()
+        let results = Self::send(self.client.get(endpoint).query(&params))
             .await?
             .json::<Vec<SearchResult>>()
             .await?;
@@ -140,10 +242,7 @@ impl FlakeHubClient {
     async fn flakes(&self) -> Result<Vec<Flake>, FhError> {
         let endpoint = self.api_addr.join("flakes")?;
 
-        let flakes = self
-            .client
-            .get(endpoint)
-            .send()
+        let flakes = Self::send(self.client.get(endpoint))
             .await?
             .json::<Vec<Flake>>()
             .await?;
@@ -161,10 +260,7 @@ impl FlakeHubClient {
             segs.push("label").push(label);
         }
 
-        let flakes = self
-            .client
-            .get(&url.to_string())
-            .send()
+        let flakes = Self::send(self.client.get(url))
             .await?
             .json::<Vec<Flake>>()
             .await?;
@@ -172,6 +268,27 @@ impl FlakeHubClient {
         Ok(flakes)
     }
 
+    async fn flakes_by_org(&self, org: &str) -> Result<Vec<OrgFlake>, FhError> {
+        let mut url = self.api_addr.clone();
+        {
+            let mut segs = url
+                .path_segments_mut()
+                .expect("flakehub url cannot be base (this should never happen)");
+
+            segs.push("orgs").push(org).push("flakes");
+        }
+
+        let res = Self::send(self.client.get(url)).await?;
+
+        if res.status() == reqwest::StatusCode::NOT_FOUND {
+            return Err(FhError::OrgNotFound(org.to_string()));
+        }
+
+        let flakes = res.error_for_status()?.json::<Vec<OrgFlake>>().await?;
+
+        Ok(flakes)
+    }
+
     async fn releases(&self, org: &str, project: &str) -> Result<Vec<Release>, FhError> {
         let mut url = self.api_addr.clone();
         {
@@ -182,10 +299,7 @@ impl FlakeHubClient {
             segs.push("f").push(org).push(project).push("releases");
         }
 
-        let flakes = self
-            .client
-            .get(&url.to_string())
-            .send()
+        let flakes = Self::send(self.client.get(url))
             .await?
             .json::<Vec<Release>>()
             .await?;
@@ -193,13 +307,71 @@ impl FlakeHubClient {
         Ok(flakes)
     }
 
+    async fn project_metadata(&self, org: &str, project: &str) -> Result<ProjectMetadata, FhError> {
+        let mut url = self.api_addr.clone();
+        {
+            let mut segs = url
+                .path_segments_mut()
+                .expect("flakehub url cannot be base (this should never happen)");
+
+            segs.push("f").push(org).push(project);
+        }
+
+        let res = Self::send(self.client.get(url)).await?;
+
+        if res.status() == reqwest::StatusCode::NOT_FOUND {
+            return Err(FhError::FlakeNotFound(format!("{org}/{project}")));
+        }
+
+        let metadata = res.error_for_status()?.json::<ProjectMetadata>().await?;
+
+        Ok(metadata)
+    }
+
+    /// The version FlakeHub published for the Git tag `tag` of `org/project`, if any.
+    pub(crate) async fn resolve_tag(
+        &self,
+        org: &str,
+        project: &str,
+        tag: &str,
+    ) -> Result<Option<String>, FhError> {
+        let mut url = self.api_addr.clone();
+        {
+            let mut segs = url
+                .path_segments_mut()
+                .expect("flakehub url cannot be base (this should never happen)");
+
+            segs.push("f").push(org).push(project).push("tag").push(tag);
+        }
+
+        let res = Self::send(self.client.get(url)).await?;
+
+        if res.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+
+        let release = res.error_for_status()?.json::<Release>().await?;
+
+        Ok(Some(release.version))
+    }
+
+    /// The user the token belongs to.
+    async fn me(&self) -> Result<User, FhError> {
+        let endpoint = self.api_addr.join("me")?;
+
+        let user = Self::send(self.client.get(endpoint))
+            .await?
+            .error_for_status()?
+            .json::<User>()
+            .await?;
+
+        Ok(user)
+    }
+
     async fn orgs(&self) -> Result<Vec<Org>, FhError> {
         let endpoint = self.api_addr.join("orgs")?;
 
-        let orgs = self
-            .client
-            .get(endpoint)
-            .send()
+        let orgs = Self::send(self.client.get(endpoint))
             .await?
             .json::<Vec<Org>>()
             .await?;
@@ -207,7 +379,8 @@ impl FlakeHubClient {
         Ok(orgs)
     }
 
-    async fn versions(
+    /// The versions of a flake that match `constraint`, as FlakeHub resolves it.
+    async fn resolve_versions(
         &self,
         org: &str,
         project: &str,
@@ -228,16 +401,91 @@ impl FlakeHubClient {
                 .push(&version);
         }
 
-        let versions = self
-            .client
-            .get(url)
-            .send()
+        let versions = Self::send(self.client.get(url))
             .await?
             .json::<Vec<Version>>()
             .await?;
 
         Ok(versions)
     }
+
+    /// The published versions of a flake, newest first. Releases that aren't SemVer versions are
+    /// left out, and so are pre-releases (like `1.0.0-rc.1`) unless `include_pre`.
+    async fn versions(
+        &self,
+        org: &str,
+        project: &str,
+        include_pre: bool,
+    ) -> Result<Vec<Release>, FhError> {
+        let mut versions: Vec<(semver::Version, Release)> = self
+            .releases(org, project)
+            .await?
+            .into_iter()
+            .filter_map(|release| {
+                let version = semver::Version::parse(&release.version).ok()?;
+                (include_pre || version.pre.is_empty()).then_some((version, release))
+            })
+            .collect();
+        versions.sort_by(|(a, _), (b, _)| b.cmp(a));
+
+        Ok(versions.into_iter().map(|(_, release)| release).collect())
+    }
+}
+
+/// The number of times a FlakeHub request is retried by default, i.e. 3 attempts in total.
+pub(crate) const DEFAULT_RETRIES: u32 = 2;
+
+static RETRIES: AtomicU32 = AtomicU32::new(DEFAULT_RETRIES);
+
+/// The delay before the first retry; it doubles with every subsequent one.
+const RETRY_BASE_DELAY: Duration = Duration::from_millis(250);
+
+/// Sets how many times [`send_with_retries`] retries a failed request, from `--retries`.
+pub(crate) fn set_retries(retries: u32) {
+    RETRIES.store(retries, Ordering::Relaxed);
+}
+
+/// Sends the request, retrying with exponential backoff (and some jitter) on connection errors
+/// and 5xx responses. Other responses, including 4xx ones, are returned as-is.
+pub(crate) async fn send_with_retries(
+    request: reqwest::RequestBuilder,
+) -> Result<reqwest::Response, reqwest::Error> {
+    let retries = RETRIES.load(Ordering::Relaxed);
+    let mut attempt = 0;
+
+    loop {
+        // Requests with streaming bodies can't be cloned, and thus can't be retried.
+        let Some(this_request) = request.try_clone().filter(|_| attempt < retries) else {
+            return request.send().await;
+        };
+
+        match this_request.send().await {
+            Ok(res) if res.status().is_server_error() => {
+                tracing::debug!(status = %res.status(), attempt, "FlakeHub request failed; retrying");
+            }
+            Err(e) if e.is_connect() || e.is_timeout() => {
+                tracing::debug!(error = %e, attempt, "FlakeHub request failed; retrying");
+            }
+            res => return res,
+        }
+
+        tokio::time::sleep(retry_delay(attempt)).await;
+        attempt += 1;
+    }
+}
+
+fn retry_delay(attempt: u32) -> Duration {
+    let delay = RETRY_BASE_DELAY * 2u32.saturating_pow(attempt);
+
+    // Jitter by up to half the delay so that concurrent requests don't retry in lockstep. This
+    // doesn't need to be cryptographically random, so the clock is good enough.
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|now| now.subsec_nanos())
+        .unwrap_or_default();
+    let jitter = delay.mul_f64(f64::from(nanos % 1000) / 2000.0);
+
+    delay + jitter
 }
 
 pub(crate) fn print_json<T: Serialize>(value: T) -> Result<(), FhError> {
@@ -245,3 +493,95 @@ pub(crate) fn print_json<T: Serialize>(value: T) -> Result<(), FhError> {
     println!("{}", json);
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use axum::http::StatusCode;
+
+    fn flaky_router(hits: Arc<AtomicUsize>, failures: usize, status: StatusCode) -> axum::Router {
+        axum::Router::new().route(
+            "/",
+            axum::routing::get(move || async move {
+                if hits.fetch_add(1, Ordering::SeqCst) < failures {
+                    status
+                } else {
+                    StatusCode::OK
+                }
+            }),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_retries_server_errors() {
+        let hits = Arc::new(AtomicUsize::new(0));
+        let test_server = axum_test::TestServer::new(
+            flaky_router(hits.clone(), 2, StatusCode::BAD_GATEWAY).into_make_service(),
+        )
+        .unwrap();
+
+        let res =
+            super::send_with_retries(reqwest::Client::new().get(test_server.server_address()))
+                .await
+                .unwrap();
+
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(hits.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_does_not_retry_client_errors() {
+        let hits = Arc::new(AtomicUsize::new(0));
+        let test_server = axum_test::TestServer::new(
+            flaky_router(hits.clone(), 1, StatusCode::NOT_FOUND).into_make_service(),
+        )
+        .unwrap();
+
+        let res =
+            super::send_with_retries(reqwest::Client::new().get(test_server.server_address()))
+                .await
+                .unwrap();
+
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_authentication_required() {
+        let router = axum::Router::new().route(
+            "/f/:org/:project/releases",
+            axum::routing::get(|headers: axum::http::HeaderMap| async move {
+                match headers
+                    .get(axum::http::header::AUTHORIZATION)
+                    .and_then(|value| value.to_str().ok())
+                {
+                    Some("Bearer secret") => Ok(axum::Json(serde_json::json!([]))),
+                    Some(_) => Err(StatusCode::FORBIDDEN),
+                    None => Err(StatusCode::UNAUTHORIZED),
+                }
+            }),
+        );
+        let test_server = axum_test::TestServer::new(router.into_make_service()).unwrap();
+        let api_addr = test_server.server_address().parse().unwrap();
+
+        for token in [None, Some("wrong")] {
+            let client = super::FlakeHubClient::new(&api_addr, token).unwrap();
+            let res = client.releases("someorg", "someflake").await;
+            assert!(
+                matches!(res, Err(super::FhError::AuthenticationRequired)),
+                "{token:?}"
+            );
+        }
+
+        let client = super::FlakeHubClient::with_saved_token(&api_addr, Some("secret"))
+            .await
+            .unwrap();
+        assert!(client
+            .releases("someorg", "someflake")
+            .await
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -0,0 +1,224 @@
+use std::io::IsTerminal;
+use std::process::ExitCode;
+
+use clap::Parser;
+use indicatif::{ProgressBar, ProgressStyle};
+use tabled::{Table, Tabled};
+
+use super::list::{bold, dimmed};
+use super::{print_json, CommandExecute, FlakeHubClient, DEFAULT_STYLE};
+
+/// Reports which of your flake's FlakeHub inputs have newer versions available.
+///
+/// Exits with a non-zero status if any input is outdated.
+#[derive(Debug, Parser)]
+#[command(mut_arg("flake_path", |arg| arg.help("The flake.nix to check")))]
+pub(crate) struct OutdatedSubcommand {
+    #[clap(flatten)]
+    pub(crate) flake: super::FlakePathArgs,
+
+    /// Output results as JSON.
+    #[clap(long)]
+    json: bool,
+
+    #[clap(from_global)]
+    api_addr: url::Url,
+
+    #[clap(from_global)]
+    token: Option<String>,
+}
+
+#[async_trait::async_trait]
+impl CommandExecute for OutdatedSubcommand {
+    #[tracing::instrument(skip_all)]
+    async fn execute(self) -> color_eyre::Result<ExitCode> {
+        if !self.flake.path.exists() {
+            return Err(color_eyre::eyre::eyre!(
+                "the flake at {} did not exist",
+                self.flake.path.display()
+            ));
+        }
+
+        let pb = (!self.json).then(|| {
+            let pb = ProgressBar::new_spinner();
+            pb.set_style(ProgressStyle::default_spinner());
+            pb
+        });
+
+        let client =
+            FlakeHubClient::with_saved_token(&self.api_addr, self.token.as_deref()).await?;
+        let (flake_contents, parsed) = crate::cli::cmd::add::load_flake(&self.flake.path).await?;
+
+        let all_toplevel_inputs = crate::cli::cmd::add::flake::find_all_attrsets_by_path(
+            &parsed.expression,
+            Some(["inputs".into()].into()),
+        )
+        .map_err(|err| crate::cli::cmd::add::flake::with_source_context(err, &flake_contents))?;
+        let all_inputs = crate::cli::cmd::add::flake::collect_all_inputs(all_toplevel_inputs)?;
+
+        let mut rows = Vec::new();
+        for input in all_inputs.iter() {
+            let Some(input_name) = input.name() else {
+                tracing::debug!("couldn't get input name from attrpath, skipping");
+                continue;
+            };
+
+            let url = crate::cli::cmd::convert::find_input_value_by_path(
+                &input.binding.to,
+                ["url".into()].into(),
+            )
+            .map_err(|err| crate::cli::cmd::add::flake::with_source_context(err, &flake_contents))?
+            .and_then(|url| url.parse::<url::Url>().ok());
+            let Some((org, project, current, _)) = url
+                .as_ref()
+                .and_then(crate::cli::cmd::update::parse_flakehub_url)
+            else {
+                rows.push(OutdatedRow::unmanaged(input_name));
+                continue;
+            };
+
+            let versions = client.resolve_versions(&org, &project, "*").await?;
+            let latest = versions
+                .into_iter()
+                .map(|version| version.simplified_version)
+                .max();
+
+            let current = current.unwrap_or_else(|| String::from("*"));
+            let status = match &latest {
+                Some(latest) if is_up_to_date(&current, latest) => Status::UpToDate,
+                Some(_) => Status::Outdated,
+                None => Status::Unknown,
+            };
+
+            rows.push(OutdatedRow {
+                input: input_name,
+                current: Some(current),
+                latest: latest.map(|latest| latest.to_string()),
+                status,
+            });
+        }
+
+        if let Some(pb) = pb {
+            pb.finish_and_clear();
+        }
+
+        let any_outdated = rows.iter().any(|row| row.status == Status::Outdated);
+
+        if rows.is_empty() {
+            eprintln!("No inputs");
+        } else if self.json {
+            print_json(&rows)?;
+        } else if std::io::stdout().is_terminal() {
+            let mut table = Table::new(rows);
+            table.with(DEFAULT_STYLE.clone());
+            println!("{table}");
+        } else {
+            let mut writer = csv::Writer::from_writer(std::io::stdout());
+            for row in rows {
+                writer.serialize(row)?;
+            }
+        }
+
+        if any_outdated {
+            Ok(ExitCode::FAILURE)
+        } else {
+            Ok(ExitCode::SUCCESS)
+        }
+    }
+}
+
+/// Whether the version (or version requirement) an input is pinned to already covers `latest`.
+fn is_up_to_date(current: &str, latest: &semver::Version) -> bool {
+    let current = current.strip_prefix('v').unwrap_or(current);
+
+    if let Ok(current) = semver::Version::parse(current) {
+        current.cmp_precedence(latest).is_ge()
+    } else if let Ok(req) = semver::VersionReq::parse(current) {
+        req.matches(latest)
+    } else {
+        false
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+enum Status {
+    UpToDate,
+    Outdated,
+    Unknown,
+    Unmanaged,
+}
+
+impl std::fmt::Display for Status {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let status = match self {
+            Status::UpToDate => "yes",
+            Status::Outdated => "no",
+            Status::Unknown => "unknown",
+            Status::Unmanaged => "unmanaged",
+        };
+
+        write!(f, "{status}")
+    }
+}
+
+#[derive(Tabled, serde::Serialize)]
+struct OutdatedRow {
+    #[tabled(rename = "Input", display_with = "bold")]
+    #[serde(rename = "Input")]
+    input: String,
+    #[tabled(rename = "Current", display_with = "display_version")]
+    #[serde(rename = "Current")]
+    current: Option<String>,
+    #[tabled(rename = "Latest", display_with = "display_version")]
+    #[serde(rename = "Latest")]
+    latest: Option<String>,
+    #[tabled(rename = "Up to date?")]
+    #[serde(rename = "Up to date?")]
+    status: Status,
+}
+
+impl OutdatedRow {
+    fn unmanaged(input: String) -> Self {
+        Self {
+            input,
+            current: None,
+            latest: None,
+            status: Status::Unmanaged,
+        }
+    }
+}
+
+fn display_version(v: &Option<String>) -> String {
+    match v {
+        Some(v) => v.to_string(),
+        None => dimmed("-"),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #[test]
+    fn test_is_up_to_date() {
+        let latest = semver::Version::new(0, 2311, 5);
+
+        assert!(super::is_up_to_date("0.2311.5", &latest));
+        assert!(super::is_up_to_date("v0.2311.5", &latest));
+        assert!(super::is_up_to_date("0.2311.*", &latest));
+        assert!(super::is_up_to_date("*", &latest));
+        assert!(!super::is_up_to_date("0.2305.1", &latest));
+        assert!(!super::is_up_to_date("0.2305.*", &latest));
+        assert!(!super::is_up_to_date("not-a-version", &latest));
+    }
+
+    #[test]
+    fn test_is_up_to_date_zip() {
+        let latest = semver::Version::new(0, 2311, 5);
+        let url = "https://flakehub.com/f/NixOS/nixpkgs/0.2311.5.zip"
+            .parse()
+            .unwrap();
+
+        let (_, _, current, _) = crate::cli::cmd::update::parse_flakehub_url(&url).unwrap();
+        assert!(super::is_up_to_date(&current.unwrap(), &latest));
+    }
+}
@@ -0,0 +1,291 @@
+use std::collections::VecDeque;
+use std::process::ExitCode;
+
+use clap::Parser;
+
+use super::CommandExecute;
+
+/// Removes a flake input from your flake.nix.
+#[derive(Debug, Parser)]
+#[command(mut_arg("flake_path", |arg| arg.help("The flake.nix to modify")))]
+pub(crate) struct RemoveSubcommand {
+    #[clap(flatten)]
+    pub(crate) flake: super::FlakePathArgs,
+
+    /// The name of the flake input to remove.
+    pub(crate) input_name: String,
+
+    /// Print to stdout the new flake.nix contents instead of writing it to disk.
+    #[clap(long)]
+    pub(crate) dry_run: bool,
+}
+
+#[async_trait::async_trait]
+impl CommandExecute for RemoveSubcommand {
+    #[tracing::instrument(skip_all)]
+    async fn execute(self) -> color_eyre::Result<ExitCode> {
+        if !self.flake.path.exists() {
+            return Err(color_eyre::eyre::eyre!(
+                "the flake at {} did not exist",
+                self.flake.path.display()
+            ));
+        }
+
+        let (flake_contents, parsed) = crate::cli::cmd::add::load_flake(&self.flake.path).await?;
+        let new_flake_contents =
+            remove_flake_input(&parsed.expression, &self.input_name, &flake_contents).map_err(
+                |err| crate::cli::cmd::add::flake::with_source_context(err, &flake_contents),
+            )?;
+        let new_flake_contents =
+            crate::cli::cmd::add::match_trailing_newline(&flake_contents, new_flake_contents);
+
+        if self.dry_run {
+            println!("{new_flake_contents}");
+        } else {
+            tokio::fs::write(self.flake.path, new_flake_contents).await?;
+        }
+
+        Ok(ExitCode::SUCCESS)
+    }
+}
+
+#[tracing::instrument(skip_all)]
+pub(crate) fn remove_flake_input(
+    expr: &nixel::Expression,
+    input_name: &str,
+    flake_contents: &str,
+) -> color_eyre::Result<String> {
+    let input_attr_path: VecDeque<String> = ["inputs".into(), input_name.into()].into();
+    let input_attrs =
+        crate::cli::cmd::add::flake::find_all_attrsets_by_path(expr, Some(input_attr_path))?;
+
+    if input_attrs.is_empty() {
+        return Err(color_eyre::eyre::eyre!(
+            "there was no `inputs.{input_name}` attribute to remove"
+        ));
+    }
+
+    let all_toplevel_inputs = crate::cli::cmd::add::flake::find_all_attrsets_by_path(
+        expr,
+        Some(["inputs".into()].into()),
+    )?;
+    let mut follows = Vec::new();
+    for kv in all_toplevel_inputs.iter() {
+        collect_follows_of_input(kv, Vec::new(), input_name, &mut follows)?;
+    }
+
+    let mut ranges = Vec::new();
+    for kv in input_attrs.iter() {
+        ranges.push(binding_range(flake_contents, kv)?);
+    }
+    for (path, kv) in follows.iter() {
+        tracing::debug!("Removing `{path}`, which followed the removed input");
+        ranges.push(binding_range(flake_contents, kv)?);
+    }
+
+    // Follows that live inside of the removed input (e.g. `inputs.nixpkgs.inputs.foo.follows`)
+    // are removed along with it, so drop any ranges that are wholly contained in another one.
+    ranges
+        .sort_by(|(start_a, end_a), (start_b, end_b)| start_a.cmp(start_b).then(end_b.cmp(end_a)));
+    let mut deduped_ranges: Vec<(usize, usize)> = Vec::new();
+    for (start, end) in ranges {
+        match deduped_ranges.last() {
+            Some((_, last_end)) if start < *last_end => continue,
+            _ => deduped_ranges.push((start, end)),
+        }
+    }
+
+    // Remove back-to-front so that earlier offsets stay valid.
+    let mut new_flake_contents = flake_contents.to_string();
+    for (start, end) in deduped_ranges.into_iter().rev() {
+        new_flake_contents.replace_range(start..end, "");
+    }
+
+    // Anything still following the removed input would fail to lock, so let the user know.
+    let reparsed = nixel::parse(new_flake_contents.clone());
+    let remaining_toplevel_inputs = crate::cli::cmd::add::flake::find_all_attrsets_by_path(
+        &reparsed.expression,
+        Some(["inputs".into()].into()),
+    )?;
+    let mut dangling_follows = Vec::new();
+    for kv in remaining_toplevel_inputs.iter() {
+        collect_follows_of_input(kv, Vec::new(), input_name, &mut dangling_follows)?;
+    }
+    for (path, _) in dangling_follows {
+        tracing::warn!(
+            "`{path}` still follows the removed input `{input_name}`; you will need to remove it manually"
+        );
+    }
+
+    let outputs_attr = crate::cli::cmd::add::flake::find_first_attrset_by_path(
+        expr,
+        Some(["outputs".into()].into()),
+    )?;
+    if let Some(outputs_attr) = outputs_attr {
+        if let nixel::Expression::Function(f) = &*outputs_attr.to {
+            if let nixel::FunctionHead::Destructured(head) = &f.head {
+                if head
+                    .arguments
+                    .iter()
+                    .any(|arg| &*arg.identifier == input_name)
+                {
+                    tracing::warn!(
+                        "the `outputs` function still takes `{input_name}` as an argument; you will need to remove it manually"
+                    );
+                }
+            }
+        }
+    }
+
+    Ok(new_flake_contents)
+}
+
+/// Recursively collects every `...follows = "<input_name>"` (or `"<input_name>/..."`) binding
+/// under the provided binding, along with its full attribute path.
+fn collect_follows_of_input(
+    kv: &nixel::BindingKeyValue,
+    mut attr_path: Vec<String>,
+    input_name: &str,
+    found: &mut Vec<(String, nixel::BindingKeyValue)>,
+) -> color_eyre::Result<()> {
+    for part in kv.from.iter() {
+        match crate::cli::cmd::add::flake::attr_name(part) {
+            Some(name) => attr_path.push(name.to_string()),
+            None => {
+                tracing::debug!("skipped binding because we didn't get Raw parts");
+                return Ok(());
+            }
+        }
+    }
+
+    match &*kv.to {
+        nixel::Expression::Map(map) => {
+            for binding in map.bindings.iter() {
+                if let nixel::Binding::KeyValue(kv) = binding {
+                    collect_follows_of_input(kv, attr_path.clone(), input_name, found)?;
+                }
+            }
+        }
+        to if attr_path.last().map(String::as_str) == Some("follows") => {
+            let followed = crate::cli::cmd::convert::find_input_value_by_path(to, VecDeque::new())?;
+
+            if let Some(followed) = followed {
+                if followed.split('/').next() == Some(input_name) {
+                    found.push((attr_path.join("."), kv.to_owned()));
+                }
+            }
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+/// Returns the byte offsets of the entire binding (including its trailing `;`). If the binding is
+/// the only thing on its line(s), the offsets encompass the whole line(s), so that removing it
+/// doesn't leave behind an empty line.
+fn binding_range(
+    flake_contents: &str,
+    kv: &nixel::BindingKeyValue,
+) -> color_eyre::Result<(usize, usize)> {
+    let (from_span, to_span) = crate::cli::cmd::add::flake::kv_to_span(kv);
+    let index = crate::cli::cmd::add::flake::LineIndex::new(flake_contents);
+    let start = index.offset(&from_span.start)?;
+    let end = index.offset(&to_span.end)?;
+
+    let rest = &flake_contents[end..];
+    let semicolon = rest.trim_start();
+    if !semicolon.starts_with(';') {
+        return Err(color_eyre::eyre::eyre!(
+            "could not find the `;` terminating the binding at {}:{}",
+            from_span.start.line,
+            from_span.start.column
+        ));
+    }
+    let end = end + (rest.len() - semicolon.len()) + 1;
+
+    let line_start = flake_contents[..start]
+        .rfind('\n')
+        .map(|idx| idx + 1)
+        .unwrap_or(0);
+    let line_end = flake_contents[end..]
+        .find('\n')
+        .map(|idx| end + idx + 1)
+        .unwrap_or(flake_contents.len());
+
+    if flake_contents[line_start..start].trim().is_empty()
+        && flake_contents[end..line_end].trim().is_empty()
+    {
+        Ok((line_start, line_end))
+    } else {
+        // Swallow the spaces after the binding so that e.g. `{ a = 1; b = 2; }` stays tidy.
+        let trailing = &flake_contents[end..];
+        let trailing_spaces = trailing.len() - trailing.trim_start_matches(' ').len();
+
+        Ok((start, end + trailing_spaces))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #[test]
+    fn test_remove_flattened_input() {
+        let flake_contents = r#"{
+  inputs.nixpkgs.url = "github:NixOS/nixpkgs/nixos-23.05";
+  inputs.fh.url = "https://flakehub.com/f/DeterminateSystems/fh/*.tar.gz";
+  inputs.fh.inputs.nixpkgs.follows = "nixpkgs";
+
+  outputs = { self, ... } @ inputs: { };
+}
+"#;
+        let parsed = nixel::parse(flake_contents.to_string());
+
+        let res = super::remove_flake_input(&parsed.expression, "nixpkgs", flake_contents).unwrap();
+
+        assert_eq!(
+            res,
+            r#"{
+  inputs.fh.url = "https://flakehub.com/f/DeterminateSystems/fh/*.tar.gz";
+
+  outputs = { self, ... } @ inputs: { };
+}
+"#
+        );
+    }
+
+    #[test]
+    fn test_remove_nested_input() {
+        let flake_contents = include_str!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/samples/flake1.test.nix"
+        ));
+        let parsed = nixel::parse(flake_contents.to_string());
+
+        let res = super::remove_flake_input(&parsed.expression, "darwin", flake_contents).unwrap();
+
+        assert!(!res.contains("LnL7/nix-darwin"));
+        assert!(!res.contains(r#"follows = "darwin""#));
+        assert!(!res.contains(r#"inputs.darwin.inputs.nixpkgs.follows = "testing2";"#));
+        assert!(res.contains(r#"inputs.home-manager.follows = "home";"#));
+
+        let reparsed = nixel::parse(res.clone());
+        let remaining = crate::cli::cmd::add::flake::find_all_attrsets_by_path(
+            &reparsed.expression,
+            Some(["inputs".into(), "darwin".into()].into()),
+        )
+        .unwrap();
+        assert!(remaining.is_empty());
+    }
+
+    #[test]
+    fn test_remove_missing_input() {
+        let flake_contents = include_str!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/samples/flake3.test.nix"
+        ));
+        let parsed = nixel::parse(flake_contents.to_string());
+
+        let res = super::remove_flake_input(&parsed.expression, "nope", flake_contents);
+        assert!(res.is_err());
+    }
+}
@@ -1,11 +1,15 @@
 use clap::Parser;
 use indicatif::{ProgressBar, ProgressStyle};
+use owo_colors::OwoColorize;
 use serde::{Deserialize, Serialize};
-use std::{io::IsTerminal, process::ExitCode};
+use std::{io::IsTerminal, num::NonZeroUsize, process::ExitCode};
+use tabled::grid::records::{ExactRecords, PeekableRecords};
+use tabled::settings::{object::Columns, Modify, Width};
 use tabled::{Table, Tabled};
 use url::Url;
 
-use super::{list::FLAKEHUB_WEB_ROOT, print_json, CommandExecute, FlakeHubClient};
+use super::list::{display_option, FLAKEHUB_WEB_ROOT};
+use super::{print_json, CommandExecute, FlakeHubClient};
 
 /// Searches FlakeHub for flakes that match your query.
 #[derive(Debug, Parser)]
@@ -14,29 +18,89 @@ pub(crate) struct SearchSubcommand {
     query: String,
 
     /// The maximum number of search results to return.
-    #[clap(short, long, default_value = "10")]
+    #[clap(short, long, visible_alias = "limit", default_value = "10")]
     max_results: usize,
 
-    /// Output results as JSON.
-    #[clap(long)]
+    /// Which page of search results to return, where each page holds `--max-results` results.
+    #[clap(long, default_value = "1")]
+    page: NonZeroUsize,
+
+    /// How to output the results: a `table` (or CSV, when not writing to a terminal), `csv`, `tsv`,
+    /// or `json`.
+    #[clap(long, value_enum, default_value_t = SearchFormat::Table)]
+    format: SearchFormat,
+
+    /// Output results as JSON. Shorthand for `--format json`.
+    #[clap(long, conflicts_with = "format")]
     json: bool,
 
+    /// Show descriptions in full instead of truncating them to fit the terminal.
+    #[clap(long)]
+    wide: bool,
+
+    /// Only show the flake whose name is exactly the query (e.g. `NixOS/nixpkgs`, ignoring case),
+    /// and fail if there's no such flake.
+    #[clap(long)]
+    exact: bool,
+
     #[clap(from_global)]
     api_addr: url::Url,
+
+    #[clap(from_global)]
+    token: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+enum SearchFormat {
+    Table,
+    Csv,
+    Tsv,
+    Json,
 }
 
 #[derive(Deserialize, Serialize)]
 pub struct SearchResult {
     org: String,
     project: String,
+    #[serde(default)]
+    description: Option<String>,
+    #[serde(default)]
+    tags: Vec<String>,
+}
+
+/// A [`SearchResult`] along with its computed name and FlakeHub URL, for `--json` output.
+#[derive(Serialize)]
+struct SearchResultJson<'a> {
+    #[serde(flatten)]
+    result: &'a SearchResult,
+    name: String,
+    url: Url,
+}
+
+impl<'a> From<&'a SearchResult> for SearchResultJson<'a> {
+    fn from(result: &'a SearchResult) -> Self {
+        Self {
+            name: result.name(),
+            url: result.url(),
+            result,
+        }
+    }
+}
+
+/// The results whose name is `query`, ignoring case.
+fn exact_matches(results: Vec<SearchResult>, query: &str) -> Vec<SearchResult> {
+    results
+        .into_iter()
+        .filter(|result| result.name().eq_ignore_ascii_case(query.trim()))
+        .collect()
 }
 
 impl SearchResult {
-    fn name(&self) -> String {
+    pub(crate) fn name(&self) -> String {
         format!("{}/{}", self.org, self.project)
     }
 
-    fn url(&self) -> Url {
+    pub(crate) fn url(&self) -> Url {
         let mut url = Url::parse(FLAKEHUB_WEB_ROOT)
             .expect("failed to parse flakehub web root url (this should never happen)");
         {
@@ -54,6 +118,8 @@ impl SearchResult {
 pub struct SearchResultRow {
     name: String,
     url: Url,
+    #[tabled(display_with = "display_option")]
+    description: Option<String>,
 }
 
 impl From<SearchResult> for SearchResultRow {
@@ -61,44 +127,387 @@ impl From<SearchResult> for SearchResultRow {
         Self {
             name: value.name(),
             url: value.url(),
+            description: value.description,
         }
     }
 }
 
+impl SearchResultRow {
+    /// Like the [`From`] impl, but with the parts of the name that match `query` highlighted
+    /// (unless `color` is off).
+    fn highlighted(value: SearchResult, query: &str, color: bool) -> Self {
+        let name = value.name();
+
+        Self {
+            name: if color {
+                highlight_matches(&name, query)
+            } else {
+                name
+            },
+            url: value.url(),
+            // Descriptions may span several lines, which would make for very tall rows
+            description: value
+                .description
+                .map(|description| description.split_whitespace().collect::<Vec<_>>().join(" ")),
+        }
+    }
+}
+
+/// A [`SearchResult`] flattened into a single CSV/TSV record, with its tags separated by spaces.
+#[derive(Serialize)]
+struct SearchResultRecord {
+    name: String,
+    url: Url,
+    description: Option<String>,
+    tags: String,
+}
+
+impl From<SearchResult> for SearchResultRecord {
+    fn from(value: SearchResult) -> Self {
+        Self {
+            name: value.name(),
+            url: value.url(),
+            tags: value.tags.join(" "),
+            description: value.description,
+        }
+    }
+}
+
+/// Writes the results as delimiter-separated records, with a header row.
+fn write_records(
+    writer: impl std::io::Write,
+    results: Vec<SearchResult>,
+    delimiter: u8,
+) -> color_eyre::Result<()> {
+    let mut writer = csv::WriterBuilder::new()
+        .delimiter(delimiter)
+        .from_writer(writer);
+    for result in results {
+        writer.serialize(SearchResultRecord::from(result))?;
+    }
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// The indices of the URL and description columns in a table of [`SearchResultRow`]s.
+const URL_COLUMN: usize = 1;
+const DESCRIPTION_COLUMN: usize = 2;
+
+/// Whether to style the output, which the user can turn off by setting `NO_COLOR` (see
+/// <https://no-color.org>).
+fn use_color() -> bool {
+    std::env::var_os("NO_COLOR").is_none_or(|no_color| no_color.is_empty())
+}
+
+/// The width of the widest cell in `column`.
+fn column_width(table: &Table, column: usize) -> usize {
+    let records = table.get_records();
+    (0..records.count_rows())
+        .map(|row| records.get_width((row, column)))
+        .max()
+        .unwrap_or_default()
+}
+
+/// Makes the table fit within `terminal_width` columns: first by truncating the descriptions (with
+/// an ellipsis), as long as that leaves them some room, and then by wrapping the URLs, which would
+/// be useless if they were truncated.
+fn fit_to_width(table: &mut Table, terminal_width: usize) {
+    const MIN_DESCRIPTION_WIDTH: usize = 16;
+    const MIN_URL_WIDTH: usize = 24;
+
+    let overflow = table.total_width().saturating_sub(terminal_width);
+    if overflow == 0 {
+        return;
+    }
+
+    let max_width = column_width(table, DESCRIPTION_COLUMN)
+        .saturating_sub(overflow)
+        .max(MIN_DESCRIPTION_WIDTH);
+    table.with(
+        Modify::new(Columns::single(DESCRIPTION_COLUMN))
+            .with(Width::truncate(max_width).suffix("…")),
+    );
+
+    let overflow = table.total_width().saturating_sub(terminal_width);
+    if overflow == 0 {
+        return;
+    }
+
+    let max_width = column_width(table, URL_COLUMN)
+        .saturating_sub(overflow)
+        .max(MIN_URL_WIDTH);
+    table.with(Modify::new(Columns::single(URL_COLUMN)).with(Width::wrap(max_width)));
+}
+
+/// Underlines and bolds every case-insensitive occurrence of each of the query's terms in `text`.
+fn highlight_matches(text: &str, query: &str) -> String {
+    let haystack = text.to_ascii_lowercase();
+    let mut ranges: Vec<(usize, usize)> = Vec::new();
+
+    for term in query.split_whitespace() {
+        let term = term.to_ascii_lowercase();
+        ranges.extend(
+            haystack
+                .match_indices(&term)
+                .map(|(start, found)| (start, start + found.len())),
+        );
+    }
+
+    ranges.sort();
+    let mut merged: Vec<(usize, usize)> = Vec::new();
+    for (start, end) in ranges {
+        match merged.last_mut() {
+            Some((_, last_end)) if start <= *last_end => *last_end = (*last_end).max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+
+    let mut highlighted = String::new();
+    let mut last = 0;
+    for (start, end) in merged {
+        highlighted.push_str(&text[last..start]);
+        let matched = &text[start..end];
+        highlighted.push_str(&matched.bold().underline().to_string());
+        last = end;
+    }
+    highlighted.push_str(&text[last..]);
+
+    highlighted
+}
+
 #[async_trait::async_trait]
 impl CommandExecute for SearchSubcommand {
     async fn execute(self) -> color_eyre::Result<ExitCode> {
-        let pb = ProgressBar::new_spinner();
-        pb.set_style(ProgressStyle::default_spinner());
+        let format = if self.json {
+            SearchFormat::Json
+        } else {
+            self.format
+        };
+
+        // Keep machine-readable output clean for piping
+        let pb = (format == SearchFormat::Table).then(|| {
+            let pb = ProgressBar::new_spinner();
+            pb.set_style(ProgressStyle::default_spinner());
+            pb
+        });
+
+        let client =
+            FlakeHubClient::with_saved_token(&self.api_addr, self.token.as_deref()).await?;
 
-        let client = FlakeHubClient::new(&self.api_addr)?;
+        match client.search(self.query.clone()).await {
+            Ok(mut results) => {
+                if self.exact {
+                    results = exact_matches(results, &self.query);
+
+                    if results.is_empty() {
+                        if let Some(pb) = pb {
+                            pb.finish_and_clear();
+                        }
+                        eprintln!("No flake is named `{}`", self.query);
+
+                        return Ok(ExitCode::FAILURE);
+                    }
+                }
+
+                // The search endpoint doesn't paginate (yet), so we page through its results here.
+                let total = results.len();
+                let offset = (self.page.get() - 1).saturating_mul(self.max_results);
+                let results: Vec<SearchResult> = results
+                    .into_iter()
+                    .skip(offset)
+                    .take(self.max_results)
+                    .collect();
 
-        match client.search(self.query).await {
-            Ok(results) => {
                 if results.is_empty() {
                     eprintln!("No results");
-                } else if self.json {
+                } else if format == SearchFormat::Json {
+                    let results: Vec<SearchResultJson> = results.iter().map(Into::into).collect();
                     print_json(&results)?;
+                } else if format == SearchFormat::Csv {
+                    write_records(std::io::stdout(), results, b',')?;
+                } else if format == SearchFormat::Tsv {
+                    write_records(std::io::stdout(), results, b'\t')?;
                 } else {
-                    let rows: Vec<SearchResultRow> = results
-                        .into_iter()
-                        .take(self.max_results)
-                        .map(Into::into)
-                        .collect();
+                    let shown = results.len();
 
                     if std::io::stdout().is_terminal() {
-                        let table = Table::new(rows);
+                        let color = use_color();
+                        let rows: Vec<SearchResultRow> = results
+                            .into_iter()
+                            .map(|result| SearchResultRow::highlighted(result, &self.query, color))
+                            .collect();
+                        let mut table = Table::new(rows);
+                        if !self.wide {
+                            if let Some((terminal_size::Width(width), _)) =
+                                terminal_size::terminal_size()
+                            {
+                                fit_to_width(&mut table, width.into());
+                            }
+                        }
                         println!("{table}");
                     } else {
-                        csv::Writer::from_writer(std::io::stdout()).serialize(rows)?;
+                        write_records(std::io::stdout(), results, b',')?;
+                    }
+
+                    if let Some(pb) = pb {
+                        let mut footer = format!(
+                            "showing {}-{} of {total} results",
+                            offset + 1,
+                            offset + shown
+                        );
+                        if offset + shown < total {
+                            footer.push_str(&format!(
+                                " (use `--page {}` to see more)",
+                                self.page.get() + 1
+                            ));
+                        }
+                        pb.finish_with_message(footer);
                     }
                 }
             }
             Err(e) => {
+                if let Some(pb) = pb {
+                    pb.finish_and_clear();
+                }
                 eprintln!("Error: {e}");
+
+                return Ok(ExitCode::FAILURE);
             }
         }
 
         Ok(ExitCode::SUCCESS)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use owo_colors::OwoColorize;
+
+    #[tokio::test]
+    async fn test_search_error_exits_with_failure() {
+        use super::super::CommandExecute;
+
+        let router = axum::Router::new().route(
+            "/search",
+            axum::routing::get(|| async { axum::http::StatusCode::BAD_REQUEST }),
+        );
+        let test_server = axum_test::TestServer::new(router.into_make_service()).unwrap();
+
+        let search = super::SearchSubcommand {
+            query: "rust".to_string(),
+            max_results: 10,
+            page: std::num::NonZeroUsize::MIN,
+            format: super::SearchFormat::Json,
+            json: false,
+            wide: false,
+            exact: false,
+            api_addr: test_server.server_address().parse().unwrap(),
+            token: None,
+        };
+        assert_eq!(
+            search.execute().await.unwrap(),
+            std::process::ExitCode::FAILURE
+        );
+    }
+
+    #[test]
+    fn test_exact_matches() {
+        let results = ["NixOS/nixpkgs", "NixOS/nixpkgs-unstable", "someorg/nixpkgs"]
+            .into_iter()
+            .map(|name| {
+                let (org, project) = name.split_once('/').unwrap();
+                super::SearchResult {
+                    org: org.to_string(),
+                    project: project.to_string(),
+                    description: None,
+                    tags: vec![],
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let matches = super::exact_matches(results, "nixos/NIXPKGS");
+        assert_eq!(
+            matches
+                .iter()
+                .map(super::SearchResult::name)
+                .collect::<Vec<_>>(),
+            ["NixOS/nixpkgs"]
+        );
+        assert!(super::exact_matches(matches, "nixpkgs").is_empty());
+    }
+
+    #[test]
+    fn test_fit_to_width() {
+        let rows = vec![super::SearchResultRow {
+            name: "someorg/someflake".to_string(),
+            url: "https://flakehub.com/flake/someorg/someflake"
+                .parse()
+                .unwrap(),
+            description: Some("A flake with a rather long description. ".repeat(4)),
+        }];
+
+        let mut table = tabled::Table::new(&rows);
+        let untruncated = table.to_string();
+        super::fit_to_width(&mut table, 1000);
+        assert_eq!(table.to_string(), untruncated);
+
+        super::fit_to_width(&mut table, 120);
+        assert!(table.total_width() <= 120);
+        assert!(table.to_string().contains("…"));
+
+        // Once the descriptions can't get any narrower, the URLs are wrapped rather than cut off
+        let mut table = tabled::Table::new(&rows);
+        super::fit_to_width(&mut table, 72);
+        assert!(table.total_width() <= 72, "{table}");
+        let rendered = table.to_string();
+        assert!(!rendered.contains("https://flakehub.com/flake/someorg/someflake"));
+        assert!(rendered.contains("someflake"), "{rendered}");
+    }
+
+    #[test]
+    fn test_write_records() {
+        let results = || {
+            vec![super::SearchResult {
+                org: "someorg".to_string(),
+                project: "someflake".to_string(),
+                description: Some("Flakes, \"quoted\"".to_string()),
+                tags: vec!["rust".to_string(), "nix".to_string()],
+            }]
+        };
+
+        let mut csv = Vec::new();
+        super::write_records(&mut csv, results(), b',').unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "name,url,description,tags\n\
+            someorg/someflake,https://flakehub.com/flake/someorg/someflake,\"Flakes, \"\"quoted\"\"\",rust nix\n"
+        );
+
+        let mut tsv = Vec::new();
+        super::write_records(&mut tsv, results(), b'\t').unwrap();
+        assert_eq!(
+            String::from_utf8(tsv).unwrap(),
+            "name\turl\tdescription\ttags\n\
+            someorg/someflake\thttps://flakehub.com/flake/someorg/someflake\t\"Flakes, \"\"quoted\"\"\"\trust nix\n"
+        );
+    }
+
+    #[test]
+    fn test_highlight_matches() {
+        let hl = |s: &str| s.bold().underline().to_string();
+
+        assert_eq!(
+            super::highlight_matches("NixOS/nixpkgs", "nix"),
+            format!("{}OS/{}pkgs", hl("Nix"), hl("nix"))
+        );
+        assert_eq!(
+            super::highlight_matches("nix-community/fenix", "rust fenix"),
+            format!("nix-community/{}", hl("fenix"))
+        );
+        assert_eq!(
+            super::highlight_matches("astro/deadnix", "rust"),
+            "astro/deadnix"
+        );
+    }
+}
@@ -89,12 +89,12 @@ pub(crate) async fn get_status_from_auth_token(
     let mut cli_status = api_addr;
     cli_status.set_path("/cli/status");
 
-    let res = reqwest::Client::builder()
+    let request = reqwest::Client::builder()
         .user_agent(crate::APP_USER_AGENT)
         .build()?
         .get(cli_status)
-        .header(AUTHORIZATION, &format!("Bearer {token}"))
-        .send()
+        .header(AUTHORIZATION, &format!("Bearer {token}"));
+    let res = crate::cli::cmd::send_with_retries(request)
         .await
         .wrap_err("Failed to send request")?;
 
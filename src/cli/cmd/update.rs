@@ -0,0 +1,332 @@
+use std::collections::VecDeque;
+use std::process::ExitCode;
+
+use clap::Parser;
+
+use super::add::ArchiveFormat;
+use super::CommandExecute;
+
+/// Updates a FlakeHub input in your flake.nix to its latest (or a specific) version.
+#[derive(Debug, Parser)]
+#[command(mut_arg("flake_path", |arg| arg.help("The flake.nix to modify")))]
+pub(crate) struct UpdateSubcommand {
+    #[clap(flatten)]
+    pub(crate) flake: super::FlakePathArgs,
+
+    /// The name of the flake input to update.
+    pub(crate) input_name: String,
+
+    /// The version (or version requirement) to pin the input to, e.g. `0.2305.*`.
+    ///
+    /// If not provided, the input is updated to the latest version.
+    #[clap(long)]
+    pub(crate) version: Option<String>,
+
+    /// Print to stdout the new flake.nix contents instead of writing it to disk.
+    #[clap(long)]
+    pub(crate) dry_run: bool,
+
+    /// Don't run `nix flake lock` after writing the new flake.nix contents.
+    #[clap(long)]
+    pub(crate) no_lock: bool,
+
+    #[clap(from_global)]
+    api_addr: url::Url,
+
+    #[clap(from_global)]
+    token: Option<String>,
+}
+
+#[async_trait::async_trait]
+impl CommandExecute for UpdateSubcommand {
+    #[tracing::instrument(skip_all)]
+    async fn execute(self) -> color_eyre::Result<ExitCode> {
+        if !self.flake.path.exists() {
+            return Err(color_eyre::eyre::eyre!(
+                "the flake at {} did not exist",
+                self.flake.path.display()
+            ));
+        }
+
+        let (flake_contents, parsed) = crate::cli::cmd::add::load_flake(&self.flake.path).await?;
+        let original_flake_contents = flake_contents.clone();
+        let strip_tar_gz = crate::cli::cmd::add::nix_supports_bare_flakehub_urls().await;
+        let Some(new_flake_contents) = update_flakehub_input(
+            &self.api_addr,
+            &parsed.expression,
+            &self.input_name,
+            self.version.as_deref(),
+            flake_contents,
+            strip_tar_gz,
+            self.token.as_deref(),
+        )
+        .await
+        .map_err(|err| {
+            crate::cli::cmd::add::flake::with_source_context(err, &original_flake_contents)
+        })?
+        else {
+            return Ok(ExitCode::SUCCESS);
+        };
+
+        let new_flake_contents = crate::cli::cmd::add::match_trailing_newline(
+            &original_flake_contents,
+            new_flake_contents,
+        );
+
+        if self.dry_run {
+            println!("{new_flake_contents}");
+        } else {
+            tokio::fs::write(&self.flake.path, new_flake_contents).await?;
+
+            if !self.no_lock {
+                crate::cli::cmd::add::lock_flake(&self.flake.path).await?;
+            }
+        }
+
+        Ok(ExitCode::SUCCESS)
+    }
+}
+
+/// Rewrites the `inputs.<input_name>.url` of a FlakeHub input to point at the requested version
+/// (or the latest one). Returns `None` if the input isn't a FlakeHub input.
+#[tracing::instrument(skip_all, fields(input_name))]
+pub(crate) async fn update_flakehub_input(
+    api_addr: &url::Url,
+    expr: &nixel::Expression,
+    input_name: &str,
+    version: Option<&str>,
+    flake_contents: String,
+    strip_tar_gz: bool,
+    token: Option<&str>,
+) -> color_eyre::Result<Option<String>> {
+    let input_url_attr_path: VecDeque<String> =
+        ["inputs".into(), input_name.into(), "url".into()].into();
+    let Some(attr) =
+        crate::cli::cmd::add::flake::find_first_attrset_by_path(expr, Some(input_url_attr_path))?
+    else {
+        return Err(color_eyre::eyre::eyre!(
+            "there was no `inputs.{input_name}.url` attribute to update"
+        ));
+    };
+
+    let current_url =
+        crate::cli::cmd::convert::find_input_value_by_path(&attr.to, VecDeque::new())?
+            .and_then(|url| url.parse::<url::Url>().ok());
+    let Some((org, project, _, archive_format)) = current_url.as_ref().and_then(parse_flakehub_url)
+    else {
+        tracing::warn!("`inputs.{input_name}.url` is not a FlakeHub URL; not updating it");
+        return Ok(None);
+    };
+    // Keep the input's archive format; only tarball URLs can go without a suffix
+    let strip_tar_gz = strip_tar_gz && archive_format == crate::cli::cmd::add::ArchiveFormat::TarGz;
+
+    let (_, mut new_url) = crate::cli::cmd::add::get_flakehub_project_and_url(
+        api_addr,
+        &org,
+        &project,
+        version,
+        strip_tar_gz,
+        archive_format,
+        token,
+    )
+    .await?;
+    crate::cli::cmd::add::keep_version_requirement(
+        &mut new_url,
+        version,
+        strip_tar_gz,
+        archive_format,
+    );
+
+    let new_flake_contents = crate::cli::cmd::add::flake::update_flake_input(
+        attr,
+        input_name.to_string(),
+        new_url,
+        flake_contents,
+    )?;
+
+    Ok(Some(new_flake_contents))
+}
+
+/// Extracts the org, project, version (if any), and archive format from a FlakeHub URL like
+/// `https://flakehub.com/f/NixOS/nixpkgs/0.2305.*.tar.gz`.
+pub(crate) fn parse_flakehub_url(
+    url: &url::Url,
+) -> Option<(String, String, Option<String>, ArchiveFormat)> {
+    match url.host() {
+        Some(url::Host::Domain("flakehub.com" | "api.flakehub.com")) => {}
+        _ => return None,
+    }
+
+    match url.path_segments()?.collect::<Vec<_>>()[..] {
+        ["f", org, project, ref rest @ ..] if !org.is_empty() && !project.is_empty() => {
+            let archive_format = rest
+                .first()
+                .copied()
+                .map(ArchiveFormat::of)
+                .unwrap_or_default();
+            let version = rest
+                .first()
+                .map(|version| ArchiveFormat::strip_suffix(version))
+                .filter(|version| !version.is_empty())
+                .map(ToString::to_string);
+
+            Some((
+                org.to_string(),
+                project.to_string(),
+                version,
+                archive_format,
+            ))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::cli::cmd::mock_flakehub::MockFlakeHub;
+
+    const FLAKE_CONTENTS: &str = r#"{
+  inputs.nixpkgs.url = "https://flakehub.com/f/NixOS/nixpkgs/0.2305.1.tar.gz";
+  inputs.crane.url = "github:ipetkov/crane";
+
+  outputs = { self, ... } @ inputs: { };
+}
+"#;
+
+    #[tokio::test]
+    async fn test_update_to_latest_and_pinned() {
+        let flakehub = MockFlakeHub::new(&["NixOS/nixpkgs"]);
+        let server_url = flakehub.api_addr().parse().unwrap();
+        let parsed = nixel::parse(FLAKE_CONTENTS.to_string());
+
+        let res = super::update_flakehub_input(
+            &server_url,
+            &parsed.expression,
+            "nixpkgs",
+            None,
+            FLAKE_CONTENTS.to_string(),
+            false,
+            None,
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert!(res.contains(
+            r#"inputs.nixpkgs.url = "https://flakehub.com/f/NixOS/nixpkgs/0.1.0.tar.gz";"#
+        ));
+        assert!(res.contains(r#"inputs.crane.url = "github:ipetkov/crane";"#));
+
+        // FlakeHub resolves `0.2305.*` to `0.2305.7`, but the input keeps following the requirement
+        let res = super::update_flakehub_input(
+            &server_url,
+            &parsed.expression,
+            "nixpkgs",
+            Some("0.2305.*"),
+            FLAKE_CONTENTS.to_string(),
+            false,
+            None,
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert!(res.contains(
+            r#"inputs.nixpkgs.url = "https://flakehub.com/f/NixOS/nixpkgs/0.2305.*.tar.gz";"#
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_update_keeps_archive_format() {
+        const FLAKE_CONTENTS: &str = r#"{
+  inputs.nixpkgs.url = "https://flakehub.com/f/NixOS/nixpkgs/0.2305.1.zip";
+
+  outputs = { self, ... } @ inputs: { };
+}
+"#;
+        let flakehub = MockFlakeHub::new(&["NixOS/nixpkgs"]);
+        let server_url = flakehub.api_addr().parse().unwrap();
+        let parsed = nixel::parse(FLAKE_CONTENTS.to_string());
+
+        for (version, expected) in [(None, "0.1.0.zip"), (Some("0.2305.*"), "0.2305.*.zip")] {
+            // Even when Nix supports bare tarball URLs
+            let res = super::update_flakehub_input(
+                &server_url,
+                &parsed.expression,
+                "nixpkgs",
+                version,
+                FLAKE_CONTENTS.to_string(),
+                true,
+                None,
+            )
+            .await
+            .unwrap()
+            .unwrap();
+            let expected = format!(
+                r#"inputs.nixpkgs.url = "https://flakehub.com/f/NixOS/nixpkgs/{expected}";"#
+            );
+            assert!(res.contains(&expected), "{res}");
+        }
+    }
+
+    #[test]
+    fn test_parse_flakehub_url() {
+        use super::ArchiveFormat;
+
+        for (url, expected) in [
+            (
+                "https://flakehub.com/f/NixOS/nixpkgs/0.2305.*.tar.gz",
+                Some(("0.2305.*", ArchiveFormat::TarGz)),
+            ),
+            (
+                "https://flakehub.com/f/NixOS/nixpkgs/0.2305.1.zip",
+                Some(("0.2305.1", ArchiveFormat::Zip)),
+            ),
+            (
+                "https://flakehub.com/f/NixOS/nixpkgs/0.2305.1",
+                Some(("0.2305.1", ArchiveFormat::TarGz)),
+            ),
+            ("https://flakehub.com/f/NixOS/nixpkgs", None),
+        ] {
+            let (org, project, version, archive_format) =
+                super::parse_flakehub_url(&url.parse().unwrap()).unwrap();
+            assert_eq!((org.as_str(), project.as_str()), ("NixOS", "nixpkgs"));
+            assert_eq!(
+                version.as_deref().map(|version| (version, archive_format)),
+                expected,
+                "{url}"
+            );
+        }
+        assert!(super::parse_flakehub_url(&"github:NixOS/nixpkgs".parse().unwrap()).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_update_skips_non_flakehub_input() {
+        let flakehub = MockFlakeHub::new(&["NixOS/nixpkgs"]);
+        let server_url = flakehub.api_addr().parse().unwrap();
+        let parsed = nixel::parse(FLAKE_CONTENTS.to_string());
+
+        let res = super::update_flakehub_input(
+            &server_url,
+            &parsed.expression,
+            "crane",
+            None,
+            FLAKE_CONTENTS.to_string(),
+            false,
+            None,
+        )
+        .await
+        .unwrap();
+        assert!(res.is_none());
+
+        let res = super::update_flakehub_input(
+            &server_url,
+            &parsed.expression,
+            "nope",
+            None,
+            FLAKE_CONTENTS.to_string(),
+            false,
+            None,
+        )
+        .await;
+        assert!(res.is_err());
+    }
+}
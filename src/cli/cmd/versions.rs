@@ -0,0 +1,148 @@
+use std::io::IsTerminal;
+use std::process::ExitCode;
+
+use clap::Parser;
+use indicatif::{ProgressBar, ProgressStyle};
+use tabled::{Table, Tabled};
+
+use super::list::{bold, dimmed, display_option, Flake, Release};
+use super::{print_json, CommandExecute, FlakeHubClient, DEFAULT_STYLE};
+
+/// Lists the published versions of a flake on FlakeHub.
+#[derive(Debug, Parser)]
+pub(crate) struct VersionsSubcommand {
+    /// The flake for which you want to list versions, e.g. `NixOS/nixpkgs`.
+    flake: String,
+
+    /// Include pre-release versions (e.g. `1.0.0-rc.1`).
+    #[clap(long)]
+    pre: bool,
+
+    /// Output results as JSON.
+    #[clap(long)]
+    json: bool,
+
+    #[clap(from_global)]
+    api_addr: url::Url,
+
+    #[clap(from_global)]
+    token: Option<String>,
+}
+
+#[async_trait::async_trait]
+impl CommandExecute for VersionsSubcommand {
+    async fn execute(self) -> color_eyre::Result<ExitCode> {
+        let pb = ProgressBar::new_spinner();
+        pb.set_style(ProgressStyle::default_spinner());
+
+        let client =
+            FlakeHubClient::with_saved_token(&self.api_addr, self.token.as_deref()).await?;
+        let flake = Flake::try_from(self.flake)?;
+
+        let rows = client
+            .versions(&flake.org, &flake.project, self.pre)
+            .await?
+            .into_iter()
+            .map(Into::into)
+            .collect::<Vec<VersionRow>>();
+
+        if rows.is_empty() {
+            eprintln!("No results");
+        } else if self.json {
+            print_json(&rows)?;
+        } else if std::io::stdout().is_terminal() {
+            let mut table = Table::new(rows);
+            table.with(DEFAULT_STYLE.clone());
+            println!("{table}");
+        } else {
+            let mut writer = csv::Writer::from_writer(std::io::stdout());
+            for row in rows {
+                writer.serialize(row)?;
+            }
+        }
+
+        Ok(ExitCode::SUCCESS)
+    }
+}
+
+#[derive(Tabled, serde::Serialize)]
+struct VersionRow {
+    #[tabled(rename = "Version", display_with = "bold")]
+    #[serde(rename = "Version")]
+    version: String,
+    #[tabled(rename = "Released", display_with = "released")]
+    #[serde(rename = "Released")]
+    published_at: Option<String>,
+}
+
+impl From<Release> for VersionRow {
+    fn from(value: Release) -> Self {
+        Self {
+            version: value.version,
+            published_at: value.published_at,
+        }
+    }
+}
+
+fn released(v: &Option<String>) -> String {
+    dimmed(display_option(v))
+}
+
+#[cfg(test)]
+mod test {
+    use axum::extract::Path;
+
+    async fn releases(Path((org, _project)): Path<(String, String)>) -> axum::response::Response {
+        use axum::response::IntoResponse;
+
+        if org != "someorg" {
+            return axum::http::StatusCode::NOT_FOUND.into_response();
+        }
+
+        axum::Json(serde_json::json!([
+            { "version": "0.1.0", "published_at": "2023-10-01T00:00:00Z" },
+            { "version": "0.10.0", "published_at": "2023-12-01T00:00:00Z" },
+            { "version": "0.2.0-rc.1" },
+            { "version": "nightly" },
+        ]))
+        .into_response()
+    }
+
+    #[tokio::test]
+    async fn test_versions() {
+        let router =
+            axum::Router::new().route("/f/:org/:project/releases", axum::routing::get(releases));
+        let test_server = axum_test::TestServer::new(router.into_make_service()).unwrap();
+        let api_addr = test_server.server_address().parse().unwrap();
+        let client = super::FlakeHubClient::new(&api_addr, None).unwrap();
+
+        for (include_pre, expected) in [
+            (false, &["0.10.0", "0.1.0"][..]),
+            (true, &["0.10.0", "0.2.0-rc.1", "0.1.0"]),
+        ] {
+            let versions = client
+                .versions("someorg", "someflake", include_pre)
+                .await
+                .unwrap();
+            let versions: Vec<&str> = versions
+                .iter()
+                .map(|release| release.version.as_str())
+                .collect();
+            assert_eq!(versions, expected);
+        }
+
+        let versions = client
+            .versions("someorg", "someflake", false)
+            .await
+            .unwrap();
+        assert_eq!(
+            versions[0].published_at.as_deref(),
+            Some("2023-12-01T00:00:00Z")
+        );
+
+        assert!(client
+            .versions("otherorg", "someflake", false)
+            .await
+            .is_err());
+    }
+}
@@ -0,0 +1,132 @@
+use std::process::ExitCode;
+
+use clap::Parser;
+use serde::{Deserialize, Serialize};
+
+use super::{print_json, CommandExecute, FhError, FlakeHubClient};
+
+/// Shows who FlakeHub thinks you are, e.g. to find out why a private flake doesn't resolve.
+///
+/// Exits with a non-zero status if you aren't authenticated.
+#[derive(Debug, Parser)]
+pub(crate) struct WhoamiSubcommand {
+    /// Output results as JSON.
+    #[clap(long)]
+    json: bool,
+
+    #[clap(from_global)]
+    api_addr: url::Url,
+
+    #[clap(from_global)]
+    token: Option<String>,
+}
+
+/// The user a FlakeHub token belongs to.
+#[derive(Debug, Deserialize, Serialize)]
+pub(crate) struct User {
+    pub(crate) username: String,
+    #[serde(default)]
+    pub(crate) organizations: Vec<String>,
+}
+
+#[async_trait::async_trait]
+impl CommandExecute for WhoamiSubcommand {
+    async fn execute(self) -> color_eyre::Result<ExitCode> {
+        let token = match self.token {
+            Some(token) => Some(token),
+            None => crate::cli::cmd::login::saved_token().await?,
+        };
+
+        let user = match &token {
+            Some(token) => authenticated_user(&self.api_addr, token).await?,
+            None => None,
+        };
+
+        if self.json {
+            match &user {
+                Some(user) => print_json(serde_json::json!({
+                    "authenticated": true,
+                    "username": user.username,
+                    "organizations": user.organizations,
+                }))?,
+                None => print_json(serde_json::json!({ "authenticated": false }))?,
+            }
+        } else {
+            match &user {
+                Some(user) => {
+                    println!("Logged in as {}", user.username);
+                    if user.organizations.is_empty() {
+                        println!("Organizations: none");
+                    } else {
+                        println!("Organizations: {}", user.organizations.join(", "));
+                    }
+                }
+                None => {
+                    println!("Not authenticated");
+                    if token.is_some() {
+                        eprintln!("FlakeHub rejected the token; run `fh login` to get a new one");
+                    } else {
+                        eprintln!("Run `fh login`, or pass a token with `--token` or `FH_TOKEN`");
+                    }
+                }
+            }
+        }
+
+        if user.is_some() {
+            Ok(ExitCode::SUCCESS)
+        } else {
+            Ok(ExitCode::FAILURE)
+        }
+    }
+}
+
+/// The user `token` belongs to, or `None` if FlakeHub rejects it.
+async fn authenticated_user(api_addr: &url::Url, token: &str) -> color_eyre::Result<Option<User>> {
+    let client = FlakeHubClient::new(api_addr, Some(token))?;
+
+    match client.me().await {
+        Ok(user) => Ok(Some(user)),
+        Err(FhError::AuthenticationRequired) => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use axum::{http::HeaderMap, response::IntoResponse};
+
+    async fn me(headers: HeaderMap) -> axum::response::Response {
+        if headers
+            .get("Authorization")
+            .and_then(|value| value.to_str().ok())
+            != Some("Bearer good-token")
+        {
+            return axum::http::StatusCode::UNAUTHORIZED.into_response();
+        }
+
+        axum::Json(serde_json::json!({
+            "username": "someuser",
+            "organizations": ["DeterminateSystems", "NixOS"],
+        }))
+        .into_response()
+    }
+
+    #[tokio::test]
+    async fn test_authenticated_user() {
+        let router = axum::Router::new().route("/me", axum::routing::get(me));
+        let test_server = axum_test::TestServer::new(router.into_make_service()).unwrap();
+        let api_addr = test_server.server_address().parse().unwrap();
+
+        let user = super::authenticated_user(&api_addr, "good-token")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(user.username, "someuser");
+        assert_eq!(user.organizations, ["DeterminateSystems", "NixOS"]);
+
+        assert!(super::authenticated_user(&api_addr, "bad-token")
+            .await
+            .unwrap()
+            .is_none());
+    }
+}
@@ -0,0 +1,404 @@
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+use clap::parser::ValueSource;
+use clap::{CommandFactory, FromArgMatches, Parser};
+use color_eyre::eyre::WrapErr;
+use serde::Deserialize;
+
+use super::Cli;
+
+/// The name of the per-project config file, which is looked up from the current directory upward.
+const PROJECT_CONFIG_FILE_NAME: &str = ".fh.toml";
+
+/// The flags that decide where the FlakeHub token is sent (and which token that is), which only the
+/// user's own config may set: otherwise, running `fh` in a cloned repository could hand the token
+/// saved by `fh login` to whatever host the repository's `.fh.toml` chooses.
+const USER_ONLY_FLAGS: [&str; 3] = ["api-addr", "frontend-addr", "token"];
+
+/// Defaults for `fh`'s flags, e.g.:
+///
+/// ```toml
+/// api-addr = "https://api.flakehub.com"
+/// token = "..."
+///
+/// [convert]
+/// no-flake-compat-fixup = true
+/// ```
+///
+/// Flags passed on the command line (or set through environment variables) take precedence.
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct Config {
+    #[serde(default)]
+    api_addr: Option<String>,
+    #[serde(default)]
+    frontend_addr: Option<String>,
+    #[serde(default)]
+    token: Option<String>,
+    #[serde(default)]
+    retries: Option<u32>,
+    /// The flags of each subcommand, e.g. `[convert]`, keyed by their long name.
+    #[serde(flatten)]
+    subcommands: BTreeMap<String, BTreeMap<String, toml::Value>>,
+}
+
+impl Config {
+    /// Reads `$XDG_CONFIG_HOME/fh/config.toml` and the closest `.fh.toml`, the latter taking
+    /// precedence.
+    pub(crate) fn load() -> Self {
+        let mut config = Self::default();
+
+        let user_config = xdg::BaseDirectories::new()
+            .ok()
+            .and_then(|dirs| dirs.find_config_file("fh/config.toml"));
+        if let Some(path) = user_config {
+            config.merge_file(&path, Self::read);
+        }
+
+        let project_config = std::env::current_dir()
+            .ok()
+            .and_then(|dir| find_project_config(&dir));
+        if let Some(path) = project_config {
+            config.merge_file(&path, Self::read_project);
+        }
+
+        config
+    }
+
+    /// Merges in the config at `path`, or reports why it can't and carries on without it, so that
+    /// a broken config doesn't keep every subcommand (even `fh login`) from running.
+    fn merge_file(&mut self, path: &Path, read: fn(&Path) -> color_eyre::Result<Self>) {
+        match read(path) {
+            Ok(config) => self.merge(config),
+            Err(err) => eprintln!("Warning: ignoring {}: {err:#}", path.display()),
+        }
+    }
+
+    fn read(path: &Path) -> color_eyre::Result<Self> {
+        let contents = std::fs::read_to_string(path).wrap_err("Failed to read it")?;
+
+        Ok(toml::from_str(&contents)?)
+    }
+
+    /// Reads a project's `.fh.toml`, which mustn't set any of the [`USER_ONLY_FLAGS`].
+    fn read_project(path: &Path) -> color_eyre::Result<Self> {
+        let config = Self::read(path)?;
+        config.check_project()?;
+
+        Ok(config)
+    }
+
+    fn check_project(&self) -> color_eyre::Result<()> {
+        let global_flags = [
+            ("api-addr", self.api_addr.is_some()),
+            ("frontend-addr", self.frontend_addr.is_some()),
+            ("token", self.token.is_some()),
+        ]
+        .into_iter()
+        .filter_map(|(flag, set)| set.then_some(flag));
+        // Global flags can be set per subcommand too
+        let subcommand_flags = self
+            .subcommands
+            .values()
+            .flat_map(|flags| flags.keys().map(String::as_str))
+            .filter(|flag| USER_ONLY_FLAGS.contains(flag));
+
+        match global_flags.chain(subcommand_flags).next() {
+            Some(flag) => Err(color_eyre::eyre::eyre!(
+                "`{flag}` can only be set in `$XDG_CONFIG_HOME/fh/config.toml`, not in a project's `{PROJECT_CONFIG_FILE_NAME}`"
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// Overrides this config's values with the ones `other` sets.
+    fn merge(&mut self, other: Self) {
+        self.api_addr = other.api_addr.or(self.api_addr.take());
+        self.frontend_addr = other.frontend_addr.or(self.frontend_addr.take());
+        self.token = other.token.or(self.token.take());
+        self.retries = other.retries.or(self.retries);
+
+        for (subcommand, flags) in other.subcommands {
+            self.subcommands
+                .entry(subcommand)
+                .or_default()
+                .extend(flags);
+        }
+    }
+
+    /// Parses the command line, filling in flags that weren't passed with the values from this
+    /// config.
+    pub(crate) fn parse_cli(
+        &self,
+        args: impl IntoIterator<Item = impl Into<OsString> + Clone>,
+    ) -> Result<Cli, clap::Error> {
+        let mut args: Vec<OsString> = args.into_iter().map(Into::into).collect();
+
+        let mut command = Cli::command();
+        // Propagates the global flags (like `--retries`) to the subcommands, so that they can be
+        // set per subcommand too
+        command.build();
+        let matches = command.clone().try_get_matches_from(&args)?;
+
+        let global_flags = [
+            ("api-addr", self.api_addr.clone().map(toml::Value::String)),
+            (
+                "frontend-addr",
+                self.frontend_addr.clone().map(toml::Value::String),
+            ),
+            ("token", self.token.clone().map(toml::Value::String)),
+            (
+                "retries",
+                self.retries
+                    .map(|retries| toml::Value::Integer(retries.into())),
+            ),
+        ]
+        .into_iter()
+        .filter_map(|(flag, value)| Some((flag.to_string(), value?)));
+        let mut extra_args = flags_to_args(&command, &matches, global_flags)?;
+
+        if let Some((name, subcommand_matches)) = matches.subcommand() {
+            if let (Some(flags), Some(subcommand)) =
+                (self.subcommands.get(name), command.find_subcommand(name))
+            {
+                let flags = flags
+                    .iter()
+                    .map(|(flag, value)| (flag.clone(), value.clone()));
+                extra_args.extend(flags_to_args(subcommand, subcommand_matches, flags)?);
+            }
+        }
+
+        if extra_args.is_empty() {
+            return Cli::from_arg_matches(&matches);
+        }
+
+        // Anything after a `--` is positional, so the extra flags have to come before it
+        let insert_at = args
+            .iter()
+            .position(|arg| arg == "--")
+            .unwrap_or(args.len());
+        args.splice(insert_at..insert_at, extra_args);
+
+        Cli::try_parse_from(args)
+    }
+}
+
+/// Turns the flags from the config (keyed by their long name) into command-line arguments for
+/// `command`, leaving out the ones that were already passed on the command line or set through the
+/// environment.
+fn flags_to_args(
+    command: &clap::Command,
+    matches: &clap::ArgMatches,
+    flags: impl IntoIterator<Item = (String, toml::Value)>,
+) -> Result<Vec<OsString>, clap::Error> {
+    let mut args = Vec::new();
+
+    for (flag, value) in flags {
+        let Some(arg) = command
+            .get_arguments()
+            .find(|arg| arg.get_long() == Some(flag.as_str()))
+        else {
+            return Err(config_error(
+                command,
+                format!("`{flag}` is not a flag of `{}`", command.get_name()),
+            ));
+        };
+
+        if matches!(
+            matches.value_source(arg.get_id().as_str()),
+            Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+        ) {
+            continue;
+        }
+
+        let values = match value {
+            toml::Value::Array(values) => values,
+            value => vec![value],
+        };
+        for value in values {
+            match value {
+                toml::Value::Boolean(set) if !arg.get_action().takes_values() => {
+                    if set {
+                        args.push(format!("--{flag}").into());
+                    }
+                }
+                toml::Value::String(value) => args.push(format!("--{flag}={value}").into()),
+                value => args.push(format!("--{flag}={value}").into()),
+            }
+        }
+    }
+
+    Ok(args)
+}
+
+fn config_error(command: &clap::Command, message: String) -> clap::Error {
+    let mut command = command.clone();
+    command.error(
+        clap::error::ErrorKind::UnknownArgument,
+        format!("invalid config: {message}"),
+    )
+}
+
+/// The closest `.fh.toml` in `dir` or any of its parents.
+fn find_project_config(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors()
+        .map(|dir| dir.join(PROJECT_CONFIG_FILE_NAME))
+        .find(|path| path.is_file())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::cli::cmd::FhSubcommands;
+
+    const CONFIG: &str = r#"
+api-addr = "http://localhost:8080"
+retries = 5
+
+[convert]
+no-flake-compat-fixup = true
+only-scheme = ["github", "gitlab"]
+"#;
+
+    #[test]
+    fn test_config_fills_in_unset_flags() {
+        let config: super::Config = toml::from_str(CONFIG).unwrap();
+
+        let cli = config.parse_cli(["fh", "convert"]).unwrap();
+        assert_eq!(cli.api_addr.as_str(), "http://localhost:8080/");
+        assert_eq!(cli.retries, 5);
+        let FhSubcommands::Convert(convert) = cli.subcommand else {
+            panic!("expected `fh convert`");
+        };
+        assert!(convert.no_flake_compat_fixup);
+        assert_eq!(convert.only_schemes, ["github", "gitlab"]);
+
+        // Flags on the command line win
+        let cli = config
+            .parse_cli([
+                "fh",
+                "convert",
+                "--only-scheme",
+                "sourcehut",
+                "--api-addr",
+                "http://localhost:9090",
+            ])
+            .unwrap();
+        assert_eq!(cli.api_addr.as_str(), "http://localhost:9090/");
+        let FhSubcommands::Convert(convert) = cli.subcommand else {
+            panic!("expected `fh convert`");
+        };
+        assert_eq!(convert.only_schemes, ["sourcehut"]);
+
+        // Other subcommands don't pick up `[convert]`
+        let cli = config.parse_cli(["fh", "search", "rust"]).unwrap();
+        assert_eq!(cli.api_addr.as_str(), "http://localhost:8080/");
+    }
+
+    #[test]
+    fn test_config_sets_flake_path_per_subcommand() {
+        let config: super::Config =
+            toml::from_str("[remove]\nflake-path = \"nix/flake.nix\"\n").unwrap();
+
+        let cli = config.parse_cli(["fh", "remove", "nixpkgs"]).unwrap();
+        let FhSubcommands::Remove(remove) = cli.subcommand else {
+            panic!("expected `fh remove`");
+        };
+        assert_eq!(remove.flake.path, std::path::Path::new("nix/flake.nix"));
+
+        // The command line still wins
+        let cli = config
+            .parse_cli(["fh", "remove", "--flake-path", "other.nix", "nixpkgs"])
+            .unwrap();
+        let FhSubcommands::Remove(remove) = cli.subcommand else {
+            panic!("expected `fh remove`");
+        };
+        assert_eq!(remove.flake.path, std::path::Path::new("other.nix"));
+
+        let cli = config.parse_cli(["fh", "update", "nixpkgs"]).unwrap();
+        let FhSubcommands::Update(update) = cli.subcommand else {
+            panic!("expected `fh update`");
+        };
+        assert_eq!(update.flake.path, std::path::Path::new("./flake.nix"));
+
+        // Only the subcommands that read a flake have a `--flake-path`
+        assert!(config
+            .parse_cli(["fh", "search", "--flake-path", "other.nix", "rust"])
+            .is_err());
+        let config: super::Config =
+            toml::from_str("[search]\nflake-path = \"nix/flake.nix\"\n").unwrap();
+        assert!(config.parse_cli(["fh", "search", "rust"]).is_err());
+    }
+
+    #[test]
+    fn test_config_sets_default_org() {
+        let config: super::Config = toml::from_str("[add]\ndefault-org = \"someorg\"\n").unwrap();
+
+        let cli = config.parse_cli(["fh", "add", "repo"]).unwrap();
+        let FhSubcommands::Add(add) = cli.subcommand else {
+            panic!("expected `fh add`");
+        };
+        assert_eq!(add.default_org.as_deref(), Some("someorg"));
+    }
+
+    #[test]
+    fn test_config_rejects_unknown_flags() {
+        let config: super::Config = toml::from_str("[convert]\nno-such-flag = true\n").unwrap();
+        let Err(err) = config.parse_cli(["fh", "convert"]) else {
+            panic!("`no-such-flag` should be rejected");
+        };
+        assert!(err.to_string().contains("no-such-flag"), "{err}");
+
+        assert!(toml::from_str::<super::Config>("api-addr = 1\n").is_err());
+    }
+
+    #[test]
+    fn test_project_config_cant_set_user_only_flags() {
+        for contents in [
+            "api-addr = \"http://localhost:8080\"\n",
+            "frontend-addr = \"http://localhost:8080\"\n",
+            "token = \"stolen\"\n",
+            "[add]\ntoken = \"stolen\"\n",
+            "[convert]\napi-addr = \"http://localhost:8080\"\n",
+        ] {
+            let config: super::Config = toml::from_str(contents).unwrap();
+            let err = config.check_project().unwrap_err().to_string();
+            assert!(err.contains("can only be set in"), "{contents}: {err}");
+        }
+
+        let config: super::Config =
+            toml::from_str(CONFIG.replace("api-addr", "# api-addr").as_str()).unwrap();
+        assert!(config.check_project().is_ok());
+    }
+
+    #[test]
+    fn test_config_skips_broken_files() {
+        let dir = std::env::temp_dir().join(format!("fh-config-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let path = dir.join(super::PROJECT_CONFIG_FILE_NAME);
+        let mut config: super::Config = toml::from_str("retries = 1\n").unwrap();
+
+        std::fs::write(&path, "retries = \n").unwrap();
+        config.merge_file(&path, super::Config::read);
+        std::fs::write(&path, "retries = 2\ntoken = \"stolen\"\n").unwrap();
+        config.merge_file(&path, super::Config::read_project);
+        config.merge_file(&dir.join("missing.toml"), super::Config::read);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(config.retries, Some(1));
+        assert_eq!(config.token, None);
+    }
+
+    #[test]
+    fn test_config_merge() {
+        let mut config: super::Config =
+            toml::from_str("token = \"global\"\nretries = 1\n[convert]\nno-lock = true\n").unwrap();
+        config.merge(toml::from_str("retries = 2\n[convert]\noffline = true\n").unwrap());
+
+        assert_eq!(config.token.as_deref(), Some("global"));
+        assert_eq!(config.retries, Some(2));
+        assert_eq!(config.subcommands["convert"].len(), 2);
+    }
+}
@@ -33,8 +33,18 @@ pub struct Instrumentation {
     /// Enable debug logs, -vv for trace
     #[clap(short = 'v', env = "FH_VERBOSITY", long, action = clap::ArgAction::Count, global = true)]
     pub verbose: u8,
-    /// Which logger to use
-    #[clap(long, env = "FH_LOGGER", default_value_t = Default::default(), global = true)]
+    /// Only log errors
+    #[clap(
+        short = 'q',
+        env = "FH_QUIET",
+        long,
+        global = true,
+        conflicts_with = "verbose"
+    )]
+    pub quiet: bool,
+    /// Which logger to use; `json` emits one JSON object per event, including the fields of the
+    /// spans it happened in
+    #[clap(long, visible_alias = "log-format", env = "FH_LOGGER", default_value_t = Default::default(), global = true)]
     pub logger: Logger,
     /// Tracing directives
     ///
@@ -45,6 +55,10 @@ pub struct Instrumentation {
 
 impl<'a> Instrumentation {
     pub fn log_level(&self) -> String {
+        if self.quiet {
+            return "error".to_string();
+        }
+
         match self.verbose {
             0 => "info",
             1 => "debug",
@@ -105,9 +119,11 @@ impl<'a> Instrumentation {
         S: tracing::Subscriber + for<'span> tracing_subscriber::registry::LookupSpan<'span>,
     {
         tracing_subscriber::fmt::Layer::new()
-            .with_ansi(std::io::stderr().is_terminal())
+            .with_ansi(false)
             .with_writer(std::io::stderr)
             .json()
+            .with_current_span(true)
+            .with_span_list(true)
     }
 
     pub fn fmt_layer_compact<S>(&self) -> impl tracing_subscriber::layer::Layer<S>
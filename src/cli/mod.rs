@@ -1,4 +1,5 @@
 pub(crate) mod cmd;
+pub(crate) mod config;
 pub(crate) mod instrumentation;
 
 /// fh: a CLI for interacting with FlakeHub
@@ -12,6 +13,7 @@ pub(crate) struct Cli {
         global = true,
         long,
         default_value = "https://api.flakehub.com",
+        value_parser = parse_flakehub_addr,
         hide = true
     )]
     pub api_addr: url::Url,
@@ -23,13 +25,71 @@ pub(crate) struct Cli {
         global = true,
         long,
         default_value = "https://flakehub.com",
+        value_parser = parse_flakehub_addr,
         hide = true
     )]
     pub frontend_addr: url::Url,
 
+    /// The FlakeHub token to authenticate with, e.g. to access private flakes.
+    ///
+    /// If not provided, the token saved by `fh login` is used (if any).
+    #[clap(global = true, long, env = "FH_TOKEN", hide_env_values = true)]
+    pub token: Option<String>,
+
+    /// How many times to retry a FlakeHub request that failed with a connection error or a server
+    /// error.
+    #[clap(global = true, long, default_value_t = cmd::DEFAULT_RETRIES)]
+    pub retries: u32,
+
     #[clap(subcommand)]
     pub subcommand: cmd::FhSubcommands,
 
     #[clap(flatten)]
     pub instrumentation: instrumentation::Instrumentation,
 }
+
+/// Parses a FlakeHub address, rejecting URLs we couldn't send requests to (like `localhost:8080`,
+/// which is a valid URL with the scheme `localhost`) up front instead of failing confusingly later.
+fn parse_flakehub_addr(addr: &str) -> Result<url::Url, String> {
+    let url = url::Url::parse(addr).map_err(|e| format!("`{addr}` is not a valid URL: {e}"))?;
+
+    if !matches!(url.scheme(), "http" | "https") || url.host().is_none() {
+        return Err(format!(
+            "`{addr}` is not an http(s) URL; did you mean `http://{addr}`?"
+        ));
+    }
+
+    Ok(url)
+}
+
+#[cfg(test)]
+mod test {
+    use clap::Parser;
+
+    #[test]
+    fn test_bad_api_addr() {
+        let cli = super::Cli::try_parse_from([
+            "fh",
+            "--api-addr",
+            "http://localhost:8080",
+            "search",
+            "rust",
+        ]);
+        assert!(cli.is_ok());
+
+        for api_addr in ["localhost:8080", "not a url", "file:///tmp"] {
+            let err = match super::Cli::try_parse_from([
+                "fh",
+                "--api-addr",
+                api_addr,
+                "search",
+                "rust",
+            ]) {
+                Ok(_) => panic!("{api_addr} should be rejected"),
+                Err(err) => err,
+            };
+            assert_eq!(err.kind(), clap::error::ErrorKind::ValueValidation);
+            assert!(err.to_string().contains(api_addr), "{err}");
+        }
+    }
+}
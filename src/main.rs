@@ -2,12 +2,7 @@ pub(crate) mod cli;
 
 use std::io::IsTerminal;
 
-use clap::Parser;
-
-use crate::cli::{
-    cmd::{CommandExecute, FhSubcommands},
-    Cli,
-};
+use crate::cli::{cmd::CommandExecute, config::Config};
 
 static APP_USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"),);
 
@@ -25,18 +20,11 @@ async fn main() -> color_eyre::Result<std::process::ExitCode> {
         })
         .install()?;
 
-    let cli = Cli::parse();
+    let cli = Config::load()
+        .parse_cli(std::env::args_os())
+        .unwrap_or_else(|e| e.exit());
     cli.instrumentation.setup().await?;
+    crate::cli::cmd::set_retries(cli.retries);
 
-    match cli.subcommand {
-        FhSubcommands::Add(add) => add.execute().await,
-        FhSubcommands::Init(init) => init.execute().await,
-        FhSubcommands::List(list) => list.execute().await,
-        FhSubcommands::Search(search) => search.execute().await,
-        FhSubcommands::Completion(completion) => completion.execute().await,
-        FhSubcommands::Convert(convert) => convert.execute().await,
-        FhSubcommands::Login(login) => login.execute().await,
-        FhSubcommands::Status(status) => status.execute().await,
-        FhSubcommands::Eject(eject) => eject.execute().await,
-    }
+    cli.subcommand.execute().await
 }